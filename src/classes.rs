@@ -0,0 +1,139 @@
+//! Normalisierung der Klassenbezeichnungen.
+
+use clap::ValueEnum;
+use log::warn;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::error::IsitError;
+use crate::read::*;
+use crate::records::*;
+
+/// Schreibweise, in die Klassenbezeichnungen wie "07 B" oder "7/2" gebracht werden.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ClassStyle {
+    /// Jahrgang und Zug ohne Trenner, nummerierte Züge werden zu Buchstaben (7/2 → 7b).
+    Compact,
+    /// Jahrgang und Zug mit Schrägstrich (7b → 7/b).
+    Slash,
+    /// Klasse unverändert übernehmen.
+    Verbatim,
+    /// Freie Klassennamen wie "Lerngruppe Blau" übernehmen, auch ohne Oberstufen- und
+    /// Halbjahreszusammenfassung; nur die Zuordnungen aus --class-rules werden angewendet.
+    Passthrough,
+}
+
+pub struct ClassNormalizer {
+    pub style: ClassStyle,
+    pub collapse_semesters: bool,
+    pub pattern: Regex,
+    pub semester_pattern: Regex,
+    /// Ausdrückliche Zuordnungen aus der Regeldatei, werden vor allem anderen angewendet.
+    pub rules: HashMap<String, String>,
+    /// Gefundene Halbjahresbezeichnungen und wie oft sie vorkamen.
+    pub semester_variants: BTreeMap<String, usize>,
+}
+
+impl ClassNormalizer {
+    pub fn new(style: ClassStyle, collapse_semesters: bool) -> Self {
+        ClassNormalizer {
+            style,
+            collapse_semesters,
+            // Jahrgang, dann entweder ein Buchstaben-Zug oder ein nummerierter Zug mit Trenner.
+            pattern: Regex::new(r"^(\d+)\s*(?:([[:alpha:]]+)|(?:[/.\-]|\s)\s*(\d+))$").unwrap(),
+            semester_pattern: Regex::new(r"(?i)^(EF|Q[12])\s*\.\s*[12]$").unwrap(),
+            semester_variants: BTreeMap::new(),
+            rules: HashMap::new(),
+        }
+    }
+
+    /// Liest eine Regeldatei (CSV mit Spalten Von;Nach) mit festen Klassenzuordnungen.
+    pub fn load_rules(&mut self, path: &Path, encoding: Encoding) -> Result<(), IsitError> {
+        let options = ReadOptions {
+            encoding,
+            ..ReadOptions::default()
+        };
+        let text = read_decoded(path.to_path_buf(), &options)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(text.as_bytes());
+        for rule in reader.deserialize() {
+            let rule: ClassRule = rule.map_err(IsitError::csv(path))?;
+            self.rules.insert(rule.von, rule.nach);
+        }
+        Ok(())
+    }
+
+    /// Ob Klassen ohne Umweg über die Format-Logik (z.B. Oberstufe "11a" → "11") übernommen werden.
+    pub fn is_passthrough(&self) -> bool {
+        self.style == ClassStyle::Passthrough
+    }
+
+    pub fn normalize(&mut self, klasse: &str) -> String {
+        let klasse = klasse.trim();
+        if let Some(mapped) = self.rules.get(klasse) {
+            return mapped.clone();
+        }
+        if self.is_passthrough() {
+            return klasse.to_owned();
+        }
+        if let Some(captures) = self.semester_pattern.captures(klasse) {
+            *self.semester_variants.entry(klasse.to_owned()).or_default() += 1;
+            if self.collapse_semesters {
+                return captures[1].to_uppercase();
+            }
+        }
+        if self.style == ClassStyle::Verbatim {
+            return klasse.to_owned();
+        }
+        let captures = match self.pattern.captures(klasse) {
+            Some(captures) => captures,
+            None => return klasse.to_owned(),
+        };
+        let jahrgang = &captures[1];
+        match (self.style, captures.get(2), captures.get(3)) {
+            (ClassStyle::Compact, Some(letter), _) => format!("{}{}", jahrgang, letter.as_str()),
+            (ClassStyle::Compact, _, Some(number)) => match number.as_str().parse::<u8>() {
+                Ok(n @ 1..=26) => format!("{}{}", jahrgang, char::from(b'a' + n - 1)),
+                _ => format!("{}/{}", jahrgang, number.as_str()),
+            },
+            (_, letter, number) => {
+                let zug = letter.or(number).map(|m| m.as_str()).unwrap_or_default();
+                format!("{}/{}", jahrgang, zug)
+            }
+        }
+    }
+
+    /// Meldet, welche Halbjahresbezeichnungen in der Eingabe vorkamen.
+    pub fn report(&self) {
+        if self.semester_variants.is_empty() {
+            return;
+        }
+        let variants: Vec<String> = self
+            .semester_variants
+            .iter()
+            .map(|(variant, count)| format!("{} ({}×)", variant, count))
+            .collect();
+        if self.collapse_semesters {
+            warn!(
+                "Halbjahresbezeichnungen zur Stufe zusammengefasst: {}",
+                variants.join(", ")
+            );
+        } else {
+            warn!(
+                "Halbjahresbezeichnungen unverändert übernommen: {}",
+                variants.join(", ")
+            );
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClassRule {
+    #[serde(rename = "Von", deserialize_with = "trimmed")]
+    pub von: String,
+    #[serde(rename = "Nach", deserialize_with = "trimmed")]
+    pub nach: String,
+}
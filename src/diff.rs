@@ -0,0 +1,227 @@
+//! Abgleich mit den Konten, die schon in IServ angelegt sind.
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::error::IsitError;
+use crate::read::*;
+use crate::records::*;
+
+/// Ein Konto aus dem Benutzerexport von IServ (Verwaltung → Benutzer → Export) oder aus einer
+/// früher von isit geschriebenen Importdatei. Nur die Import-ID ist Pflicht, weitere Spalten
+/// werden übergangen.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordExisting {
+    #[serde(
+        rename = "Import-ID",
+        alias = "Importkennung",
+        deserialize_with = "trimmed"
+    )]
+    pub import_id: String,
+    #[serde(
+        rename = "Account",
+        alias = "Benutzername",
+        default,
+        deserialize_with = "trimmed"
+    )]
+    pub account: String,
+    #[serde(rename = "Nachname", default, deserialize_with = "trimmed")]
+    pub nachname: String,
+    #[serde(rename = "Vorname", default, deserialize_with = "trimmed")]
+    pub vorname: String,
+    #[serde(rename = "Klasse", default, deserialize_with = "trimmed")]
+    pub klasse: String,
+}
+
+/// Liest die bestehenden Konten. Konten ohne Import-ID, z.B. von Hand angelegte
+/// Verwaltungskonten, lassen sich keinem Schüler zuordnen und werden übergangen.
+pub fn read_existing(path: &Path) -> Result<Vec<RecordExisting>, IsitError> {
+    let text = read_decoded(PathBuf::from(path), &ReadOptions::default())?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(detect_delimiter(&text))
+        .comment(Some(b'#'))
+        .from_reader(text.as_bytes());
+    let headers = rdr.headers().map_err(IsitError::csv(path))?;
+    if !headers
+        .iter()
+        .any(|h| matches!(h.trim(), "Import-ID" | "Importkennung"))
+    {
+        return Err(IsitError::input(
+            path,
+            "Keine Spalte \"Import-ID\", bitte beim Export in IServ mit auswählen.".to_owned(),
+        ));
+    }
+    let mut existing = Vec::new();
+    let mut without_id = 0;
+    for record in rdr.deserialize() {
+        let record: RecordExisting = record.map_err(IsitError::csv(path))?;
+        if record.import_id.is_empty() {
+            without_id += 1;
+        } else {
+            existing.push(record);
+        }
+    }
+    if without_id > 0 {
+        info!(
+            "{} Konten in {} haben keine Import-ID und werden nicht abgeglichen.",
+            without_id,
+            path.display()
+        );
+    }
+    Ok(existing)
+}
+
+/// Ein Schüler, der seit dem letzten Import die Klasse gewechselt hat; sein Konto bleibt, nur
+/// die Gruppe in IServ muss angepasst werden.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordClassChange {
+    #[serde(rename = "Import-ID")]
+    pub import_id: String,
+    #[serde(rename = "Nachname")]
+    pub nachname: String,
+    #[serde(rename = "Vorname")]
+    pub vorname: String,
+    #[serde(rename = "Alte Klasse")]
+    pub alte_klasse: String,
+    #[serde(rename = "Neue Klasse")]
+    pub neue_klasse: String,
+}
+
+/// Ergebnis des Abgleichs.
+#[derive(Debug, Default)]
+pub struct Diff {
+    /// Schüler, deren Import-ID in IServ noch fehlt und die ein Konto brauchen.
+    pub new: Vec<RecordIserv>,
+    /// Anzahl der Schüler, die schon ein Konto haben.
+    pub known: usize,
+    /// Konten, deren Import-ID im neuen Export fehlt, z.B. Abgänger zum Schuljahresende.
+    pub leavers: Vec<RecordExisting>,
+    /// Schüler mit Konto, deren Klasse sich geändert hat. Konten ohne Klasse im Export werden
+    /// nicht verglichen.
+    pub class_changes: Vec<RecordClassChange>,
+}
+
+/// Gleicht die Datensätze über die Import-ID mit den bestehenden Konten ab. Schüler ohne
+/// Einwilligung (`excluded`) sind noch an der Schule und zählen nicht als Abgänger.
+pub fn diff(
+    records: Vec<RecordIserv>,
+    excluded: &[RecordExcluded],
+    existing: &[RecordExisting],
+) -> Diff {
+    let by_id: HashMap<&str, &RecordExisting> =
+        existing.iter().map(|e| (e.import_id.as_str(), e)).collect();
+    let present: HashSet<&str> = records
+        .iter()
+        .map(|r| r.import_id.as_str())
+        .chain(excluded.iter().map(|r| r.import_id.as_str()))
+        .collect();
+    let leavers = existing
+        .iter()
+        .filter(|e| !present.contains(e.import_id.as_str()))
+        .cloned()
+        .collect();
+    let mut diff = Diff {
+        leavers,
+        ..Diff::default()
+    };
+    for record in records {
+        if let Some(account) = by_id.get(record.import_id.as_str()) {
+            diff.known += 1;
+            if !account.klasse.is_empty() && account.klasse != record.klasse {
+                diff.class_changes.push(RecordClassChange {
+                    import_id: record.import_id,
+                    nachname: record.nachname,
+                    vorname: record.vorname,
+                    alte_klasse: account.klasse.clone(),
+                    neue_klasse: record.klasse,
+                });
+            }
+        } else {
+            diff.new.push(record);
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn student(import_id: &str, klasse: &str) -> RecordIserv {
+        RecordIserv::new(
+            "Meier".to_owned(),
+            "Tom".to_owned(),
+            klasse.to_owned(),
+            import_id.to_owned(),
+        )
+    }
+
+    fn account(import_id: &str, klasse: &str) -> RecordExisting {
+        RecordExisting {
+            import_id: import_id.to_owned(),
+            account: "tom.meier".to_owned(),
+            nachname: "Meier".to_owned(),
+            vorname: "Tom".to_owned(),
+            klasse: klasse.to_owned(),
+        }
+    }
+
+    fn ids<'a>(ids: impl Iterator<Item = &'a String>) -> Vec<&'a str> {
+        ids.map(String::as_str).collect()
+    }
+
+    #[test]
+    fn only_students_without_account_are_new() {
+        let records = vec![student("1", "5a"), student("2", "5a"), student("3", "6b")];
+        let existing = [account("1", "5a"), account("2", "")];
+        let diff = diff(records, &[], &existing);
+        assert_eq!(diff.known, 2);
+        assert_eq!(ids(diff.new.iter().map(|r| &r.import_id)), ["3"]);
+    }
+
+    #[test]
+    fn accounts_missing_from_the_export_are_leavers() {
+        let records = vec![student("1", "5a")];
+        let excluded = [RecordExcluded::from(student("2", "5a"))];
+        let existing = [account("1", "5a"), account("2", "5a"), account("9", "10a")];
+        let diff = diff(records, &excluded, &existing);
+        assert_eq!(ids(diff.leavers.iter().map(|l| &l.import_id)), ["9"]);
+    }
+
+    #[test]
+    fn class_changes_are_listed_with_old_and_new_class() {
+        let records = vec![student("1", "6a"), student("2", "5b"), student("3", "7c")];
+        let existing = [account("1", "5a"), account("2", "5b"), account("3", "")];
+        let diff = diff(records, &[], &existing);
+        assert_eq!(diff.class_changes.len(), 1);
+        let change = &diff.class_changes[0];
+        assert_eq!(change.import_id, "1");
+        assert_eq!(
+            (change.alte_klasse.as_str(), change.neue_klasse.as_str()),
+            ("5a", "6a")
+        );
+    }
+
+    #[test]
+    fn iserv_export_is_read_by_header() {
+        let path =
+            std::env::temp_dir().join(format!("isit-test-{}-export.csv", std::process::id()));
+        fs::write(
+            &path,
+            "Account,Vorname,Nachname,Importkennung,Gruppen\n\
+             tom.meier,Tom,Meier, 4711 ,5a\n\
+             admin,Admin,,,\n",
+        )
+        .unwrap();
+        let existing = read_existing(&path);
+        let _ = fs::remove_file(&path);
+        let existing = existing.unwrap();
+        assert_eq!(existing.len(), 1);
+        assert_eq!(existing[0].import_id, "4711");
+        assert_eq!(existing[0].account, "tom.meier");
+        assert_eq!(existing[0].klasse, "");
+    }
+}
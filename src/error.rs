@@ -0,0 +1,141 @@
+//! Fehler beim Lesen, Umwandeln und Schreiben, jeweils mit Datei und Zeile.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum IsitError {
+    /// Eine Datei lässt sich nicht öffnen, lesen oder schreiben.
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    /// Die CSV-Datei ist fehlerhaft, z.B. eine Zeile mit falscher Spaltenzahl.
+    #[error("{}{}: {source}", path.display(), at_line(*line))]
+    Csv {
+        path: PathBuf,
+        line: Option<usize>,
+        source: csv::Error,
+    },
+    /// Die Excel-Datei oder eine ihrer Zeilen lässt sich nicht lesen.
+    #[error("{}{}: {source}", path.display(), at_line(*line))]
+    Excel {
+        path: PathBuf,
+        line: Option<usize>,
+        source: calamine::Error,
+    },
+    /// Das ZIP-Archiv ist beschädigt oder enthält die gesuchte Datei nicht.
+    #[error("{}: {source}", path.display())]
+    Archive {
+        path: PathBuf,
+        source: zip::result::ZipError,
+    },
+    /// Der Inhalt lässt sich im gewählten Encoding nicht dekodieren.
+    #[error("{}: nicht als {encoding} lesbar: {source}", path.display())]
+    Encoding {
+        path: PathBuf,
+        encoding: &'static str,
+        source: io::Error,
+    },
+    /// Eine Formatdefinition, Richtlinie oder Regeldatei ist ungültig.
+    #[error("{}: {message}", path.display())]
+    Config { path: PathBuf, message: String },
+    /// Die Eingabe wird abgelehnt, z.B. weil sie zu groß ist oder Spalten fehlen.
+    #[error("{}{}: {message}", path.display(), at_line(*line))]
+    Input {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+    /// Die Datensätze bestehen eine Prüfung nicht, es wird nichts geschrieben.
+    #[error("{message}")]
+    Conversion { message: String },
+    /// Die Optionen passen nicht zusammen.
+    #[error("{0}")]
+    Usage(String),
+}
+
+fn at_line(line: Option<usize>) -> String {
+    line.map(|line| format!(", Zeile {}", line))
+        .unwrap_or_default()
+}
+
+impl IsitError {
+    pub fn io(path: &Path) -> impl FnOnce(io::Error) -> IsitError + '_ {
+        move |source| IsitError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn csv(path: &Path) -> impl FnOnce(csv::Error) -> IsitError + '_ {
+        move |source| IsitError::Csv {
+            path: path.to_path_buf(),
+            line: source.position().map(|position| position.line() as usize),
+            source,
+        }
+    }
+
+    pub fn excel<E: Into<calamine::Error>>(path: &Path) -> impl FnOnce(E) -> IsitError + '_ {
+        move |source| IsitError::Excel {
+            path: path.to_path_buf(),
+            line: None,
+            source: source.into(),
+        }
+    }
+
+    pub fn archive(path: &Path) -> impl FnOnce(zip::result::ZipError) -> IsitError + '_ {
+        move |source| IsitError::Archive {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn config<E: ToString>(path: &Path) -> impl FnOnce(E) -> IsitError + '_ {
+        move |e| IsitError::Config {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        }
+    }
+
+    pub fn input(path: &Path, message: String) -> IsitError {
+        IsitError::Input {
+            path: path.to_path_buf(),
+            line: None,
+            message,
+        }
+    }
+
+    pub fn conversion(message: String) -> IsitError {
+        IsitError::Conversion { message }
+    }
+
+    /// Die Meldung ohne Datei und Zeile, z.B. für die Datei der abgelehnten Zeilen.
+    pub fn reason(&self) -> String {
+        match self {
+            IsitError::Io { source, .. } | IsitError::Encoding { source, .. } => source.to_string(),
+            IsitError::Csv { source, .. } => source.to_string(),
+            IsitError::Excel { source, .. } => source.to_string(),
+            IsitError::Archive { source, .. } => source.to_string(),
+            IsitError::Config { message, .. }
+            | IsitError::Input { message, .. }
+            | IsitError::Conversion { message }
+            | IsitError::Usage(message) => message.clone(),
+        }
+    }
+
+    /// Exit-Code des Programms, damit Skripte die Fehlerart unterscheiden können:
+    /// 2 Aufruf oder Konfiguration, 3 Dateizugriff, 4 unlesbare Eingabe, 5 abgelehnte
+    /// Eingabe, 6 Prüfung nicht bestanden.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            IsitError::Usage(_) | IsitError::Config { .. } => 2,
+            IsitError::Io { .. } => 3,
+            IsitError::Csv { .. }
+            | IsitError::Excel { .. }
+            | IsitError::Archive { .. }
+            | IsitError::Encoding { .. } => 4,
+            IsitError::Input { .. } => 5,
+            IsitError::Conversion { .. } => 6,
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Eingabeformate aus formats.d/ und die eingebauten Formate.
+
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::IsitError;
+
+/// Eingabeformat aus einer TOML-Datei in formats.d/. Damit lassen sich weitere Exporte
+/// unterstützen, ohne einen eigenen Datensatztyp zu schreiben.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatDefinition {
+    /// Name des Formats, ergibt sich aus dem Dateinamen.
+    #[serde(skip)]
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub example: String,
+    pub columns: ColumnAliases,
+    #[serde(default)]
+    pub transforms: FieldTransforms,
+}
+
+/// Mögliche Spaltennamen je Feld, verglichen ohne Beachtung der Groß- und Kleinschreibung.
+/// Statt einer Liste darf auch ein einzelner Name stehen.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnAliases {
+    #[serde(deserialize_with = "one_or_many")]
+    pub nachname: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub vorname: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub klasse: Vec<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub import_id: Vec<String>,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    })
+}
+
+/// Umformungen je Feld, die der Reihe nach auf den Spaltenwert angewendet werden, z.B.:
+///
+/// ```toml
+/// [transforms]
+/// nachname = [{ part = { separator = ", ", index = 0 } }]
+/// vorname = [{ part = { separator = ", ", index = 1 } }, "capitalize"]
+/// klasse = ["uppercase", { replace = { from = " ", to = "" } }]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FieldTransforms {
+    pub nachname: Vec<Transform>,
+    pub vorname: Vec<Transform>,
+    pub klasse: Vec<Transform>,
+    pub import_id: Vec<Transform>,
+}
+
+impl FieldTransforms {
+    pub fn fields(&self) -> [&[Transform]; 4] {
+        [&self.nachname, &self.vorname, &self.klasse, &self.import_id]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum Transform {
+    Lowercase,
+    Uppercase,
+    /// Erster Buchstabe jedes Wortes groß, der Rest klein ("MÜLLER-LÜDENSCHEIDT" →
+    /// "Müller-Lüdenscheidt").
+    Capitalize,
+    Replace {
+        from: String,
+        to: String,
+    },
+    /// Ein Teil des Werts, z.B. der Nachname aus "Müller, Anna"; fehlt er, bleibt das Feld leer.
+    Part {
+        separator: String,
+        index: usize,
+    },
+}
+
+impl Transform {
+    pub fn apply(&self, value: &str) -> String {
+        match self {
+            Transform::Lowercase => value.to_lowercase(),
+            Transform::Uppercase => value.to_uppercase(),
+            Transform::Capitalize => {
+                let mut capitalized = String::with_capacity(value.len());
+                let mut word_start = true;
+                for c in value.chars() {
+                    if word_start {
+                        capitalized.extend(c.to_uppercase());
+                    } else {
+                        capitalized.extend(c.to_lowercase());
+                    }
+                    word_start = !c.is_alphanumeric();
+                }
+                capitalized
+            }
+            Transform::Replace { from, to } => value.replace(from.as_str(), to),
+            Transform::Part { separator, index } => value
+                .split(separator.as_str())
+                .nth(*index)
+                .map(|part| part.trim().to_owned())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl ColumnAliases {
+    pub fn fields(&self) -> [(&'static str, &[String]); 4] {
+        [
+            ("nachname", &self.nachname),
+            ("vorname", &self.vorname),
+            ("klasse", &self.klasse),
+            ("import_id", &self.import_id),
+        ]
+    }
+}
+
+/// Formate aus formats.d/, die mit dem Feature `portable` ins Programm eingebaut werden, damit
+/// es ohne weitere Dateien läuft.
+#[cfg(feature = "portable")]
+pub const EMBEDDED_FORMATS: &[(&str, &str)] =
+    &[("einfach", include_str!("../formats.d/einfach.toml"))];
+
+#[cfg(not(feature = "portable"))]
+pub const EMBEDDED_FORMATS: &[(&str, &str)] = &[];
+
+/// Liest alle Formate aus dem Verzeichnis; fehlt es, gibt es nur die eingebauten. Eine Datei im
+/// Verzeichnis ersetzt ein eingebautes Format gleichen Namens.
+pub fn load_formats(dir: &Path) -> Result<Vec<FormatDefinition>, IsitError> {
+    let mut formats = Vec::new();
+    if dir.is_dir() {
+        let pattern = dir.join("*.toml");
+        let paths = glob::glob(&pattern.to_string_lossy()).map_err(IsitError::config(dir))?;
+        for path in paths {
+            let path = path.map_err(|e| IsitError::Io {
+                path: e.path().to_path_buf(),
+                source: e.into_error(),
+            })?;
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let definition = fs::read_to_string(&path).map_err(IsitError::io(&path))?;
+            formats.push(parse_format(name, &path, &definition)?);
+        }
+    }
+    for (name, definition) in EMBEDDED_FORMATS {
+        if !formats.iter().any(|format| format.name == *name) {
+            let path = PathBuf::from(format!("{} (eingebaut)", name));
+            formats.push(parse_format(name.to_string(), &path, definition)?);
+        }
+    }
+    formats.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(formats)
+}
+
+/// Liest eine Formatdefinition; `path` dient nur den Fehlermeldungen.
+pub fn parse_format(
+    name: String,
+    path: &Path,
+    definition: &str,
+) -> Result<FormatDefinition, IsitError> {
+    let mut format: FormatDefinition =
+        toml::from_str(definition).map_err(IsitError::config(path))?;
+    format.name = name;
+    Ok(format)
+}
+
+/// Liest die Spaltenzuordnung für `--record-type custom` aus einer Datei im Format von formats.d/.
+pub fn load_mapping(path: &Path) -> Result<FormatDefinition, IsitError> {
+    let definition = fs::read_to_string(path).map_err(IsitError::io(path))?;
+    parse_format("custom".to_owned(), path, &definition)
+}
+
+pub fn find_format(dir: &Path, name: &str) -> Result<FormatDefinition, IsitError> {
+    load_formats(dir)?
+        .into_iter()
+        .find(|format| format.name == name)
+        .ok_or_else(|| {
+            IsitError::Usage(format!(
+                "Unbekanntes Format \"{}\", siehe isit list-formats --formats-dir {}.",
+                name,
+                dir.display()
+            ))
+        })
+}
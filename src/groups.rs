@@ -0,0 +1,82 @@
+//! Klassenlehrerzuordnung und daraus erzeugte Klassengruppen.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::classes::*;
+use crate::error::IsitError;
+use crate::read::*;
+use crate::records::*;
+
+#[derive(Debug, Deserialize)]
+pub struct RecordTutor {
+    #[serde(rename = "Klasse", deserialize_with = "trimmed")]
+    pub klasse: String,
+    #[serde(rename = "Lehrer", deserialize_with = "trimmed")]
+    pub lehrer: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordGroup {
+    pub name: String,
+    pub besitzer: String,
+}
+
+/// Liest die Klassenlehrerzuordnung; die Klassen werden wie in der Hauptdatei normalisiert.
+pub fn read_tutors(
+    path: &Path,
+    encoding: Encoding,
+    mut normalizer: ClassNormalizer,
+) -> Result<BTreeMap<String, String>, IsitError> {
+    let options = ReadOptions {
+        encoding,
+        ..ReadOptions::default()
+    };
+    let text = read_decoded(path.to_path_buf(), &options)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .from_reader(text.as_bytes());
+    let mut tutors = BTreeMap::new();
+    for record in reader.deserialize() {
+        let record: RecordTutor = record.map_err(IsitError::csv(path))?;
+        let klasse = normalizer.normalize(&record.klasse);
+        if let Some(previous) = tutors.insert(klasse.clone(), record.lehrer) {
+            warn!(
+                "Klasse {} ist mehrfach zugeordnet, \"{}\" wird ersetzt.",
+                klasse, previous
+            );
+        }
+    }
+    Ok(tutors)
+}
+
+/// Eine Gruppe je Klasse der Ausgabe mit dem Klassenlehrer als Besitzer.
+pub fn class_groups(
+    records: &[RecordIserv],
+    mut tutors: BTreeMap<String, String>,
+) -> Vec<RecordGroup> {
+    let classes: BTreeSet<&str> = records.iter().map(|r| r.klasse.as_str()).collect();
+    let groups = classes
+        .into_iter()
+        .map(|klasse| {
+            let besitzer = tutors.remove(klasse).unwrap_or_else(|| {
+                warn!("Für Klasse {} ist kein Klassenlehrer hinterlegt.", klasse);
+                String::new()
+            });
+            RecordGroup {
+                name: klasse.to_owned(),
+                besitzer,
+            }
+        })
+        .collect();
+    for klasse in tutors.keys() {
+        info!(
+            "Klasse {} aus der Klassenlehrerliste kommt in der Eingabe nicht vor.",
+            klasse
+        );
+    }
+    groups
+}
@@ -0,0 +1,416 @@
+//! Passwortbriefe als PDF: eine Seite je Schüler mit Schulkopf, Benutzername und Passwort.
+
+use encoding_rs::WINDOWS_1252;
+use qrcode::{Color, EcLevel, QrCode, Version};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::error::IsitError;
+
+/// Vorlage für die Briefe, als TOML-Datei:
+///
+/// ```toml
+/// kopf = ["Gymnasium Musterstadt", "Schulstraße 1, 12345 Musterstadt"]
+/// betreff = "Deine Zugangsdaten für IServ"
+/// text = """
+/// Hallo {vorname},
+///
+/// dein Benutzername ist {benutzername}, dein Passwort {passwort}.
+/// """
+/// ```
+///
+/// Im Text werden {vorname}, {nachname}, {klasse}, {benutzername}, {passwort} und
+/// {export_date} (Datum des Exports, JJJJ-MM-TT) ersetzt. Mit
+/// `qr = "{benutzername}\n{passwort}"` oder einer Anmelde-URL bekommt jeder Brief oben rechts
+/// einen QR-Code mit diesem Inhalt, die Platzhalter sind dieselben.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LetterTemplate {
+    pub kopf: Vec<String>,
+    pub betreff: String,
+    pub text: String,
+    pub qr: Option<String>,
+}
+
+const DEFAULT_TEXT: &str = "Hallo {vorname} {nachname} ({klasse}),
+
+hier sind deine Zugangsdaten für IServ.
+
+Benutzername: {benutzername}
+Passwort: {passwort}
+
+Bitte ändere das Passwort nach der ersten Anmeldung und gib es niemandem weiter.";
+
+impl Default for LetterTemplate {
+    fn default() -> Self {
+        LetterTemplate {
+            kopf: Vec::new(),
+            betreff: "Zugangsdaten für IServ".to_owned(),
+            text: DEFAULT_TEXT.to_owned(),
+            qr: None,
+        }
+    }
+}
+
+/// Inhalt eines Briefs.
+pub struct Letter {
+    pub vorname: String,
+    pub nachname: String,
+    pub klasse: String,
+    pub benutzername: String,
+    pub passwort: String,
+    pub export_date: String,
+}
+
+impl LetterTemplate {
+    pub fn load(path: &Path) -> Result<Self, IsitError> {
+        let text = fs::read_to_string(path).map_err(IsitError::io(path))?;
+        toml::from_str(&text).map_err(IsitError::config(path))
+    }
+
+    fn fill(text: &str, letter: &Letter) -> String {
+        [
+            ("{vorname}", &letter.vorname),
+            ("{nachname}", &letter.nachname),
+            ("{klasse}", &letter.klasse),
+            ("{benutzername}", &letter.benutzername),
+            ("{passwort}", &letter.passwort),
+            ("{export_date}", &letter.export_date),
+        ]
+        .iter()
+        .fold(text.to_owned(), |text, (placeholder, value)| {
+            text.replace(placeholder, value)
+        })
+    }
+
+    /// Inhaltsstrom einer Seite.
+    fn page(&self, letter: &Letter) -> Result<Vec<u8>, IsitError> {
+        let mut content = Vec::new();
+        let mut y = PAGE_HEIGHT - MARGIN;
+        for line in &self.kopf {
+            y -= 16.0;
+            text_line(&mut content, "F2", 14.0, y, line);
+        }
+        if !self.kopf.is_empty() {
+            y -= 10.0;
+            content.extend_from_slice(
+                format!(
+                    "0.5 w {} {:.1} m {} {:.1} l S\n",
+                    MARGIN,
+                    y,
+                    PAGE_WIDTH - MARGIN,
+                    y
+                )
+                .as_bytes(),
+            );
+            y -= 24.0;
+        }
+        let mut line_chars = LINE_CHARS;
+        if let Some(qr) = &self.qr {
+            let data = LetterTemplate::fill(qr, letter);
+            let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M)
+                .ok()
+                .filter(|code| matches!(code.version(), Version::Normal(v) if v <= MAX_QR_VERSION))
+                .ok_or_else(|| {
+                    IsitError::conversion(format!(
+                        "QR-Inhalt für {} ist mit {} Bytes zu lang, möglich sind 213.",
+                        letter.benutzername,
+                        data.len()
+                    ))
+                })?;
+            qr_code(
+                &mut content,
+                &code,
+                PAGE_WIDTH - MARGIN - QR_SIZE,
+                y - QR_SIZE,
+            );
+            // Der Text läuft links am Code vorbei.
+            line_chars -= 25;
+        }
+        y -= 14.0;
+        text_line(&mut content, "F2", 12.0, y, &self.betreff);
+        y -= 10.0;
+        for paragraph in LetterTemplate::fill(&self.text, letter).lines() {
+            for line in wrap(paragraph, line_chars) {
+                y -= 18.0;
+                text_line(&mut content, "F1", 12.0, y, &line);
+            }
+        }
+        Ok(content)
+    }
+
+    /// Setzt alle Briefe zu einem PDF zusammen.
+    pub fn render(&self, letters: &[Letter]) -> Result<Vec<u8>, IsitError> {
+        let pages = letters
+            .iter()
+            .map(|letter| self.page(letter))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(pdf(&pages))
+    }
+}
+
+// A4 in Punkt.
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 72.0;
+// Grob, was bei 12 Punkt Helvetica zwischen die Ränder passt.
+const LINE_CHARS: usize = 80;
+// Kantenlänge des QR-Codes samt Ruhezone, gut 4 cm.
+const QR_SIZE: f32 = 120.0;
+// Größte QR-Version, deren 57 Module auf QR_SIZE noch sicher lesbar gedruckt werden; mit
+// Fehlerkorrekturstufe M passen bis zu 213 Bytes.
+const MAX_QR_VERSION: i16 = 10;
+
+/// Bricht an Leerzeichen um, sodass keine Zeile länger als `width` Zeichen wird, außer ein
+/// einzelnes Wort ist schon länger.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for word in text.split(' ') {
+        let line = lines.last_mut().expect("mindestens eine Zeile");
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(word.to_owned());
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+    }
+    lines
+}
+
+/// Text in Windows-1252 für die Standardschriften, mit maskierten Klammern. Zeichen außerhalb
+/// von Windows-1252 werden zu "?".
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(text.len() + 2);
+    encoded.push(b'(');
+    for c in text.chars() {
+        let mut buffer = [0u8; 4];
+        let (bytes, _, unmappable) = WINDOWS_1252.encode(c.encode_utf8(&mut buffer));
+        let byte = if unmappable { b'?' } else { bytes[0] };
+        if matches!(byte, b'(' | b')' | b'\\') {
+            encoded.push(b'\\');
+        }
+        encoded.push(byte);
+    }
+    encoded.push(b')');
+    encoded
+}
+
+fn text_line(content: &mut Vec<u8>, font: &str, size: f32, y: f32, text: &str) {
+    content
+        .extend_from_slice(format!("BT /{} {} Tf {} {:.1} Td ", font, size, MARGIN, y).as_bytes());
+    content.extend_from_slice(&pdf_string(text));
+    content.extend_from_slice(b" Tj ET\n");
+}
+
+/// Zeichnet den Code mit vier Modulen Ruhezone als schwarze Rechtecke, je Zeile zusammenhängende
+/// dunkle Module in einem Rechteck. (`x`, `y`) ist die linke untere Ecke.
+fn qr_code(content: &mut Vec<u8>, code: &QrCode, x: f32, y: f32) {
+    let size = code.width();
+    let module = QR_SIZE / (size + 8) as f32;
+    let dark: Vec<bool> = code
+        .to_colors()
+        .into_iter()
+        .map(|color| color == Color::Dark)
+        .collect();
+    content.extend_from_slice(b"0 g\n");
+    for (row, modules) in dark.chunks(size).enumerate() {
+        let top = y + QR_SIZE - (row + 4) as f32 * module;
+        let mut column = 0;
+        while column < size {
+            if !modules[column] {
+                column += 1;
+                continue;
+            }
+            let start = column;
+            while column < size && modules[column] {
+                column += 1;
+            }
+            content.extend_from_slice(
+                format!(
+                    "{:.2} {:.2} {:.2} {:.2} re\n",
+                    x + (start + 4) as f32 * module,
+                    top - module,
+                    (column - start) as f32 * module,
+                    module
+                )
+                .as_bytes(),
+            );
+        }
+    }
+    content.extend_from_slice(b"f\n");
+}
+
+/// Ein PDF mit einer Seite je Inhaltsstrom, in Helvetica (F1) und Helvetica-Bold (F2).
+fn pdf(pages: &[Vec<u8>]) -> Vec<u8> {
+    // Objekte 1 bis 4 sind Katalog, Seitenbaum und die beiden Schriften, danach folgen je
+    // Seite das Seitenobjekt und sein Inhalt.
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    let kids: Vec<String> = (0..pages.len())
+        .map(|page| format!("{} 0 R", 5 + 2 * page))
+        .collect();
+    objects.push(
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+    );
+    for font in ["Helvetica", "Helvetica-Bold"] {
+        objects.push(
+            format!(
+                "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                font
+            )
+            .into_bytes(),
+        );
+    }
+    for (page, content) in pages.iter().enumerate() {
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                6 + 2 * page
+            )
+            .into_bytes(),
+        );
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend_from_slice(content);
+        stream.extend_from_slice(b"\nendstream");
+        objects.push(stream);
+    }
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::new();
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", index + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn letter() -> Letter {
+        Letter {
+            vorname: "Jörg".to_owned(),
+            nachname: "Weiß".to_owned(),
+            klasse: "5a".to_owned(),
+            benutzername: "joerg.weiss".to_owned(),
+            passwort: "x7Kq".to_owned(),
+            export_date: "2024-08-01".to_owned(),
+        }
+    }
+
+    fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+        haystack[from..]
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .map(|position| from + position)
+    }
+
+    /// Die Zahl nach `key` in `pdf` ab `from`, bis zum nächsten Leerzeichen oder Zeilenende.
+    fn number_after(pdf: &[u8], key: &[u8], from: usize) -> (usize, usize) {
+        let start = find(pdf, key, from).unwrap() + key.len();
+        let end = start
+            + pdf[start..]
+                .iter()
+                .position(|&b| b == b' ' || b == b'\n')
+                .unwrap();
+        let number = std::str::from_utf8(&pdf[start..end]).unwrap();
+        (number.parse().unwrap(), end)
+    }
+
+    #[test]
+    fn placeholders_include_the_export_date() {
+        let text = "{vorname} {nachname}, {klasse}: {benutzername}/{passwort}, Stand {export_date}";
+        assert_eq!(
+            LetterTemplate::fill(text, &letter()),
+            "Jörg Weiß, 5a: joerg.weiss/x7Kq, Stand 2024-08-01"
+        );
+    }
+
+    #[test]
+    fn pdf_strings_are_escaped_in_windows_1252() {
+        assert_eq!(pdf_string("a(b)c\\d"), b"(a\\(b\\)c\\\\d)");
+        assert_eq!(pdf_string("Jörg Weiß"), b"(J\xf6rg Wei\xdf)");
+        assert_eq!(pdf_string("ÄÖÜäü€"), b"(\xc4\xd6\xdc\xe4\xfc\x80)");
+        assert_eq!(pdf_string("Şule"), b"(?ule)");
+    }
+
+    #[test]
+    fn qr_content_is_limited_to_version_10() {
+        let page = |length| {
+            LetterTemplate {
+                qr: Some("{passwort}".to_owned()),
+                ..LetterTemplate::default()
+            }
+            .page(&Letter {
+                passwort: "x".repeat(length),
+                ..letter()
+            })
+        };
+        assert!(page(213).is_ok());
+        assert!(page(214).is_err());
+    }
+
+    #[test]
+    fn xref_offsets_and_stream_lengths_match_the_bytes() {
+        let template = LetterTemplate {
+            qr: Some("{benutzername}\n{passwort}".to_owned()),
+            ..LetterTemplate::default()
+        };
+        let pdf = template.render(&[letter(), letter()]).unwrap();
+
+        let (xref, _) = number_after(&pdf, b"startxref\n", 0);
+        assert!(pdf[xref..].starts_with(b"xref\n0 "));
+        let (count, _) = number_after(&pdf, b"xref\n0 ", xref);
+        // Katalog, Seitenbaum, zwei Schriften, je Seite Seitenobjekt und Inhalt.
+        assert_eq!(count, 1 + 4 + 2 * 2);
+        let entries = find(&pdf, b"65535 f \n", xref).unwrap() + 9;
+        for object in 1..count {
+            let entry = &pdf[entries + (object - 1) * 20..entries + object * 20];
+            let offset: usize = std::str::from_utf8(&entry[..10]).unwrap().parse().unwrap();
+            assert!(
+                pdf[offset..].starts_with(format!("{} 0 obj\n", object).as_bytes()),
+                "Objekt {}",
+                object
+            );
+        }
+
+        let mut streams = 0;
+        let mut from = 0;
+        while let Some(position) = find(&pdf, b"<< /Length ", from) {
+            let (length, end) = number_after(&pdf, b"<< /Length ", position);
+            let start = end + b" >>\nstream\n".len();
+            assert!(pdf[start + length..].starts_with(b"\nendstream"));
+            streams += 1;
+            from = start + length;
+        }
+        assert_eq!(streams, 2);
+    }
+}
@@ -0,0 +1,23 @@
+//! Umwandlung von Schülerlisten aus der Schulverwaltung in IServ-Importdateien.
+//!
+//! Die Datensätze liegen in [`records`], das Einlesen in [`read`] und das Schreiben in
+//! [`write`](mod@write). Das Programm `isit` ist nur die Kommandozeile darum.
+
+// Idee:
+// Für alle möglichen csv-Formate gibt es passende structs.
+// Per CLI-Argumente teilt man dem Programm mit, welche es verarbeiten soll.
+// Es werden Passwörter generiert werden.
+// Am Ende wird eine csv-Datei erstellt, die für IServ gedacht ist.
+
+pub mod classes;
+pub mod diff;
+pub mod error;
+pub mod formats;
+pub mod groups;
+pub mod letters;
+pub mod password;
+pub mod read;
+pub mod records;
+pub mod transliterate;
+pub mod validate;
+pub mod write;
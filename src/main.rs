@@ -1,262 +1,2551 @@
-use calamine::{open_workbook, Error, RangeDeserializerBuilder, Reader, Xlsx};
-use chbs::config::BasicConfig;
-use chbs::probability::Probability;
-use chbs::scheme::ToScheme;
-use chbs::word::WordList;
-use clap::{Parser, ValueEnum};
-use encoding_rs::{UTF_8, WINDOWS_1252};
-use encoding_rs_io::DecodeReaderBytesBuilder;
-use serde::{Deserialize, Serialize};
-use std::error::Error as OtherError;
-use std::fs::File;
-use std::path::PathBuf;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use encoding_rs::WINDOWS_1252;
+use log::{error, info, warn, Log};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{self, Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
-use log::{error, info};
+use isit::classes::*;
+use isit::diff::*;
+use isit::error::IsitError;
+use isit::formats::*;
+use isit::groups::*;
+use isit::letters::*;
+use isit::password::*;
+use isit::read::*;
+use isit::records::*;
+use isit::transliterate::*;
+use isit::validate::*;
+use isit::write::*;
 
-// Idee:
-// Für alle möglichen csv-Formate gibt es passende structs.
-// Per CLI-Argumente teilt man dem Programm mit, welche es verarbeiten soll.
-// Es werden Passwörter generiert werden.
-// Am Ende wird eine csv-Datei erstellt, die für IServ gedacht ist.
+const SELFTEST_SCHILD: &str = include_str!("../res/selftest/schild.csv");
 
-pub const WORDLIST: &str = include_str!("../res/words.txt");
+const SELFTEST_SCHILD_EXPECTED: &str = include_str!("../res/selftest/schild_expected.csv");
 
-#[derive(Debug, Deserialize)]
-enum Record {
-    RecordSchild(RecordSchild),
-    RecordGastschueler(RecordGastschueler),
+const SELFTEST_GASTSCHUELER: &str = include_str!("../res/selftest/gastschueler.csv");
+
+const SELFTEST_GASTSCHUELER_EXPECTED: &str =
+    include_str!("../res/selftest/gastschueler_expected.csv");
+
+const SELFTEST_LOGINEO: &str = include_str!("../res/selftest/logineo.csv");
+
+const SELFTEST_LOGINEO_EXPECTED: &str = include_str!("../res/selftest/logineo_expected.csv");
+
+fn list_formats(formats_dir: &Path) {
+    for record_type in RecordType::value_variants() {
+        let info = record_type.format_info();
+        println!(
+            "{} – {}",
+            record_type.to_possible_value().unwrap().get_name(),
+            info.description
+        );
+        let columns: Vec<String> = info
+            .required_columns
+            .iter()
+            .map(|c| format!("\"{}\"", c))
+            .collect();
+        if columns.is_empty() {
+            println!("  Pflichtspalten:  wie in der Zuordnungsdatei angegeben");
+        } else {
+            println!("  Pflichtspalten:  {}", columns.join(", "));
+        }
+        println!("  Zusatzspalten:   beliebig, z.B. für --consent-column");
+        println!("  Klassen:         {}", info.class_normalization);
+        println!("  Beispiel:        {}", info.example);
+        println!();
+    }
+    let formats = match load_formats(formats_dir) {
+        Ok(formats) => formats,
+        Err(e) => {
+            error!("Formate aus {} nicht lesbar: {}", formats_dir.display(), e);
+            return;
+        }
+    };
+    for format in formats {
+        println!("{} – {} (--format)", format.name, format.description);
+        let columns: Vec<String> = format
+            .columns
+            .fields()
+            .iter()
+            .map(|(_, aliases)| match aliases.split_first() {
+                Some((first, [])) => format!("\"{}\"", first),
+                Some((first, rest)) => format!("\"{}\" (oder {})", first, rest.join(", ")),
+                None => String::new(),
+            })
+            .collect();
+        println!("  Pflichtspalten:  {}", columns.join(", "));
+        println!("  Zusatzspalten:   beliebig, z.B. für --consent-column");
+        println!("  Klassen:         keine, die Klasse wird übernommen");
+        if !format.example.is_empty() {
+            println!("  Beispiel:        {}", format.example);
+        }
+        println!();
+    }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum RecordType {
-    Schild,
-    Gastschueler,
+// Ohne Unterbefehl wird wie mit `isit convert` umgewandelt, so funktionieren bestehende Aufrufe
+// weiter.
+#[derive(Debug, Parser)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true,
+    args_override_self = true
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// Konfigurationsdatei (TOML) mit Voreinstellungen; ohne Angabe wird isit.toml im aktuellen
+    /// Verzeichnis gelesen, falls vorhanden. Optionen auf der Kommandozeile haben Vorrang.
+    #[clap(long, value_parser, global = true)]
+    config: Option<PathBuf>,
+    /// Verzeichnis mit zusätzlichen Eingabeformaten (eine TOML-Datei je Format).
+    #[clap(default_value = "formats.d", long, value_parser, global = true)]
+    formats_dir: PathBuf,
+    #[clap(flatten)]
+    args: Args,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum FileType {
-    Csv,
-    Excel,
+// Optionen der Umwandlung, für `isit`, `isit convert` und `isit diff`; `isit validate` nimmt
+// nur die Eingabe- und Passwort-Optionen.
+#[derive(Debug, clap::Args)]
+struct Args {
+    #[clap(flatten)]
+    input: InputArgs,
+    #[clap(flatten)]
+    passwords: PasswordArgs,
+    #[clap(flatten)]
+    usernames: UsernameArgs,
+    #[clap(flatten)]
+    output: OutputArgs,
+    /// Datum des Exports (JJJJ-MM-TT), wird nach dem Einlesen bestimmt und für {export_date}
+    /// in Ausgabepfaden eingesetzt.
+    #[clap(skip)]
+    export_date: String,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum Encoding {
-    Utf8,
-    Windows,
+/// Optionen von `isit validate`: Was geprüft wird, ohne die Ausgabe.
+#[derive(Debug, clap::Args)]
+struct ValidateArgs {
+    #[clap(flatten)]
+    input: InputArgs,
+    #[clap(flatten)]
+    passwords: PasswordArgs,
 }
 
-#[derive(Debug, Parser)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    #[clap(short, long, value_parser)]
-    file_path: String,
-    #[clap(default_value = "./import_iserv_ready.csv", short, long, value_parser)]
-    output_path: String,
-    #[clap(default_value_t = RecordType::Schild ,short, long, arg_enum, value_parser)]
-    record_type: RecordType,
-    #[clap(default_value_t = FileType::Csv, short = 't', long, arg_enum, value_parser)]
+impl From<ValidateArgs> for Args {
+    fn from(args: ValidateArgs) -> Self {
+        Args {
+            input: args.input,
+            passwords: args.passwords,
+            usernames: defaults(),
+            output: defaults(),
+            export_date: String::new(),
+        }
+    }
+}
+
+/// Eine Gruppe von Optionen mit ihren Voreinstellungen, wie ohne Angabe auf der Kommandozeile.
+fn defaults<T: clap::Args + clap::FromArgMatches>() -> T {
+    let command = T::augment_args(clap::Command::new("isit"));
+    T::from_arg_matches(&command.get_matches_from(["isit"]))
+        .expect("Optionsgruppe ohne Pflichtangaben")
+}
+
+/// Einlesen und Prüfen der Eingabe, gemeinsam für alle Unterbefehle, die einen Export lesen.
+#[derive(Debug, clap::Args)]
+#[clap(next_help_heading = "EINGABE")]
+struct InputArgs {
+    /// Eingabedatei, `-` für die Standardeingabe; mehrfach angeben oder ein Muster wie
+    /// `exports/*.csv`, um mehrere Dateien zu einer Ausgabe zusammenzuführen. Mit TYP=PFAD gilt
+    /// für diese Datei ein eigener Datensatztyp, z.B. -f schild.csv -f gastschueler=gast.xlsx.
+    /// Doppelte Import-IDs werden zusammengeführt.
+    #[clap(short, long, value_parser, required = true)]
+    file_path: Vec<String>,
+    /// Datensatztyp der Eingabe; ohne Angabe wird er an der Kopfzeile erkannt.
+    #[clap(short, long, arg_enum, value_parser)]
+    record_type: Option<RecordType>,
+    /// Eingabeformat aus --formats-dir statt eines eingebauten Datensatztyps.
+    #[clap(long, value_parser, conflicts_with = "record-type")]
+    format: Option<String>,
+    /// Zuordnungsdatei für --record-type custom (TOML wie in formats.d/, mit [transforms]).
+    #[clap(long, value_parser, required_if_eq("record-type", "custom"))]
+    mapping: Option<PathBuf>,
+    #[clap(default_value_t = FileType::Auto, short = 't', long, arg_enum, value_parser)]
     file_type: FileType,
-    #[clap(default_value_t = Encoding::Utf8, short, arg_enum, long, value_parser)]
+    #[clap(default_value_t = Encoding::Auto, short, arg_enum, long, value_parser)]
     encoding: Encoding,
+    /// Trennzeichen der CSV-Eingabe; `auto` erkennt `;`, `,` und Tab an den ersten Zeilen.
+    #[clap(default_value_t = Delimiter::Auto, long, arg_enum, value_parser)]
+    delimiter: Delimiter,
+    /// Spalte, in der die Einwilligung vermerkt ist. Ohne Angabe werden alle exportiert.
+    #[clap(long, value_parser)]
+    consent_column: Option<String>,
+    /// Wert in der Einwilligungsspalte, der als Einwilligung zählt. Ohne Angabe gelten
+    /// übliche Ja-Werte wie "ja", "x", "1" oder "wahr".
+    #[clap(long, value_parser)]
+    consent_value: Option<String>,
+    /// Regulärer Ausdruck, dem jede Import-ID entsprechen muss.
+    #[clap(default_value = DEFAULT_ID_REGEX, long, value_parser = Regex::new)]
+    id_regex: Regex,
+    /// Maximale Länge einer Import-ID.
+    #[clap(default_value_t = 64, long, value_parser)]
+    id_max_length: usize,
+    /// Erwartete Domain der Dienst-E-Mail von Lehrkräften, z.B. "schule.nrw.de".
+    #[clap(long, value_parser)]
+    email_domain: Option<String>,
+    /// Lehrkräfte ohne gültige Dienst-E-Mail als Fehler behandeln statt zu warnen, weil der
+    /// LDAP-Abgleich in IServ sie braucht.
+    #[clap(long, value_parser)]
+    require_email: bool,
+    /// Warnungen (z.B. eine leere Eingabe) als Fehler behandeln.
+    #[clap(long, value_parser)]
+    strict: bool,
+    /// Schreibweise der Klassen in der Ausgabe.
+    #[clap(default_value_t = ClassStyle::Compact, long, arg_enum, value_parser)]
+    class_style: ClassStyle,
+    /// Halbjahresbezeichnungen wie "Q1.1" nicht zur Stufe ("Q1") zusammenfassen.
+    #[clap(long, value_parser)]
+    keep_semesters: bool,
+    /// Feste Klassenzuordnungen (CSV mit Spalten Von;Nach), z.B. "Lerngruppe Blau;Blau".
+    #[clap(long, value_parser)]
+    class_rules: Option<PathBuf>,
+    /// Zweite Vornamen von Gastschülern ("Anna (Maria)", "Anna/Maria") im Vornamen behalten.
+    #[clap(long, value_parser)]
+    include_middle_names: bool,
+    /// Gastschüler, die mit anderer ID auch im SchILD-Export stehen, an Name und Klasse erkennen
+    /// und unter der SchILD-GUID zusammenführen. Jede ersetzte Gastschüler-ID wird gemeldet,
+    /// weil isit keine Historie führt, in der sie vermerkt werden könnte.
+    #[clap(long, value_parser)]
+    reconcile_guests: bool,
+    /// Höchstzahl verschiedener Klassen in der Ausgabe, bevor gewarnt (mit --strict:
+    /// abgebrochen) wird.
+    #[clap(default_value_t = 100, long, value_parser)]
+    max_classes: usize,
+    /// Wohin ein Namenszusatz aus SchILD ("von", "zu") übernommen wird.
+    #[clap(default_value_t = NameSuffixPolicy::PrependSurname, long, arg_enum, value_parser)]
+    name_suffix_policy: NameSuffixPolicy,
+    /// Verhalten bei Zeilen, die sich nicht lesen lassen oder eine ungültige Import-ID haben.
+    #[clap(default_value_t = OnError::Abort, long, arg_enum, value_parser)]
+    on_error: OnError,
+    /// Lauf abbrechen, wenn mehr Zeilen übersprungen wurden.
+    #[clap(long, value_parser)]
+    max_skipped: Option<usize>,
+    /// Lauf abbrechen, wenn mehr als dieser Anteil (in Prozent) der Zeilen übersprungen wurde.
+    #[clap(long, value_parser)]
+    max_skipped_percent: Option<f64>,
+    /// Höchstens so viele Datenzeilen einlesen, sonst wird die Datei abgelehnt.
+    #[clap(long, value_parser)]
+    max_rows: Option<usize>,
+    /// Höchstlänge eines einzelnen Feldes in Zeichen.
+    #[clap(long, value_parser)]
+    max_field_len: Option<usize>,
+    /// Größte zulässige Eingabedatei in MiB, bei Archiven auch für den entpackten Inhalt.
+    #[clap(long, value_parser)]
+    max_file_mb: Option<u64>,
+    /// Datei im ZIP-Archiv, die gelesen werden soll. Ohne Angabe wird die einzige Datei mit
+    /// passender Endung genommen.
+    #[clap(long, value_parser)]
+    zip_member: Option<String>,
+    /// Tabellenblatt in Excel- und ODS-Dateien, als Name oder Nummer ab 1. Ohne Angabe das erste.
+    #[clap(long, value_parser)]
+    sheet: Option<String>,
+    /// Alle Tabellenblätter lesen und ihre Zeilen zusammenführen, z.B. ein Blatt je Jahrgang.
+    #[clap(long, value_parser, conflicts_with = "sheet")]
+    all_sheets: bool,
+    /// Zeilen am Dateianfang, die vor der Kopfzeile übersprungen werden, z.B. ein Titelblock.
+    #[clap(default_value_t = 0, long, value_parser)]
+    skip_rows: usize,
+    /// Anzahl der Zeilen, in denen in Excel- und ODS-Dateien nach der Kopfzeile gesucht wird.
+    #[clap(default_value_t = 20, long, value_parser)]
+    header_scan_rows: usize,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct RecordSchild {
-    nachname: String,
-    vorname: String,
-    klasse: String,
-    #[serde(rename = "eindeutige Nummer (GUID)")]
-    guid: String,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "UPPERCASE")]
-struct RecordGastschueler {
-    #[serde(rename = "NAME, VORNAME")]
-    name: String,
-    klasse: String,
-    #[serde(rename = "SCHÜLERNR")]
-    schuelernr: String,
-}
-
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "PascalCase")]
-struct RecordIserv {
-    nachname: String,
-    vorname: String,
-    klasse: String,
-    #[serde(rename = "Import-ID")]
-    import_id: String,
-    password: String,
-}
-
-impl RecordIserv {
-    fn new(nachname: String, vorname: String, klasse: String, import_id: String) -> Self {
-        let mut config = BasicConfig::default();
-        config.words = 2;
-        config.word_provider = WordList::new(
-            WORDLIST
-                .lines()
-                .map(|w| w.to_owned())
-                .collect::<Vec<String>>(),
-        )
-        .sampler();
-        config.separator = "-".into();
-        config.capitalize_first = Probability::Never;
-        let scheme = config.to_scheme();
-        let password = scheme.generate();
-        RecordIserv {
-            nachname,
-            vorname,
-            klasse,
-            import_id,
-            password,
-        }
-    }
-}
-
-impl From<RecordSchild> for RecordIserv {
-    fn from(record: RecordSchild) -> Self {
-        let klasse: String;
-        if record.klasse.starts_with("11") {
-            klasse = "11".to_string();
-        } else if record.klasse.starts_with("12") {
-            klasse = "12".to_string();
-        } else if record.klasse.starts_with("13") {
-            klasse = "13".to_string();
+/// Wie neue Passwörter erzeugt und geprüft werden.
+#[derive(Debug, clap::Args)]
+#[clap(next_help_heading = "PASSWÖRTER")]
+struct PasswordArgs {
+    /// Startwert für neue Passwörter: mit gleichem Wert bekommt jede Import-ID bei jedem Lauf
+    /// dasselbe Passwort, z.B. nach dem Korrigieren eines Tippfehlers. Wer den Wert kennt, kann
+    /// die Passwörter nachrechnen, er gehört also nicht in geteilte Skripte.
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+    /// Art der neuen Passwörter; die übrigen Passwort-Optionen außer --pin-length gelten nur für
+    /// Wörter.
+    #[clap(default_value_t = PasswordStyle::Words, long, arg_enum, value_parser)]
+    password_style: PasswordStyle,
+    /// Anzahl der Ziffern einer PIN bei --password-style pin.
+    #[clap(default_value_t = 6, long, value_parser)]
+    pin_length: usize,
+    /// Anzahl der Wörter in neuen Passwörtern; mehr Wörter sind sicherer, aber schwerer zu merken,
+    /// z.B. 3 oder 4 für Lehrerkonten.
+    #[clap(default_value_t = 2, long, value_parser)]
+    password_words: usize,
+    /// Eigene Wortliste für neue Passwörter statt der eingebauten, ein Wort je Zeile, z.B. mit
+    /// altersgerechten Wörtern; mindestens 200 verschiedene.
+    #[clap(long, value_parser, conflicts_with = "wordlist-preset")]
+    wordlist: Option<PathBuf>,
+    /// Eingebaute Wortliste für neue Passwörter, z.B. englisch für Schüler, die noch kein
+    /// Deutsch lesen.
+    #[clap(default_value_t = WordlistPreset::Deutsch, long, arg_enum, value_parser)]
+    wordlist_preset: WordlistPreset,
+    /// Wörter mit weniger Zeichen aus der Wortliste streichen, z.B. 4.
+    #[clap(default_value_t = 0, long, value_parser)]
+    wordlist_min_length: usize,
+    /// Datei mit Wörtern, die nicht in Passwörtern vorkommen sollen, einer je Zeile; gestrichen
+    /// wird jedes Wort der Wortliste, das einen Eintrag enthält.
+    #[clap(long, value_parser)]
+    wordlist_blocklist: Option<PathBuf>,
+    /// Zeichen zwischen den Wörtern neuer Passwörter, z.B. "." oder leer für "WortWort".
+    #[clap(default_value = "-", long, value_parser)]
+    password_separator: String,
+    /// Groß- und Kleinschreibung der Wörter in neuen Passwörtern.
+    #[clap(default_value_t = Capitalize::Keep, long, arg_enum, value_parser)]
+    password_capitalize: Capitalize,
+    /// So viele zufällige Ziffern an neue Passwörter anhängen, für Richtlinien, die Ziffern
+    /// verlangen.
+    #[clap(default_value_t = 0, long, value_parser)]
+    password_digits: usize,
+    /// Ein zufälliges Sonderzeichen (!#$%&*+?) an neue Passwörter anhängen.
+    #[clap(long, value_parser)]
+    password_symbol: bool,
+    /// Abweichende Passwortverfahren je Rolle und Klasse (TOML), z.B. längere Passwörter für
+    /// Lehrkräfte in einer gemeinsamen Datei mit Schülern oder PINs für einen Jahrgang; ohne
+    /// Angabe gelten die Optionen oben für alle. `isit validate` zeigt das Verfahren je Klasse.
+    #[clap(long, value_parser)]
+    password_schemes: Option<PathBuf>,
+    /// Passwortrichtlinie (TOML), gegen die jedes Passwort geprüft wird: neue Passwörter werden
+    /// neu erzeugt, bis sie sie erfüllen, Passwörter aus der Eingabe bei Bedarf ergänzt.
+    #[clap(long, value_parser)]
+    verify_policy: Option<PathBuf>,
+    /// Keine Passwörter schreiben, auch nicht solche aus der Eingabe, z.B. um in IServ nur Namen
+    /// und Klassen zu aktualisieren, ohne die Zugangsdaten zurückzusetzen.
+    #[clap(long, value_parser, conflicts_with = "verify-policy")]
+    no_passwords: bool,
+    /// Fester Text in der Passwortspalte bei --no-passwords statt eines leeren Felds.
+    #[clap(long, value_parser, requires = "no-passwords")]
+    password_placeholder: Option<String>,
+    /// Warnen, wenn neue Passwörter weniger Entropie in Bit haben; anders als `min_entropy_bits`
+    /// in --verify-policy bricht der Lauf nicht ab. Die Entropie je Passwort kann mit
+    /// --column "Entropie={entropie}" mitgeschrieben werden.
+    #[clap(long, value_parser)]
+    warn_entropy_bits: Option<f64>,
+}
+
+/// Wie aus Namen Benutzernamen werden.
+#[derive(Debug, clap::Args)]
+#[clap(next_help_heading = "BENUTZERNAMEN")]
+struct UsernameArgs {
+    /// Eigene Umschriften für Benutzernamen (CSV mit Spalten Zeichen;Umschrift, z.B. "ş;sch"),
+    /// gehen der eingebauten Umschrift und any_ascii vor.
+    #[clap(long, value_parser)]
+    transliteration_table: Option<PathBuf>,
+    /// Apostrophe in Namen ("O'Brien") für Benutzernamen entfernen oder durch "-" ersetzen.
+    #[clap(default_value_t = NameSeparator::Remove, long, arg_enum, value_parser)]
+    username_apostrophe: NameSeparator,
+    /// Leerzeichen in Namen ("De la Cruz") für Benutzernamen entfernen oder durch "-" ersetzen.
+    #[clap(default_value_t = NameSeparator::Hyphen, long, arg_enum, value_parser)]
+    username_space: NameSeparator,
+    /// Umlaute und ß in Benutzernamen umschreiben ("joerg"), ohne Punkte schreiben ("jorg") oder
+    /// behalten ("jörg"), wenn die IServ-Installation sie in Logins erlaubt.
+    #[clap(default_value_t = UmlautPolicy::AeOeUe, long, arg_enum, value_parser)]
+    username_umlauts: UmlautPolicy,
+    /// Benutzernamen auf so viele Zeichen kürzen, zuerst den Vornamen, dann den Nachnamen.
+    #[clap(long, value_parser)]
+    username_max_length: Option<usize>,
+}
+
+/// Was wohin geschrieben wird.
+#[derive(Debug, clap::Args)]
+#[clap(next_help_heading = "AUSGABE")]
+struct OutputArgs {
+    /// Ausgabedatei, `-` für die Standardausgabe.
+    #[clap(default_value = "./import_iserv_ready.csv", short, long, value_parser)]
+    output_path: String,
+    /// Liste der Schüler ohne Einwilligung für das Sekretariat.
+    #[clap(default_value = "./ohne_einwilligung.csv", long, value_parser)]
+    consent_excluded_path: String,
+    /// Aufbewahrungsfrist der erzeugten Dateien (z.B. 14d, 36h, 2w), wird im Manifest vermerkt.
+    #[clap(long, value_parser = parse_ttl)]
+    credentials_ttl: Option<Duration>,
+    /// Zwischendateien mit Passwörtern vor dem Löschen überschreiben.
+    #[clap(long, value_parser)]
+    secure_delete: bool,
+    /// Version und Fingerabdruck der Einstellungen als Kommentar in die erste Zeile der Ausgabe
+    /// schreiben; die Einstellungen selbst stehen mit --credentials-ttl im Manifest.
+    #[clap(long, value_parser)]
+    stamp: bool,
+    /// Keine Zusammenfassung mit den nächsten Schritten am Ende ausgeben.
+    #[clap(short, long, value_parser)]
+    quiet: bool,
+    /// Zusätzlich je Klasse eine Datei in dieses Verzeichnis schreiben; die Gesamtdatei unter
+    /// --output-path wird weiterhin geschrieben.
+    #[clap(long, value_parser)]
+    split_by_class: Option<String>,
+    /// Zusätzlich je Rolle eine Datei neben --output-path schreiben, z.B. import_lehrer.csv und
+    /// import_schueler.csv, für getrennte Importprofile in IServ. Lehrkräfte kommen aus Eingaben
+    /// vom Typ lehrer, etwa -f schild.csv -f lehrer=lehrer.csv.
+    #[clap(long, value_parser)]
+    split_by_role: bool,
+    /// Dateiname der Klassendateien, {klasse} wird durch die Klasse ersetzt.
+    #[clap(default_value = "import_{klasse}.csv", long, value_parser)]
+    class_file_template: String,
+    /// Datei für die mit --on-error skip übersprungenen Zeilen.
+    #[clap(default_value = "./abgelehnt.csv", long, value_parser)]
+    rejects_path: String,
+    /// Bestehende IServ-Datei, an die nur neue Import-IDs angehängt werden; sie wird sortiert
+    /// neu geschrieben.
+    #[clap(long, value_parser, conflicts_with = "columns")]
+    append_to: Option<String>,
+    /// Spalte der IServ-Datei als "Überschrift={feld}", mehrfach in der gewünschten Reihenfolge,
+    /// z.B. --column Nachname --column Vorname --column Klasse --column "Importkennung={import_id}".
+    /// Felder: nachname, vorname, namenszusatz, klasse, import_id, passwort, benutzername, email,
+    /// entropie; in der Konfigurationsdatei als Liste `column = [...]`. Für Spalten, die eine
+    /// E-Mail-Adresse, Import-ID oder ein Login bilden, gelten --username-apostrophe und
+    /// --username-space auch für die Namen.
+    #[clap(long = "column", value_parser = parse_output_column)]
+    columns: Vec<OutputColumn>,
+    #[clap(default_value_t = OutputFormat::Csv, long, arg_enum, value_parser)]
+    output_format: OutputFormat,
+    /// Weitere Ausgabe als FORMAT=PFAD (z.B. credentials=zugaenge.csv), kann mehrfach angegeben
+    /// werden. Ohne Format wird eine IServ-CSV geschrieben.
+    #[clap(long = "output", alias = "also-output", value_parser = parse_output_spec)]
+    outputs: Vec<OutputSpec>,
+    /// Zeilenende der geschriebenen Dateien.
+    #[clap(default_value_t = LineEnding::Lf, long, arg_enum, value_parser)]
+    line_ending: LineEnding,
+    /// Zeichenkodierung der geschriebenen CSV-Dateien; Excel, JSON, LDIF und PDF bleiben, wie
+    /// ihr Format es vorschreibt.
+    #[clap(default_value_t = OutputEncoding::Utf8, long, arg_enum, value_parser)]
+    output_encoding: OutputEncoding,
+    /// Trennzeichen der geschriebenen CSV-Dateien, z.B. für andere Systeme als IServ. Die
+    /// Moodle-Ausgabe bleibt bei Kommas.
+    #[clap(default_value_t = OutputDelimiter::Semicolon, long, arg_enum, value_parser)]
+    output_delimiter: OutputDelimiter,
+    /// Wann Felder der geschriebenen CSV-Dateien in Anführungszeichen stehen.
+    #[clap(default_value_t = Quoting::Necessary, long, arg_enum, value_parser)]
+    output_quoting: Quoting,
+    /// Zuordnung Klasse → Lehrerkürzel (CSV mit Spalten Klasse;Lehrer) für den Gruppenimport.
+    #[clap(long, value_parser)]
+    tutors: Option<PathBuf>,
+    /// Gruppenimport mit den Klassenlehrern als Besitzer, wird nur mit --tutors geschrieben.
+    #[clap(default_value = "./gruppen_iserv.csv", long, value_parser)]
+    groups_path: String,
+    /// DN, unter dem die LDIF-Ausgabe die Konten anlegt, z.B. "ou=schueler,dc=schule,dc=de".
+    #[clap(long, value_parser)]
+    ldap_base_dn: Option<String>,
+    /// objectClass der LDIF-Einträge, kann mehrfach angegeben werden.
+    #[clap(default_value = "inetOrgPerson", long, value_parser)]
+    ldap_object_class: Vec<String>,
+    /// Vorlage (TOML mit kopf, betreff und text) für die Passwortbriefe der Ausgabe letters.
+    #[clap(long, value_parser)]
+    letter_template: Option<PathBuf>,
+    /// QR-Code auf jedem Passwortbrief mit diesem Inhalt, z.B. "{benutzername}\n{passwort}" oder
+    /// eine Anmelde-URL; Platzhalter wie in --letter-template.
+    #[clap(long, value_parser)]
+    letter_qr: Option<String>,
+}
+
+impl InputArgs {
+    fn class_normalizer(&self) -> Result<ClassNormalizer, IsitError> {
+        let mut normalizer = ClassNormalizer::new(self.class_style, !self.keep_semesters);
+        if let Some(path) = &self.class_rules {
+            normalizer.load_rules(path, self.encoding)?;
+        }
+        Ok(normalizer)
+    }
+
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions {
+            encoding: self.encoding,
+            delimiter: self.delimiter,
+            zip_member: self.zip_member.clone(),
+            header_scan_rows: self.header_scan_rows,
+            on_error: self.on_error,
+            limits: ReadLimits {
+                max_rows: self.max_rows,
+                max_field_len: self.max_field_len,
+                max_file_size: self.max_file_mb.map(|mb| mb * 1024 * 1024),
+            },
+            sheet: self.sheet.clone(),
+            all_sheets: self.all_sheets,
+            skip_rows: self.skip_rows,
+        }
+    }
+}
+
+impl PasswordArgs {
+    fn password_options(&self, encoding: Encoding) -> Result<PasswordOptions, IsitError> {
+        if self.password_words == 0 {
+            return Err(IsitError::Usage(
+                "--password-words muss mindestens 1 sein.".to_owned(),
+            ));
+        }
+        if self.password_separator.contains(char::is_whitespace) {
+            return Err(IsitError::Usage(
+                "--password-separator darf keine Leerzeichen enthalten.".to_owned(),
+            ));
+        }
+        if self.password_style == PasswordStyle::Pin && self.pin_length < 4 {
+            return Err(IsitError::Usage(
+                "--pin-length muss mindestens 4 sein.".to_owned(),
+            ));
+        }
+        let mut options = PasswordOptions {
+            style: self.password_style,
+            seed: self.seed,
+            pin_length: self.pin_length,
+            words: self.password_words,
+            digits: self.password_digits,
+            symbol: self.password_symbol,
+            separator: self.password_separator.clone(),
+            capitalize: self.password_capitalize,
+            ..PasswordOptions::default()
+        };
+        let wordlist = match &self.wordlist {
+            Some(path) => load_wordlist(path, encoding)?,
+            None => self.wordlist_preset.words(),
+        };
+        options.wordlist = self.filter_wordlist(wordlist, encoding)?;
+        Ok(options)
+    }
+
+    /// Wendet --wordlist-min-length und --wordlist-blocklist an.
+    fn filter_wordlist(
+        &self,
+        wordlist: Vec<String>,
+        encoding: Encoding,
+    ) -> Result<Vec<String>, IsitError> {
+        let blocklist = match &self.wordlist_blocklist {
+            Some(path) => load_blocklist(path, encoding)?,
+            None => Vec::new(),
+        };
+        filter_wordlist(wordlist, self.wordlist_min_length, &blocklist)
+    }
+
+    /// Das Verfahren der Passwort-Optionen für alle, davor die aus --password-schemes.
+    fn password_schemes(&self, encoding: Encoding) -> Result<PasswordSchemes, IsitError> {
+        let mut schemes = PasswordSchemes::new(self.password_options(encoding)?);
+        let Some(path) = &self.password_schemes else {
+            return Ok(schemes);
+        };
+        let table = SchemeTable::load(path)?;
+        let default = schemes.0[0].1.clone();
+        let roles = [
+            (Rolle::Schueler, table.schueler),
+            (Rolle::Lehrer, table.lehrer),
+        ];
+        let roles = roles
+            .into_iter()
+            .filter_map(|(rolle, scheme)| Some((SchemeKey::Rolle(rolle), scheme?)));
+        // Kürzere Schlüssel zuerst, damit "5a" vor "5" steht.
+        let mut classes: Vec<_> = table.klassen.into_iter().collect();
+        classes.sort_by_key(|(klasse, _)| klasse.len());
+        let classes = classes
+            .into_iter()
+            .map(|(klasse, scheme)| (SchemeKey::Klasse(klasse), scheme));
+        for (key, scheme) in roles.chain(classes) {
+            let options = scheme
+                .apply(&default, |preset| {
+                    self.filter_wordlist(preset.words(), encoding)
+                })
+                .map_err(|error| IsitError::config(path)(error.to_string()))?;
+            schemes.add(key, options);
+        }
+        Ok(schemes)
+    }
+}
+
+impl UsernameArgs {
+    fn username_options(&self, encoding: Encoding) -> Result<UsernameOptions, IsitError> {
+        let overrides = match &self.transliteration_table {
+            Some(path) => Some(OverrideTable::load(path, encoding)?),
+            None => None,
+        };
+        Ok(UsernameOptions {
+            transliterator: Transliterator::with_overrides(overrides),
+            apostrophe: self.username_apostrophe,
+            space: self.username_space,
+            umlauts: self.username_umlauts,
+            max_length: self.username_max_length,
+        })
+    }
+}
+
+impl OutputArgs {
+    /// Zeilenende, Kodierung, Trenner und Anführungszeichen für Listen wie die abgelehnten
+    /// Zeilen, die keine IServ-Datei sind.
+    fn csv_options(&self) -> OutputOptions {
+        OutputOptions {
+            line_ending: self.line_ending,
+            encoding: self.output_encoding,
+            delimiter: self.output_delimiter,
+            quoting: self.output_quoting,
+            ..OutputOptions::default()
+        }
+    }
+}
+
+impl Args {
+    /// Setzt das Exportdatum in alle Ausgabepfade ein, die den Platzhalter {export_date} enthalten.
+    fn expand_export_date(&mut self, export_date: String) {
+        let expand = |path: &mut String| *path = path.replace("{export_date}", &export_date);
+        expand(&mut self.output.output_path);
+        for output in &mut self.output.outputs {
+            expand(&mut output.path);
+        }
+        expand(&mut self.output.consent_excluded_path);
+        expand(&mut self.output.groups_path);
+        expand(&mut self.output.rejects_path);
+        expand(&mut self.output.class_file_template);
+        if let Some(dir) = &mut self.output.split_by_class {
+            expand(dir);
+        }
+        if let Some(path) = &mut self.output.append_to {
+            expand(path);
+        }
+        self.export_date = export_date;
+    }
+
+    /// Alle Einstellungen für die Ausgaben eines Laufs; `options_hash` kommt mit --stamp in die
+    /// erste Zeile.
+    fn output_options(&self, options_hash: &str) -> Result<OutputOptions, IsitError> {
+        let mut letter_template = match &self.output.letter_template {
+            Some(path) => LetterTemplate::load(path)?,
+            None => LetterTemplate::default(),
+        };
+        if self.output.letter_qr.is_some() {
+            letter_template.qr = self.output.letter_qr.clone();
+        }
+        Ok(OutputOptions {
+            stamp: self
+                .output
+                .stamp
+                .then(|| format!("# isit {}, Optionen {}", VERSION, options_hash)),
+            name_suffix_column: self.input.name_suffix_policy == NameSuffixPolicy::SeparateColumn,
+            username: self.usernames.username_options(self.input.encoding)?,
+            ldap: LdapOptions {
+                base_dn: self.output.ldap_base_dn.clone(),
+                object_classes: self.output.ldap_object_class.clone(),
+            },
+            letter_template,
+            export_date: self.export_date.clone(),
+            columns: self.output.columns.clone(),
+            ..self.output.csv_options()
+        })
+    }
+}
+
+/// Wert einer Auswahloption, wie er auf der Kommandozeile steht.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_owned())
+        .unwrap_or_default()
+}
+
+/// Setzt einen Wert für die Kommandozeile in Anführungszeichen, wenn er leer ist oder
+/// Leerzeichen oder Anführungszeichen enthält.
+fn shell_word(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"') {
+        return value.to_owned();
+    }
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Args {
+    /// Die Einstellungen dieses Laufs, die den Inhalt der Ausgabe bestimmen, als Optionen für
+    /// die Kommandozeile in fester Reihenfolge, z.B. "--class-style compact --password-words 2".
+    /// Pfade und das Exportdatum fehlen, damit gleiche Einstellungen gleich aussehen; von
+    /// --seed steht nur, dass es gesetzt war.
+    fn settings(&self) -> String {
+        let mut settings: Vec<(&str, Option<String>)> = Vec::new();
+        let mut set = |name, value: Option<String>| settings.push((name, value));
+        if let Some(record_type) = self.input.record_type {
+            set("record-type", Some(value_name(record_type)));
+        }
+        if let Some(format) = &self.input.format {
+            set("format", Some(format.clone()));
+        }
+        set("file-type", Some(value_name(self.input.file_type)));
+        set("encoding", Some(value_name(self.input.encoding)));
+        set("delimiter", Some(value_name(self.input.delimiter)));
+        if let Some(sheet) = &self.input.sheet {
+            set("sheet", Some(sheet.clone()));
+        }
+        if self.input.all_sheets {
+            set("all-sheets", None);
+        }
+        set("skip-rows", Some(self.input.skip_rows.to_string()));
+        set(
+            "header-scan-rows",
+            Some(self.input.header_scan_rows.to_string()),
+        );
+        if let Some(column) = &self.input.consent_column {
+            set("consent-column", Some(column.clone()));
+        }
+        if let Some(value) = &self.input.consent_value {
+            set("consent-value", Some(value.clone()));
+        }
+        set("id-regex", Some(self.input.id_regex.as_str().to_owned()));
+        set("id-max-length", Some(self.input.id_max_length.to_string()));
+        set("on-error", Some(value_name(self.input.on_error)));
+        set("class-style", Some(value_name(self.input.class_style)));
+        if self.input.keep_semesters {
+            set("keep-semesters", None);
+        }
+        if self.input.include_middle_names {
+            set("include-middle-names", None);
+        }
+        if self.input.reconcile_guests {
+            set("reconcile-guests", None);
+        }
+        set(
+            "name-suffix-policy",
+            Some(value_name(self.input.name_suffix_policy)),
+        );
+        set("output-format", Some(value_name(self.output.output_format)));
+        for output in &self.output.outputs {
+            set("output", Some(value_name(output.format)));
+        }
+        for column in &self.output.columns {
+            set(
+                "column",
+                Some(format!("{}={}", column.header, column.value)),
+            );
+        }
+        set("line-ending", Some(value_name(self.output.line_ending)));
+        set(
+            "output-encoding",
+            Some(value_name(self.output.output_encoding)),
+        );
+        set(
+            "output-delimiter",
+            Some(value_name(self.output.output_delimiter)),
+        );
+        set(
+            "output-quoting",
+            Some(value_name(self.output.output_quoting)),
+        );
+        if self.passwords.no_passwords {
+            set("no-passwords", None);
+            if let Some(placeholder) = &self.passwords.password_placeholder {
+                set("password-placeholder", Some(placeholder.clone()));
+            }
         } else {
-            klasse = record.klasse
+            if self.passwords.seed.is_some() {
+                set("seed", Some("…".to_owned()));
+            }
+            set(
+                "password-style",
+                Some(value_name(self.passwords.password_style)),
+            );
+            set("pin-length", Some(self.passwords.pin_length.to_string()));
+            set(
+                "password-words",
+                Some(self.passwords.password_words.to_string()),
+            );
+            if self.passwords.wordlist.is_none() {
+                set(
+                    "wordlist-preset",
+                    Some(value_name(self.passwords.wordlist_preset)),
+                );
+            }
+            set(
+                "wordlist-min-length",
+                Some(self.passwords.wordlist_min_length.to_string()),
+            );
+            set(
+                "password-separator",
+                Some(self.passwords.password_separator.clone()),
+            );
+            set(
+                "password-capitalize",
+                Some(value_name(self.passwords.password_capitalize)),
+            );
+            set(
+                "password-digits",
+                Some(self.passwords.password_digits.to_string()),
+            );
+            if self.passwords.password_symbol {
+                set("password-symbol", None);
+            }
+        }
+        set(
+            "username-apostrophe",
+            Some(value_name(self.usernames.username_apostrophe)),
+        );
+        set(
+            "username-space",
+            Some(value_name(self.usernames.username_space)),
+        );
+        set(
+            "username-umlauts",
+            Some(value_name(self.usernames.username_umlauts)),
+        );
+        if let Some(max_length) = self.usernames.username_max_length {
+            set("username-max-length", Some(max_length.to_string()));
+        }
+        if let Some(base_dn) = &self.output.ldap_base_dn {
+            set("ldap-base-dn", Some(base_dn.clone()));
+        }
+        for object_class in &self.output.ldap_object_class {
+            set("ldap-object-class", Some(object_class.clone()));
+        }
+        if let Some(qr) = &self.output.letter_qr {
+            set("letter-qr", Some(qr.clone()));
+        }
+        settings
+            .into_iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("--{} {}", name, shell_word(&value)),
+                None => format!("--{}", name),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Kurzer, stabiler Fingerabdruck der Einstellungen aus [`Args::settings`] (FNV-1a).
+    fn options_hash(&self) -> String {
+        let hash = self
+            .settings()
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+            });
+        format!("{:016x}", hash)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Wandelt einen Export in eine IServ-Importdatei um, wie `isit` ohne Unterbefehl.
+    #[clap(args_override_self = true)]
+    Convert(Args),
+    /// Liest und prüft einen Export wie `convert`, schreibt aber keine Dateien.
+    #[clap(args_override_self = true)]
+    Validate(ValidateArgs),
+    /// Wandelt einen Export um wie `convert`, schreibt aber nur die Schüler, die in IServ noch
+    /// kein Konto haben.
+    #[clap(args_override_self = true)]
+    Diff {
+        /// Benutzerexport aus IServ oder eine früher geschriebene Importdatei; abgeglichen wird
+        /// über die Import-ID.
+        #[clap(long, value_parser)]
+        existing: PathBuf,
+        /// Liste der Konten, die im neuen Export fehlen und deaktiviert werden können. Der
+        /// IServ-Export sollte dafür nur die Schülerkonten enthalten.
+        #[clap(default_value = "./abgaenger.csv", long, value_parser)]
+        leavers_path: String,
+        /// Liste der Schüler mit Konto, die die Klasse gewechselt haben, mit alter und neuer
+        /// Klasse, um die Gruppen in IServ anzupassen.
+        #[clap(default_value = "./updates.csv", long, value_parser)]
+        updates_path: String,
+        #[clap(flatten)]
+        args: Args,
+    },
+    /// Erzeugt und prüft Passwörter, ohne einen Export umzuwandeln.
+    Passwords {
+        #[clap(subcommand)]
+        command: PasswordsCommand,
+    },
+    /// Wie `isit passwords audit`, für bestehende Aufrufe.
+    #[clap(hide = true)]
+    Audit(AuditArgs),
+    /// Löscht erzeugte Dateien, deren Aufbewahrungsfrist abgelaufen ist.
+    Cleanup {
+        /// Verzeichnis, in dem nach Manifesten gesucht wird.
+        #[clap(default_value = ".", short, long, value_parser)]
+        dir: String,
+    },
+    /// Listet alle unterstützten Eingabeformate mit ihren Spalten auf.
+    ListFormats,
+    /// Zeigt Füllgrad und Werteverteilung jeder Spalte einer Eingabedatei.
+    Profile {
+        #[clap(value_parser)]
+        file: String,
+        #[clap(default_value_t = FileType::Auto, short = 't', long, arg_enum, value_parser)]
+        file_type: FileType,
+        #[clap(default_value_t = Encoding::Auto, short, arg_enum, long, value_parser)]
+        encoding: Encoding,
+    },
+    /// Prüft mit eingebauten Beispieldateien, ob die Umwandlung auf diesem System wie erwartet arbeitet.
+    Selftest,
+    /// Zeigt eine IServ-Importdatei als Tabelle an.
+    Show {
+        #[clap(value_parser)]
+        file: String,
+        /// Passwörter nicht im Klartext anzeigen; ohne Wert werden sie vollständig verdeckt.
+        #[clap(
+            short,
+            long,
+            arg_enum,
+            value_parser,
+            default_value_t = PasswordMask::None,
+            default_missing_value = "full",
+            min_values = 0
+        )]
+        mask_passwords: PasswordMask,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PasswordsCommand {
+    /// Zeigt Beispielpasswörter je Verfahren mit ihrer Entropie, um die Passwort-Optionen und
+    /// --password-schemes vor einem Lauf zu prüfen.
+    Generate {
+        /// So viele Passwörter je Verfahren.
+        #[clap(default_value_t = 5, short = 'n', long, value_parser)]
+        count: usize,
+        /// Zeichenkodierung von --wordlist und --wordlist-blocklist.
+        #[clap(default_value_t = Encoding::Auto, short, arg_enum, long, value_parser)]
+        encoding: Encoding,
+        #[clap(flatten)]
+        passwords: PasswordArgs,
+    },
+    /// Prüft die Passwörter einer bereits ausgegebenen IServ-Datei und listet die Konten,
+    /// deren Passwort erneuert werden sollte, z.B. für `isit passwords regen`.
+    Audit {
+        #[clap(flatten)]
+        audit: AuditArgs,
+        /// Zeilenende der Liste.
+        #[clap(default_value_t = LineEnding::Lf, long, arg_enum, value_parser)]
+        line_ending: LineEnding,
+    },
+    /// Gibt den Konten aus einer Liste, z.B. erneuern.csv von `isit passwords audit`, neue
+    /// Passwörter nach den Passwort-Optionen und schreibt nur diese Konten als IServ-Datei, die
+    /// in IServ importiert die alten Passwörter ersetzt.
+    Regen {
+        /// Bereits ausgegebene IServ-Datei mit den Konten.
+        #[clap(value_parser)]
+        file: PathBuf,
+        /// Liste der zu erneuernden Konten; gelesen wird nur die Spalte Import-ID.
+        #[clap(long, value_parser)]
+        accounts: PathBuf,
+        /// IServ-Datei mit den erneuerten Konten.
+        #[clap(default_value = "./erneuert.csv", short, long, value_parser)]
+        output: String,
+        /// Zeilenende der IServ-Datei.
+        #[clap(default_value_t = LineEnding::Lf, long, arg_enum, value_parser)]
+        line_ending: LineEnding,
+        /// Zeichenkodierung von --wordlist und --wordlist-blocklist.
+        #[clap(default_value_t = Encoding::Auto, short, arg_enum, long, value_parser)]
+        encoding: Encoding,
+        #[clap(flatten)]
+        passwords: PasswordArgs,
+    },
+}
+
+/// Eine bereits ausgegebene IServ-Datei, deren Passwörter geprüft werden.
+#[derive(Debug, clap::Args)]
+struct AuditArgs {
+    #[clap(value_parser)]
+    file: PathBuf,
+    /// Passwortrichtlinie (TOML), gegen die geprüft wird; ohne sie werden nur leere,
+    /// doppelte und den Namen enthaltende Passwörter gemeldet.
+    #[clap(long, value_parser)]
+    policy: Option<PathBuf>,
+    /// Datei mit den zu erneuernden Konten.
+    #[clap(default_value = "./erneuern.csv", short, long, value_parser)]
+    output: String,
+}
+
+/// Konfigurationsdatei, die ohne --config im aktuellen Verzeichnis gesucht wird.
+const CONFIG_FILE: &str = "isit.toml";
+
+/// Macht aus jedem Eintrag der Konfigurationsdatei eine Option, z.B. `file_type = "excel"` →
+/// `--file-type=excel`, `strict = true` → `--strict`. Listen ergeben die Option mehrfach:
+///
+/// ```toml
+/// file_type = "excel"
+/// encoding = "windows"
+/// output_path = "./import_{export_date}.csv"
+/// class_rules = "klassen.csv"
+/// output = ["credentials=zugaenge.csv"]
+/// ```
+fn config_args(cli: &[OsString]) -> Result<Vec<OsString>, IsitError> {
+    let explicit = cli.iter().enumerate().find_map(|(i, arg)| {
+        let arg = arg.to_str()?;
+        match arg.strip_prefix("--config=") {
+            Some(path) => Some(OsString::from(path)),
+            None if arg == "--config" => cli.get(i + 1).cloned(),
+            None => None,
+        }
+    });
+    let path = match explicit {
+        Some(path) => PathBuf::from(path),
+        None if Path::new(CONFIG_FILE).is_file() => PathBuf::from(CONFIG_FILE),
+        None => return Ok(Vec::new()),
+    };
+    info!("Lese Einstellungen aus {}.", path.display());
+    let text = fs::read_to_string(&path).map_err(IsitError::io(&path))?;
+    let table: toml::Table = toml::from_str(&text).map_err(IsitError::config(&path))?;
+    let root = Cli::command();
+    let (_, subcommand) = config_position(cli);
+    let command = subcommand
+        .iter()
+        .try_fold(&root, |command, name| command.find_subcommand(*name))
+        .unwrap_or(&root);
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        if !knows_option(&root, &long) || long == "config" {
+            return Err(IsitError::config(&path)(format!(
+                "Unbekannte Einstellung \"{}\".",
+                key
+            )));
+        }
+        // Eine Datei für alle Unterbefehle: --output-path gilt z.B. nicht für `isit validate`.
+        let global = root
+            .get_arguments()
+            .any(|arg| arg.get_long() == Some(long.as_str()) && arg.is_global_set());
+        if !global
+            && !command
+                .get_arguments()
+                .any(|arg| arg.get_long() == Some(long.as_str()))
+        {
+            continue;
+        }
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
         };
-        RecordIserv::new(record.nachname, record.vorname, klasse, record.guid)
+        for value in values {
+            let arg = match value {
+                toml::Value::Boolean(true) => format!("--{}", long),
+                toml::Value::Boolean(false) => continue,
+                toml::Value::String(value) => format!("--{}={}", long, value),
+                toml::Value::Integer(value) => format!("--{}={}", long, value),
+                toml::Value::Float(value) => format!("--{}={}", long, value),
+                value => {
+                    return Err(IsitError::config(&path)(format!(
+                        "Ungültiger Wert {} für \"{}\".",
+                        value, key
+                    )))
+                }
+            };
+            args.push(OsString::from(arg));
+        }
     }
+    Ok(args)
+}
+
+/// Ob `command` oder einer seiner Unterbefehle die Option `--long` kennt.
+fn knows_option(command: &clap::Command, long: &str) -> bool {
+    command
+        .get_arguments()
+        .any(|arg| arg.get_long() == Some(long))
+        || command
+            .get_subcommands()
+            .any(|subcommand| knows_option(subcommand, long))
+}
+
+/// Stelle, an der die Optionen aus der Konfigurationsdatei eingefügt werden, und der
+/// Unterbefehl, für den sie gelten: hinter `convert`, `validate`, `diff` oder `passwords …`,
+/// sonst vor alle anderen.
+fn config_position(cli: &[OsString]) -> (usize, Vec<&str>) {
+    let mut i = 0;
+    while let Some(arg) = cli.get(i).and_then(|arg| arg.to_str()) {
+        match arg {
+            "--config" | "--formats-dir" => i += 2,
+            _ if arg.starts_with("--config=") || arg.starts_with("--formats-dir=") => i += 1,
+            "convert" | "validate" | "diff" => return (i + 1, vec![arg]),
+            "passwords" => {
+                return match cli.get(i + 1).and_then(|arg| arg.to_str()) {
+                    Some(subcommand) if !subcommand.starts_with('-') => {
+                        (i + 2, vec![arg, subcommand])
+                    }
+                    _ => (0, Vec::new()),
+                }
+            }
+            _ => return (0, Vec::new()),
+        }
+    }
+    (0, Vec::new())
+}
+
+fn parse_ttl(arg: &str) -> Result<Duration, String> {
+    let arg = arg.trim();
+    let split = arg.len() - arg.chars().last().map(char::len_utf8).unwrap_or(0);
+    let (amount, unit) = arg.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("\"{}\" ist keine gültige Frist, erwartet z.B. 14d", arg))?;
+    let seconds = match unit {
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Unbekannte Einheit \"{}\", erlaubt sind h, d und w",
+                unit
+            ))
+        }
+    };
+    Ok(Duration::from_secs(amount * seconds))
 }
 
-impl From<RecordGastschueler> for RecordIserv {
-    fn from(record: RecordGastschueler) -> Self {
-        let name: Vec<&str> = record.name.split(", ").collect();
-        let nachname = String::from(&name[0][1..]);
-        let vorname = name[1].split(" (G)").collect();
-        RecordIserv::new(nachname, vorname, record.klasse, record.schuelernr)
+/// Logger, der gleichartige Warnungen nur beim ersten Auftreten ausgibt und am Ende meldet,
+/// wie oft sie vorkamen. Gleichartig heißt: gleicher Text bis auf Zahlen und Werte in
+/// Anführungszeichen, z.B. dieselbe Warnung für jede Zeile einer falsch kodierten Datei.
+struct DedupLogger {
+    inner: env_logger::Logger,
+    pattern: Regex,
+    warnings: Mutex<WarningCounts>,
+}
+
+#[derive(Default)]
+struct WarningCounts {
+    /// Position jeder Warnungsart in `seen`.
+    index: HashMap<String, usize>,
+    /// Erste Meldung jeder Warnungsart und ihre Anzahl, in der Reihenfolge des Auftretens.
+    seen: Vec<(String, usize)>,
+}
+
+static LOGGER: OnceLock<DedupLogger> = OnceLock::new();
+
+impl DedupLogger {
+    fn init() {
+        let inner =
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+                .build();
+        let max_level = inner.filter();
+        let logger = LOGGER.get_or_init(|| DedupLogger {
+            inner,
+            pattern: Regex::new(r#"\d+|"[^"]*""#).unwrap(),
+            warnings: Mutex::new(WarningCounts::default()),
+        });
+        log::set_logger(logger).expect("Logger ist bereits gesetzt");
+        log::set_max_level(max_level);
+    }
+
+    /// Zählt die Warnung und gibt zurück, ob sie zum ersten Mal auftritt.
+    fn first_occurrence(&self, message: &str) -> bool {
+        let key = self.pattern.replace_all(message, "…").into_owned();
+        let mut warnings = self.warnings.lock().unwrap();
+        match warnings.index.get(&key) {
+            Some(&i) => {
+                warnings.seen[i].1 += 1;
+                false
+            }
+            None => {
+                let i = warnings.seen.len();
+                warnings.index.insert(key, i);
+                warnings.seen.push((message.to_owned(), 1));
+                true
+            }
+        }
+    }
+
+    /// Meldet alle Warnungen, die mehrfach auftraten.
+    fn report() {
+        let logger = match LOGGER.get() {
+            Some(logger) => logger,
+            None => return,
+        };
+        let warnings = logger.warnings.lock().unwrap();
+        for (first, count) in warnings.seen.iter().filter(|(_, count)| *count > 1) {
+            logger.inner.log(
+                &log::Record::builder()
+                    .args(format_args!(
+                        "Diese Warnung trat {}-mal auf, zuerst: {}",
+                        count, first
+                    ))
+                    .level(log::Level::Warn)
+                    .target(module_path!())
+                    .build(),
+            );
+        }
     }
 }
 
-impl From<Record> for RecordIserv {
-    fn from(record: Record) -> Self {
-        match record {
-            Record::RecordSchild(record) => record.into(),
-            Record::RecordGastschueler(record) => record.into(),
+impl Log for DedupLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        if record.level() == log::Level::Warn && !self.first_occurrence(&record.args().to_string())
+        {
+            return;
         }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
     }
 }
 
 fn main() {
-    env_logger::init();
+    DedupLogger::init();
     info!("Programm gestartet.");
-    let args = Args::parse();
-    let records: Result<Vec<Record>, _>;
-    let path = PathBuf::from(args.file_path);
-    info!("Öffne nun Datei.");
-    match args.file_type {
-        FileType::Csv => {
-            records = get_all_csv_records_in_file(path, args.record_type, args.encoding);
+    let cli: Vec<OsString> = std::env::args_os().collect();
+    let config = match config_args(&cli[1..]) {
+        Ok(config) => config,
+        Err(e) => fail(e),
+    };
+    let (program, rest) = cli.split_at(1);
+    let (before, after) = rest.split_at(config_position(rest).0);
+    let mut cli = Cli::parse_from(
+        program
+            .iter()
+            .chain(before)
+            .cloned()
+            .chain(config)
+            .chain(after.iter().cloned()),
+    );
+    match cli.command.take() {
+        Some(Command::Convert(args)) => {
+            if let Err(e) = convert(args, &cli.formats_dir) {
+                fail(e);
+            }
+        }
+        Some(Command::Validate(args)) => {
+            if let Err(e) = validate(args.into(), &cli.formats_dir) {
+                fail(e);
+            }
+        }
+        Some(Command::Diff {
+            existing,
+            leavers_path,
+            updates_path,
+            args,
+        }) => {
+            if let Err(e) = diff_existing(
+                args,
+                &existing,
+                leavers_path,
+                updates_path,
+                &cli.formats_dir,
+            ) {
+                fail(e);
+            }
+        }
+        Some(Command::Passwords { command }) => match command {
+            PasswordsCommand::Generate {
+                count,
+                encoding,
+                passwords,
+            } => {
+                if let Err(e) = print_sample_passwords(&passwords, encoding, count) {
+                    fail(e);
+                }
+            }
+            PasswordsCommand::Audit { audit, line_ending } => run_audit(audit, line_ending),
+            PasswordsCommand::Regen {
+                file,
+                accounts,
+                output,
+                line_ending,
+                encoding,
+                passwords,
+            } => {
+                let options = OutputOptions {
+                    line_ending,
+                    ..OutputOptions::default()
+                };
+                let regen =
+                    regen_passwords(&file, &accounts, output, &options, &passwords, encoding);
+                if let Err(e) = regen {
+                    fail(e);
+                }
+            }
+        },
+        Some(Command::Audit(audit)) => run_audit(audit, cli.args.output.line_ending),
+        Some(Command::Cleanup { dir }) => match cleanup_expired_files(dir) {
+            Ok(_) => (),
+            Err(e) => fail(e),
+        },
+        Some(Command::ListFormats) => list_formats(&cli.formats_dir),
+        Some(Command::Selftest) => {
+            if !selftest() {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Profile {
+            file,
+            file_type,
+            encoding,
+        }) => match profile_file(PathBuf::from(file), file_type, encoding) {
+            Ok(_) => (),
+            Err(e) => fail(e),
+        },
+        Some(Command::Show {
+            file,
+            mask_passwords,
+        }) => match show_iserv_file(file, mask_passwords) {
+            Ok(_) => (),
+            Err(e) => fail(e),
+        },
+        None => {
+            if let Err(e) = convert(cli.args, &cli.formats_dir) {
+                fail(e);
+            }
+        }
+    }
+    DedupLogger::report();
+    info!("Beende das Programm.");
+}
+
+/// Meldet den Fehler und beendet das Programm mit dem zur Fehlerart gehörenden Exit-Code.
+fn fail(error: IsitError) -> ! {
+    error!("{}", error);
+    DedupLogger::report();
+    std::process::exit(error.exit_code())
+}
+
+/// Eingelesene und geprüfte Datensätze, bereit zum Schreiben.
+struct Prepared {
+    records: Vec<RecordIserv>,
+    excluded: Vec<RecordExcluded>,
+    rejects: Vec<Reject>,
+    /// Fehlt bei --no-passwords.
+    passwords: Option<PasswordReport>,
+}
+
+fn convert(mut args: Args, formats_dir: &Path) -> Result<(), IsitError> {
+    let Prepared {
+        records,
+        excluded,
+        rejects,
+        passwords,
+    } = prepare(&mut args, formats_dir)?;
+    check_rejects(&args, &rejects, records.len())?;
+    let written = write_outputs(&args, &records, &excluded, |_, _| Ok(Vec::new()))?;
+    // Auf der Standardausgabe stehen dann die Datensätze, der Bericht würde sie verderben.
+    if !args.output.quiet && !is_stdio(Path::new(&args.output.output_path)) {
+        print_summary(
+            &args,
+            &written,
+            records.len(),
+            rejects.len(),
+            passwords.as_ref(),
+        );
+    }
+    Ok(())
+}
+
+/// Wandelt um und schreibt nur die Schüler, deren Import-ID in `existing` fehlt, dazu die
+/// Konten aus `existing`, die im Export fehlen, nach `leavers_path` und die Klassenwechsel nach
+/// `updates_path`.
+fn diff_existing(
+    mut args: Args,
+    existing: &Path,
+    mut leavers_path: String,
+    mut updates_path: String,
+    formats_dir: &Path,
+) -> Result<(), IsitError> {
+    let mut existing_records = read_existing(existing)?;
+    // Damit "07 B" im IServ-Export nicht als Wechsel nach "7b" gilt.
+    let mut class_normalizer = args.input.class_normalizer()?;
+    for account in existing_records.iter_mut().filter(|a| !a.klasse.is_empty()) {
+        account.klasse = class_normalizer.normalize(&account.klasse);
+    }
+    let Prepared {
+        records,
+        excluded,
+        rejects,
+        passwords,
+    } = prepare(&mut args, formats_dir)?;
+    let total = records.len();
+    let Diff {
+        new,
+        known,
+        leavers,
+        class_changes,
+    } = diff(records, &excluded, &existing_records);
+    info!(
+        "{} von {} Schülern haben schon ein Konto in {}.",
+        known,
+        total,
+        existing.display()
+    );
+    // Passwörter wurden für alle erzeugt, zählen sollen nur die geschriebenen.
+    let passwords = passwords.map(|report| report.recount(&new));
+    check_rejects(&args, &rejects, new.len())?;
+    leavers_path = leavers_path.replace("{export_date}", &args.export_date);
+    updates_path = updates_path.replace("{export_date}", &args.export_date);
+    let written = write_outputs(&args, &new, &excluded, |staging, options| {
+        write_records_to_file(&leavers, staging.stage(&leavers_path), options)?;
+        write_records_to_file(&class_changes, staging.stage(&updates_path), options)?;
+        Ok(vec![leavers_path.clone(), updates_path.clone()])
+    })?;
+    if !leavers.is_empty() && !rejects.is_empty() {
+        warn!(
+            "Unter den {} Abgängern können Schüler aus übersprungenen Zeilen sein, bitte {} \
+             prüfen.",
+            leavers.len(),
+            args.output.rejects_path
+        );
+    }
+    if !args.output.quiet && !is_stdio(Path::new(&args.output.output_path)) {
+        println!();
+        println!(
+            "Abgleich mit {}: {} Schüler haben schon ein Konto, {} sind neu.",
+            existing.display(),
+            known,
+            new.len()
+        );
+        if !leavers.is_empty() {
+            println!(
+                "{} Konten fehlen im Export und können deaktiviert werden, siehe {}.",
+                leavers.len(),
+                leavers_path
+            );
+        }
+        if !class_changes.is_empty() {
+            println!(
+                "{} Schüler haben die Klasse gewechselt, siehe {}.",
+                class_changes.len(),
+                updates_path
+            );
+        }
+        print_summary(
+            &args,
+            &written,
+            new.len(),
+            rejects.len(),
+            passwords.as_ref(),
+        );
+    }
+    Ok(())
+}
+
+/// Durchläuft alle Schritte der Umwandlung bis auf das Schreiben und berichtet das Ergebnis.
+fn validate(mut args: Args, formats_dir: &Path) -> Result<(), IsitError> {
+    let Prepared {
+        records,
+        excluded,
+        rejects,
+        passwords,
+    } = prepare(&mut args, formats_dir)?;
+    let classes: HashSet<&str> = records.iter().map(|r| r.klasse.as_str()).collect();
+    println!(
+        "{} Konten in {} Klassen könnten geschrieben werden.",
+        records.len(),
+        classes.len()
+    );
+    if let Some(report) = &passwords {
+        print_passwords(report);
+        if args.passwords.password_schemes.is_some() {
+            let policy = match &args.passwords.verify_policy {
+                Some(path) => Some(PasswordPolicy::load(path)?),
+                None => None,
+            };
+            print_schemes_by_class(report, &records, policy.as_ref());
         }
-        FileType::Excel => {
-            records = get_all_xlsx_records_in_file(path, args.record_type);
+    }
+    if args.input.consent_column.is_some() {
+        println!("{} Schüler ohne Einwilligung.", excluded.len());
+    }
+    if !rejects.is_empty() {
+        println!("{} Zeilen würden übersprungen:", rejects.len());
+        for reject in &rejects {
+            println!("  Zeile {}: {}", reject.zeile, reject.grund);
+        }
+    }
+    if too_many_skipped(&args, rejects.len(), records.len()) {
+        return Err(IsitError::conversion(
+            "Zu viele übersprungene Zeilen, die Umwandlung würde abbrechen.".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+fn prepare(args: &mut Args, formats_dir: &Path) -> Result<Prepared, IsitError> {
+    let policy = match &args.passwords.verify_policy {
+        Some(path) => Some(PasswordPolicy::load(path)?),
+        None => None,
+    };
+    let inputs = input_paths(&args.input.file_path)?;
+    let mut rejects = Vec::new();
+    let mut r = Vec::new();
+    // Eingabedatei je Datensatz, für Meldungen beim Zusammenführen.
+    let mut sources = Vec::new();
+    for input in &inputs {
+        for mut record in read_records(args, input, formats_dir, &mut rejects)? {
+            let id = record.id();
+            record.set_id(RecordId {
+                nummer: r.len() + 1,
+                ..id
+            });
+            r.push(record);
+            sources.push(input.path.display().to_string());
         }
     }
+    let path = inputs
+        .first()
+        .map(|input| input.path.clone())
+        .unwrap_or_default();
     info!("Schreibe in Datei.");
-    match records {
-        Ok(r) => {
-            let records_iserv = &r.into_iter().map(|r| r.into()).collect();
-            match write_records_to_file(records_iserv, args.output_path) {
-                Ok(_) => (),
-                Err(e) => println!("{:?}", e),
+    let date = export_date(&path, &r);
+    info!("Exportdatum: {}", date);
+    args.expand_export_date(date);
+    if r.is_empty() {
+        let hint = match args.input.record_type {
+            Some(record_type) => format!(
+                " oder passt der Datensatztyp (--record-type {}) nicht zur Kopfzeile",
+                record_type.to_possible_value().unwrap().get_name()
+            ),
+            None => String::new(),
+        };
+        let message = format!(
+            "Die Eingabe enthält keine Datensätze. Ist die Datei leer{}?",
+            hint
+        );
+        if args.input.strict {
+            return Err(IsitError::input(&path, message));
+        }
+        warn!("{}", message);
+    }
+    let (r, excluded) = match &args.input.consent_column {
+        Some(column) => {
+            info!("Filtere nach Einwilligung in Spalte \"{}\".", column);
+            r.into_iter()
+                .partition(|r| r.has_consent(column, args.input.consent_value.as_deref()))
+        }
+        None => (r, Vec::new()),
+    };
+    // Datensatztyp je Datensatz, für --reconcile-guests.
+    let origins: HashMap<usize, RecordType> = r
+        .iter()
+        .filter_map(|record| {
+            let origin = match record {
+                Record::RecordSchild(_) => RecordType::Schild,
+                Record::RecordGastschueler(_) => RecordType::Gastschueler,
+                _ => return None,
             };
+            Some((record.id().nummer, origin))
+        })
+        .collect();
+    let mut class_normalizer = args.input.class_normalizer()?;
+    let include_middle_names = args.input.include_middle_names;
+    let mut to_iserv = |record: Record| {
+        let raw_klasse = record.klasse().to_owned();
+        let mut record = RecordIserv::from(record);
+        if class_normalizer.is_passthrough() {
+            record.klasse = raw_klasse;
+        }
+        record.klasse = class_normalizer.normalize(&record.klasse);
+        args.input.name_suffix_policy.apply(&mut record);
+        if let Some(zusatzname) = record.zusatzname.take().filter(|_| include_middle_names) {
+            record.vorname = format!("{} {}", record.vorname, zusatzname);
         }
-        Err(e) => println!("{:?}", e),
+        record
+    };
+    let mut records_iserv: Vec<RecordIserv> = r.into_iter().map(&mut to_iserv).collect();
+    let records_excluded: Vec<RecordExcluded> = excluded
+        .into_iter()
+        .map(|r| RecordExcluded::from(to_iserv(r)))
+        .collect();
+    class_normalizer.report();
+    warn_semicolons_in_names(&records_iserv);
+    check_class_count(&records_iserv, args.input.max_classes, args.input.strict)?;
+    validate_import_ids(
+        &mut records_iserv,
+        &args.input.id_regex,
+        args.input.id_max_length,
+        args.input.on_error,
+        &mut rejects,
+    )?;
+    if args.input.reconcile_guests {
+        reconcile_guests(&mut records_iserv, |id| origins.get(&id.nummer).copied());
     }
-    info!("Beende das Programm.");
+    merge_duplicate_ids(
+        &mut records_iserv,
+        |id| sources[id.nummer - 1].clone(),
+        &mut rejects,
+    );
+    check_emails(
+        &records_iserv,
+        args.input.email_domain.as_deref(),
+        args.input.require_email || args.input.strict,
+    )?;
+    let passwords = if args.passwords.no_passwords {
+        info!("Ohne Passwörter, IServ behält beim Import die bestehenden.");
+        let placeholder = args
+            .passwords
+            .password_placeholder
+            .clone()
+            .unwrap_or_default();
+        for record in records_iserv.iter_mut() {
+            record.password = placeholder.clone();
+        }
+        None
+    } else {
+        let report = generate_passwords(
+            &mut records_iserv,
+            &args.passwords.password_schemes(args.input.encoding)?,
+            policy.as_ref(),
+        )?;
+        if let Some(policy) = &policy {
+            apply_password_policy(&mut records_iserv, policy)?;
+        }
+        if let Some(bits) = args.passwords.warn_entropy_bits {
+            for usage in report
+                .schemes
+                .iter()
+                .filter(|usage| usage.entropy_bits < bits)
+            {
+                warn!(
+                    "Neue Passwörter{} haben nur {:.1} Bit Entropie ({}), weniger als die {:.1} \
+                     aus --warn-entropy-bits.",
+                    usage.key.for_whom(),
+                    usage.entropy_bits,
+                    usage.scheme,
+                    bits
+                );
+            }
+        }
+        Some(report)
+    };
+    if args.input.consent_column.is_some() {
+        warn!(
+            "{} Schüler ohne Einwilligung ausgelassen.",
+            records_excluded.len()
+        );
+    }
+    Ok(Prepared {
+        records: records_iserv,
+        excluded: records_excluded,
+        rejects,
+        passwords,
+    })
 }
 
-fn get_all_xlsx_records_in_file(
+/// Eine Eingabedatei, auf Wunsch mit eigenem Datensatztyp.
+struct Input {
     path: PathBuf,
-    record_type: RecordType,
-) -> Result<Vec<Record>, Box<dyn OtherError>> {
-    let mut records: Vec<Record> = Vec::new();
-    let mut workbook: Xlsx<_> = open_workbook(path)?;
-    info!("Excel-Datei geöffnet.");
-    let sheets = workbook.sheet_names().to_owned();
-    let range = workbook
-        .worksheet_range(&sheets[0])
-        .ok_or(Error::Msg("Cannot find 'Sheet1'"))??;
-    match record_type {
-        RecordType::Schild => {
-            let iter = RangeDeserializerBuilder::new().from_range(&range)?;
-            for row in iter {
-                let record = row?;
-                records.push(Record::RecordSchild(record));
+    record_type: Option<RecordType>,
+}
+
+/// Die Eingabedateien in der angegebenen Reihenfolge; Muster mit `*`, `?` oder `[` werden zu
+/// den passenden Dateien in alphabetischer Reihenfolge. Ein Präfix TYP= setzt den
+/// Datensatztyp, sofern TYP einer ist; sonst gehört es zum Pfad.
+fn input_paths(patterns: &[String]) -> Result<Vec<Input>, IsitError> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let (record_type, pattern) = match pattern.split_once('=') {
+            Some((prefix, rest)) => match RecordType::from_str(prefix, true) {
+                Ok(record_type) => (Some(record_type), rest),
+                Err(_) => (None, pattern.as_str()),
+            },
+            None => (None, pattern.as_str()),
+        };
+        if !pattern.contains(['*', '?', '[']) {
+            paths.push(Input {
+                path: PathBuf::from(pattern),
+                record_type,
+            });
+            continue;
+        }
+        let mut matches = glob::glob(pattern)
+            .map_err(|e| IsitError::Usage(format!("Ungültiges Muster \"{}\": {}", pattern, e)))?
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            return Err(IsitError::Usage(format!(
+                "Keine Datei passt zum Muster \"{}\".",
+                pattern
+            )));
+        }
+        matches.sort();
+        paths.extend(matches.into_iter().map(|path| Input { path, record_type }));
+    }
+    Ok(paths)
+}
+
+/// Liest die Datensätze einer Eingabedatei.
+fn read_records(
+    args: &Args,
+    input: &Input,
+    formats_dir: &Path,
+    rejects: &mut Vec<Reject>,
+) -> Result<Vec<Record>, IsitError> {
+    info!("Öffne {}.", input.path.display());
+    let path = input.path.clone();
+    let record_type = input.record_type.or(args.input.record_type);
+    let file_type = args
+        .input
+        .file_type
+        .resolve(&path, args.input.zip_member.as_deref());
+    match (&args.input.format, &args.input.mapping, file_type) {
+        (Some(format), _, file_type) => {
+            let format = find_format(formats_dir, format)?;
+            get_all_mapped_records_in_file(path, file_type, &format, &args.input.read_options())
+        }
+        (None, Some(mapping), file_type) if record_type == Some(RecordType::Custom) => {
+            let format = load_mapping(mapping)?;
+            get_all_mapped_records_in_file(path, file_type, &format, &args.input.read_options())
+        }
+        (None, _, FileType::Csv) => {
+            get_all_csv_records_in_file(path, record_type, &args.input.read_options(), rejects)
+        }
+        (None, _, file_type) => get_all_xlsx_records_in_file(
+            path,
+            file_type,
+            record_type,
+            &args.input.read_options(),
+            rejects,
+        ),
+    }
+}
+
+/// Neue Passwörter mit ihrer Entropie, bei mehreren Verfahren je Verfahren.
+fn print_passwords(report: &PasswordReport) {
+    match &report.schemes[..] {
+        [] => {}
+        [usage] => println!(
+            "{} neue Passwörter mit je {:.1} Bit Entropie ({}).",
+            usage.generated, usage.entropy_bits, usage.scheme
+        ),
+        schemes => {
+            println!("{} neue Passwörter:", report.generated());
+            for usage in schemes {
+                println!(
+                    "  {}: {} mit je {:.1} Bit Entropie ({}).",
+                    usage.key.label(),
+                    usage.generated,
+                    usage.entropy_bits,
+                    usage.scheme
+                );
             }
         }
-        RecordType::Gastschueler => {
-            let iter = RangeDeserializerBuilder::new().from_range(&range)?;
-            for row in iter {
-                let record = row?;
-                records.push(Record::RecordGastschueler(record));
+    }
+}
+
+/// Welches Verfahren für welche Klasse gilt, um die Datei aus --password-schemes vor dem
+/// Erzeugen der Zugangsdaten zu prüfen. Lehrkräfte stehen als eine Gruppe am Ende.
+fn print_schemes_by_class(
+    report: &PasswordReport,
+    records: &[RecordIserv],
+    policy: Option<&PasswordPolicy>,
+) {
+    let mut groups: BTreeMap<(Rolle, &str), &SchemeUsage> = BTreeMap::new();
+    for record in records {
+        if let Some(usage) = report.usage_for(record) {
+            let klasse = match record.rolle {
+                Rolle::Schueler => record.klasse.as_str(),
+                Rolle::Lehrer => "",
+            };
+            groups.insert((record.rolle, klasse), usage);
+        }
+    }
+    if groups.is_empty() {
+        return;
+    }
+    match policy {
+        Some(policy) => println!(
+            "Passwortverfahren je Klasse (Richtlinie: mindestens {:.1} Bit):",
+            policy.min_entropy_bits
+        ),
+        None => println!("Passwortverfahren je Klasse:"),
+    }
+    for ((rolle, klasse), usage) in groups {
+        let group = match rolle {
+            Rolle::Schueler => klasse,
+            Rolle::Lehrer => "Lehrer",
+        };
+        println!(
+            "  {}: {}, {:.1} Bit Entropie ({})",
+            group,
+            usage.scheme,
+            usage.entropy_bits,
+            usage.key.label()
+        );
+    }
+}
+
+/// Zeigt nach dem Lauf, was entstanden ist und was als Nächstes zu tun ist.
+fn print_summary(
+    args: &Args,
+    written: &[String],
+    accepted: usize,
+    skipped: usize,
+    passwords: Option<&PasswordReport>,
+) {
+    println!();
+    println!("Fertig: {} Konten geschrieben.", accepted);
+    if let Some(report) = &passwords {
+        print_passwords(report);
+    }
+    println!();
+    println!("Nächste Schritte:");
+    println!(
+        "  • {} in IServ unter Verwaltung → Benutzer → Import hochladen.",
+        args.output.output_path
+    );
+    if args.output.tutors.is_some() {
+        println!(
+            "  • Danach {} unter Verwaltung → Gruppen → Import hochladen.",
+            args.output.groups_path
+        );
+    }
+    if skipped > 0 {
+        println!(
+            "  • {} Zeilen wurden übersprungen, siehe {}.",
+            skipped, args.output.rejects_path
+        );
+    }
+    if args.input.consent_column.is_some() {
+        println!(
+            "  • Die Liste der Schüler ohne Einwilligung steht in {}.",
+            args.output.consent_excluded_path
+        );
+    }
+    match args.output.credentials_ttl {
+        Some(ttl) => {
+            let hours = ttl.as_secs() / (60 * 60);
+            let ttl = if hours % 24 == 0 {
+                format!("{} Tagen", hours / 24)
+            } else {
+                format!("{} Stunden", hours)
+            };
+            println!(
+                "  • Die Dateien enthalten Passwörter und werden nach {} von `isit cleanup` \
+                 gelöscht.",
+                ttl
+            )
+        }
+        None => println!("  • Die Dateien enthalten Passwörter, bitte nach dem Import löschen."),
+    }
+    if written.len() > 1 {
+        println!();
+        println!("Geschriebene Dateien:");
+        for path in written {
+            println!("  {}", path);
+        }
+    }
+}
+
+fn profile_file(path: PathBuf, file_type: FileType, encoding: Encoding) -> Result<(), IsitError> {
+    let options = ReadOptions {
+        encoding,
+        ..Default::default()
+    };
+    let Table { headers, rows } = read_table(path, file_type, &options)?;
+    println!("{} Zeilen, {} Spalten", rows.len(), headers.len());
+    for (column, header) in headers.iter().enumerate() {
+        let values: Vec<&str> = rows
+            .iter()
+            .map(|row| row.get(column).map(|v| v.trim()).unwrap_or_default())
+            .filter(|v| !v.is_empty())
+            .collect();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for value in &values {
+            *counts.entry(value).or_default() += 1;
+        }
+        let mut most_common: Vec<(&str, usize)> = counts.iter().map(|(v, c)| (*v, *c)).collect();
+        most_common.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let lengths = values.iter().map(|v| v.chars().count());
+        let fill_rate = if rows.is_empty() {
+            0.0
+        } else {
+            100.0 * values.len() as f64 / rows.len() as f64
+        };
+        println!();
+        println!("Spalte \"{}\"", header);
+        println!(
+            "  gefüllt:      {:.0} % ({} von {})",
+            fill_rate,
+            values.len(),
+            rows.len()
+        );
+        println!("  verschieden:  {}", counts.len());
+        if let (Some(min), Some(max)) = (lengths.clone().min(), lengths.max()) {
+            println!("  Länge:        {} – {}", min, max);
+        }
+        let top: Vec<String> = most_common
+            .iter()
+            .take(5)
+            .map(|(value, count)| format!("{} ({}×)", value, count))
+            .collect();
+        if !top.is_empty() {
+            println!("  häufigste:    {}", top.join(", "));
+        }
+    }
+    Ok(())
+}
+
+/// Führt alle eingebauten Prüfungen aus und gibt zurück, ob alle bestanden wurden.
+fn selftest() -> bool {
+    let checks = [
+        (
+            "SchILD-Export",
+            check_conversion(
+                SELFTEST_SCHILD,
+                SELFTEST_SCHILD_EXPECTED,
+                RecordType::Schild,
+            ),
+        ),
+        (
+            "Gastschülerliste",
+            check_conversion(
+                SELFTEST_GASTSCHUELER,
+                SELFTEST_GASTSCHUELER_EXPECTED,
+                RecordType::Gastschueler,
+            ),
+        ),
+        (
+            "LOGINEO-Liste",
+            check_conversion(
+                SELFTEST_LOGINEO,
+                SELFTEST_LOGINEO_EXPECTED,
+                RecordType::Logineo,
+            ),
+        ),
+        ("Windows-1252", check_windows_encoding()),
+        ("Schreiben und Lesen", check_round_trip()),
+    ];
+    let mut passed = true;
+    for (name, result) in &checks {
+        match result {
+            Ok(_) => println!("ok      {}", name),
+            Err(e) => {
+                passed = false;
+                println!("FEHLER  {}: {}", name, e);
             }
         }
     }
+    passed
+}
 
+fn selftest_records(input: &str, record_type: RecordType) -> Result<Vec<RecordIserv>, String> {
+    let records = parse_csv_records(
+        input,
+        Path::new("selftest"),
+        Some(record_type),
+        &ReadOptions::default(),
+        &mut Vec::new(),
+    )
+    .map_err(|e| e.to_string())?;
+    let mut class_normalizer = ClassNormalizer::new(ClassStyle::Compact, true);
+    let mut records: Vec<RecordIserv> = records
+        .into_iter()
+        .map(|record| {
+            let mut record = RecordIserv::from(record);
+            record.klasse = class_normalizer.normalize(&record.klasse);
+            record
+        })
+        .collect();
+    generate_passwords(&mut records, &PasswordOptions::default().into(), None)
+        .map_err(|e| e.to_string())?;
     Ok(records)
 }
 
-fn get_all_csv_records_in_file(
-    path: PathBuf,
-    record_type: RecordType,
+fn check_conversion(input: &str, expected: &str, record_type: RecordType) -> Result<(), String> {
+    let records = selftest_records(input, record_type)?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .from_reader(expected.as_bytes());
+    let expected: Vec<csv::StringRecord> = rdr
+        .records()
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    if records.len() != expected.len() {
+        return Err(format!(
+            "{} Datensätze erwartet, {} erhalten",
+            expected.len(),
+            records.len()
+        ));
+    }
+    for (row, (record, expected)) in records.iter().zip(&expected).enumerate() {
+        let actual = [
+            record.nachname.as_str(),
+            &record.vorname,
+            &record.klasse,
+            &record.import_id,
+        ];
+        if actual.iter().copied().ne(expected.iter()) {
+            return Err(format!(
+                "Zeile {}: erwartet {:?}, erhalten {:?}",
+                row + 2,
+                expected.iter().collect::<Vec<_>>(),
+                actual
+            ));
+        }
+        if !is_wordlist_password(&record.password) {
+            return Err(format!(
+                "Zeile {}: Passwort \"{}\" besteht nicht aus zwei Wörtern der Wortliste",
+                row + 2,
+                record.password
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn is_wordlist_password(password: &str) -> bool {
+    let words: Vec<&str> = WORDLIST.lines().collect();
+    password.match_indices('-').any(|(split, _)| {
+        words.contains(&&password[..split]) && words.contains(&&password[split + 1..])
+    })
+}
+
+fn check_windows_encoding() -> Result<(), String> {
+    let (bytes, _, _) = WINDOWS_1252.encode(SELFTEST_SCHILD);
+    let decoded = decode(&bytes, WINDOWS_1252).map_err(|e| e.to_string())?;
+    if decoded != SELFTEST_SCHILD {
+        return Err("Umlaute wurden beim Dekodieren verändert".to_owned());
+    }
+    Ok(())
+}
+
+fn check_round_trip() -> Result<(), String> {
+    let records = selftest_records(SELFTEST_SCHILD, RecordType::Schild)?;
+    let path = std::env::temp_dir().join(format!("isit-selftest-{}.csv", std::process::id()));
+    let path = path.to_string_lossy().into_owned();
+    let result = write_and_read_back(&records, &path);
+    let _ = fs::remove_file(&path);
+    result
+}
+
+fn write_and_read_back(records: &[RecordIserv], path: &str) -> Result<(), String> {
+    let output = OutputSpec {
+        format: OutputFormat::Csv,
+        path: path.to_owned(),
+    };
+    write_iserv_records(records, &[output], &OutputOptions::default())
+        .map_err(|e| e.to_string())?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .from_path(path)
+        .map_err(|e| e.to_string())?;
+    let rows: Vec<csv::StringRecord> = rdr
+        .records()
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    if rows.len() != records.len() {
+        return Err(format!(
+            "{} von {} Zeilen gelesen",
+            rows.len(),
+            records.len()
+        ));
+    }
+    for (record, row) in records.iter().zip(&rows) {
+        let expected = [
+            record.nachname.as_str(),
+            &record.vorname,
+            &record.klasse,
+            &record.import_id,
+            &record.password,
+        ];
+        if expected.iter().copied().ne(row.iter()) {
+            return Err(format!("{:?} wurde als {:?} gelesen", expected, row));
+        }
+    }
+    Ok(())
+}
+
+/// Prüft wie [`audit_file`] und endet mit Exit-Code 1, wenn Passwörter erneuert werden sollten.
+fn run_audit(audit: AuditArgs, line_ending: LineEnding) {
+    match audit_file(
+        &audit.file,
+        audit.policy.as_deref(),
+        audit.output,
+        line_ending,
+    ) {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => fail(e),
+    }
+}
+
+/// Schreibt die Konten mit schwachen Passwörtern nach `output` und gibt zurück, ob alle
+/// Passwörter in Ordnung waren.
+fn audit_file(
+    path: &Path,
+    policy: Option<&Path>,
+    output: String,
+    line_ending: LineEnding,
+) -> Result<bool, IsitError> {
+    let policy = match policy {
+        Some(policy) => PasswordPolicy::load(policy)?,
+        None => PasswordPolicy::default(),
+    };
+    let records = read_credentials(path)?;
+    let rotations = audit_passwords(&records, &policy);
+    if rotations.is_empty() {
+        info!("Alle {} Passwörter sind in Ordnung.", records.len());
+        return Ok(true);
+    }
+    let options = OutputOptions {
+        line_ending,
+        ..OutputOptions::default()
+    };
+    write_records_to_file(&rotations, output.clone(), &options)?;
+    warn!(
+        "{} von {} Passwörtern sollten erneuert werden, siehe {}.",
+        rotations.len(),
+        records.len(),
+        output
+    );
+    Ok(false)
+}
+
+/// Liest eine bereits ausgegebene IServ-Datei mit Passwörtern.
+fn read_credentials(path: &Path) -> Result<Vec<RecordIserv>, IsitError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .comment(Some(b'#'))
+        .from_path(path)
+        .map_err(IsitError::csv(path))?;
+    rdr.deserialize()
+        .collect::<Result<Vec<RecordIserv>, _>>()
+        .map_err(IsitError::csv(path))
+}
+
+/// Erneuert die Passwörter der Konten, deren Import-ID in `accounts` steht, und schreibt nur
+/// diese Konten nach `output`. Lehrkräfte sind in einer IServ-Datei nicht zu erkennen, für alle
+/// gilt also das Verfahren für Schüler.
+fn regen_passwords(
+    path: &Path,
+    accounts: &Path,
+    output: String,
+    options: &OutputOptions,
+    passwords: &PasswordArgs,
     encoding: Encoding,
-) -> Result<Vec<Record>, Box<dyn OtherError>> {
-    let file = File::open(path).unwrap();
-    info!("CSV-Datei geöffnet.");
-    let mut records: Vec<Record> = Vec::new();
-    info!("Checke Encoding.");
-    let win_reader = match encoding {
-        Encoding::Utf8 => DecodeReaderBytesBuilder::new()
-            .encoding(Some(UTF_8))
-            .build(file),
-        Encoding::Windows => DecodeReaderBytesBuilder::new()
-            .encoding(Some(WINDOWS_1252))
-            .build(file),
+) -> Result<(), IsitError> {
+    if passwords.seed.is_some() {
+        return Err(IsitError::Usage(
+            "Mit --seed bekäme jedes Konto wieder sein altes Passwort.".to_owned(),
+        ));
+    }
+    let schemes = passwords.password_schemes(encoding)?;
+    let policy = match &passwords.verify_policy {
+        Some(path) => Some(PasswordPolicy::load(path)?),
+        None => None,
     };
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .comment(Some(b'#'))
+        .from_path(accounts)
+        .map_err(IsitError::csv(accounts))?;
+    let column = rdr
+        .headers()
+        .map_err(IsitError::csv(accounts))?
+        .iter()
+        .position(|header| header == "Import-ID")
+        .ok_or_else(|| IsitError::input(accounts, "Spalte \"Import-ID\" fehlt.".to_owned()))?;
+    let mut ids = HashSet::new();
+    for row in rdr.records() {
+        let row = row.map_err(IsitError::csv(accounts))?;
+        ids.insert(row.get(column).unwrap_or_default().trim().to_owned());
+    }
+    let mut records: Vec<RecordIserv> = read_credentials(path)?
+        .into_iter()
+        .filter(|record| ids.contains(&record.import_id))
+        .collect();
+    if records.len() < ids.len() {
+        warn!(
+            "{} Import-IDs aus {} fehlen in {}.",
+            ids.len() - records.len(),
+            accounts.display(),
+            path.display()
+        );
+    }
+    for record in records.iter_mut() {
+        record.password.clear();
+    }
+    let report = generate_passwords(&mut records, &schemes, policy.as_ref())?;
+    write_records_to_file(&records, output.clone(), options)?;
+    print_passwords(&report);
+    println!(
+        "Die erneuerten Konten stehen in {}; in IServ importiert ersetzen sie die alten \
+         Passwörter.",
+        output
+    );
+    Ok(())
+}
+
+/// Zeigt je Verfahren ein paar Passwörter, wie ein Lauf mit diesen Optionen sie erzeugen würde.
+fn print_sample_passwords(
+    passwords: &PasswordArgs,
+    encoding: Encoding,
+    count: usize,
+) -> Result<(), IsitError> {
+    let schemes = passwords.password_schemes(encoding)?;
+    let policy = match &passwords.verify_policy {
+        Some(path) => Some(PasswordPolicy::load(path)?),
+        None => None,
+    };
+    let samples = sample_passwords(&schemes, policy.as_ref(), count)?;
+    let several = samples.len() > 1;
+    for (usage, samples) in samples {
+        if several {
+            print!("{}: ", usage.key.label());
+        }
+        println!("{}, {:.1} Bit Entropie", usage.scheme, usage.entropy_bits);
+        for password in samples {
+            println!("  {}", password);
+        }
+    }
+    Ok(())
+}
 
+fn show_iserv_file(path: String, mask: PasswordMask) -> Result<(), IsitError> {
     let mut rdr = csv::ReaderBuilder::new()
         .delimiter(b';')
-        .from_reader(win_reader);
-    match record_type {
-        RecordType::Schild => {
-            for result in rdr.deserialize() {
-                let record: RecordSchild = result?;
-                records.push(Record::RecordSchild(record));
-            }
+        .comment(Some(b'#'))
+        .from_path(&path)
+        .map_err(IsitError::csv(Path::new(&path)))?;
+    let headers: Vec<String> = rdr
+        .headers()
+        .map_err(IsitError::csv(Path::new(&path)))?
+        .iter()
+        .map(|h| h.to_owned())
+        .collect();
+    let password_column = headers
+        .iter()
+        .position(|h| h.eq_ignore_ascii_case("password") || h.eq_ignore_ascii_case("passwort"));
+    let mut rows = Vec::new();
+    for result in rdr.records() {
+        let row = result.map_err(IsitError::csv(Path::new(&path)))?;
+        let mut row: Vec<String> = row.iter().map(|f| f.to_owned()).collect();
+        if let Some(password) = password_column.and_then(|column| row.get_mut(column)) {
+            *password = mask.apply(password);
         }
-        RecordType::Gastschueler => {
-            for result in rdr.deserialize() {
-                let record: RecordGastschueler = result?;
-                records.push(Record::RecordGastschueler(record));
-            }
+        rows.push(row);
+    }
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
         }
+    }
+    let print_row = |row: &[String]| {
+        let fields: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(field, width)| format!("{:<width$}", field, width = width))
+            .collect();
+        println!("{}", fields.join(" | ").trim_end());
     };
-
-    Ok(records)
+    print_row(&headers);
+    let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    println!("{}", separator.join("-+-"));
+    for row in &rows {
+        print_row(row);
+    }
+    Ok(())
 }
 
-fn write_records_to_file(
-    records: &Vec<RecordIserv>,
-    path: String,
-) -> Result<(), Box<dyn OtherError>> {
-    let mut wtr = csv::WriterBuilder::new().delimiter(b';').from_path(path)?;
-    for record in records {
-        wtr.serialize(record)?;
+/// Schreibt die abgelehnten Zeilen und bricht ab, wenn mehr übersprungen wurde, als
+/// --max-skipped oder --max-skipped-percent erlauben.
+fn check_rejects(args: &Args, rejects: &[Reject], accepted: usize) -> Result<(), IsitError> {
+    if rejects.is_empty() {
+        return Ok(());
+    }
+    let mut rejects_sorted: Vec<&Reject> = rejects.iter().collect();
+    rejects_sorted.sort_by_key(|reject| reject.zeile);
+    write_records_to_file(
+        &rejects_sorted,
+        args.output.rejects_path.clone(),
+        &args.output.csv_options(),
+    )?;
+    warn!(
+        "{} Zeilen ({:.1} %) wurden übersprungen, siehe {}.",
+        rejects.len(),
+        skipped_percent(rejects.len(), accepted),
+        args.output.rejects_path
+    );
+    if too_many_skipped(args, rejects.len(), accepted) {
+        return Err(IsitError::conversion(format!(
+            "Zu viele übersprungene Zeilen, es wurde keine Datei geschrieben. Bitte {} prüfen.",
+            args.output.rejects_path
+        )));
     }
-    wtr.flush()?;
     Ok(())
 }
+
+fn skipped_percent(skipped: usize, accepted: usize) -> f64 {
+    100.0 * skipped as f64 / (skipped + accepted).max(1) as f64
+}
+
+/// Ob mehr Zeilen übersprungen wurden, als --max-skipped oder --max-skipped-percent erlauben.
+fn too_many_skipped(args: &Args, skipped: usize, accepted: usize) -> bool {
+    args.input.max_skipped.is_some_and(|max| skipped > max)
+        || args
+            .input
+            .max_skipped_percent
+            .is_some_and(|max| skipped_percent(skipped, accepted) > max)
+}
+
+/// Schreibt alle Dateien eines Laufs. Sie werden erst an ihren Platz verschoben,
+/// wenn alle fehlerfrei geschrieben wurden. `extra` schreibt weitere Dateien eines
+/// Unterbefehls in dieselbe Zwischenablage und gibt deren Pfade zurück. Gibt die geschriebenen
+/// Pfade zurück.
+fn write_outputs(
+    args: &Args,
+    records: &[RecordIserv],
+    excluded: &Vec<RecordExcluded>,
+    extra: impl FnOnce(&mut Staging, &OutputOptions) -> Result<Vec<String>, IsitError>,
+) -> Result<Vec<String>, IsitError> {
+    let options_hash = args.options_hash();
+    let output_options = args.output_options(&options_hash)?;
+    let mut staging = Staging::new(&args.output.output_path, args.output.secure_delete)
+        .map_err(IsitError::io(Path::new(&args.output.output_path)))?;
+    let mut outputs = vec![OutputSpec {
+        format: args.output.output_format,
+        path: args.output.output_path.clone(),
+    }];
+    outputs.extend(args.output.outputs.iter().cloned());
+    let staged: Vec<OutputSpec> = outputs
+        .iter()
+        .map(|output| OutputSpec {
+            format: output.format,
+            path: staging.stage(&output.path),
+        })
+        .collect();
+    write_iserv_records(records, &staged, &output_options)?;
+    let mut written: Vec<String> = outputs.into_iter().map(|output| output.path).collect();
+    if let Some(dir) = &args.output.split_by_class {
+        fs::create_dir_all(dir).map_err(IsitError::io(Path::new(dir)))?;
+        for (klasse, class_records) in group_by_class(records) {
+            let path = class_file_path(dir, &args.output.class_file_template, klasse)?;
+            let output = OutputSpec {
+                format: args.output.output_format,
+                path: staging.stage(&path),
+            };
+            write_iserv_records(&class_records, &[output], &output_options)?;
+            written.push(path);
+        }
+    }
+    if args.output.split_by_role {
+        if is_stdio(Path::new(&args.output.output_path)) {
+            return Err(IsitError::Usage(
+                "--split-by-role braucht eine Ausgabedatei statt der Standardausgabe.".to_owned(),
+            ));
+        }
+        let roles = group_by_role(records);
+        if roles.len() < 2 {
+            warn!("--split-by-role: Die Eingabe enthält nur eine Rolle.");
+        }
+        for (rolle, role_records) in roles {
+            let path = role_file_path(&args.output.output_path, rolle);
+            let output = OutputSpec {
+                format: args.output.output_format,
+                path: staging.stage(&path),
+            };
+            write_iserv_records(&role_records, &[output], &output_options)?;
+            written.push(path);
+        }
+    }
+    if args.input.consent_column.is_some() {
+        let path = staging.stage(&args.output.consent_excluded_path);
+        write_records_to_file(excluded, path, &output_options)?;
+        written.push(args.output.consent_excluded_path.clone());
+    }
+    if let Some(append_to) = &args.output.append_to {
+        let combined = append_to_existing(append_to, records)?;
+        let output = OutputSpec {
+            format: OutputFormat::Csv,
+            path: staging.stage(append_to),
+        };
+        write_iserv_records(&combined, &[output], &output_options)?;
+        written.push(append_to.clone());
+    }
+    if let Some(tutors) = &args.output.tutors {
+        let groups = class_groups(
+            records,
+            read_tutors(tutors, args.input.encoding, args.input.class_normalizer()?)?,
+        );
+        let path = staging.stage(&args.output.groups_path);
+        write_records_to_file(&groups, path, &output_options)?;
+        written.push(args.output.groups_path.clone());
+    }
+    written.extend(extra(&mut staging, &output_options)?);
+    let mut targets = HashSet::new();
+    for path in &written {
+        let target = path::absolute(path).map_err(IsitError::io(Path::new(path)))?;
+        if !targets.insert(target) {
+            return Err(IsitError::Usage(format!(
+                "Mehrere Ausgaben würden nach {} geschrieben, bitte Pfade oder \
+                 --class-file-template anpassen.",
+                path
+            )));
+        }
+    }
+    if let Some(ttl) = args.output.credentials_ttl {
+        if written.iter().any(|path| is_stdio(Path::new(path))) {
+            return Err(IsitError::Usage(
+                "--credentials-ttl geht nicht zusammen mit der Ausgabe auf die Standardausgabe."
+                    .to_owned(),
+            ));
+        }
+        let path = staging.stage(&format!(
+            "{}.{}",
+            args.output.output_path, MANIFEST_EXTENSION
+        ));
+        write_manifest(
+            &written,
+            ttl,
+            &options_hash,
+            &args.settings(),
+            &args.export_date,
+            path,
+        )?;
+    }
+    staging.commit()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(arguments: &[&str]) -> Args {
+        let mut args = Cli::try_parse_from([&["isit"], arguments].concat())
+            .unwrap()
+            .args;
+        args.expand_export_date("2024-08-01".to_owned());
+        args
+    }
+
+    #[test]
+    fn paths_and_export_date_do_not_change_the_options_hash() {
+        let a = args(&["-f", "a.csv", "-o", "import_{export_date}.csv"]);
+        let mut b = args(&["-f", "exports/b.xlsx", "-o", "neu.csv", "--stamp"]);
+        b.expand_export_date("2025-02-01".to_owned());
+        assert_eq!(a.settings(), b.settings());
+        assert_eq!(a.options_hash(), b.options_hash());
+    }
+
+    #[test]
+    fn settings_name_the_options_but_not_the_seed() {
+        let args = args(&[
+            "-f",
+            "a.csv",
+            "--password-words",
+            "3",
+            "--password-separator",
+            "",
+            "--seed",
+            "4711",
+            "--keep-semesters",
+        ]);
+        let settings = args.settings();
+        assert!(settings.contains("--class-style compact --keep-semesters "));
+        assert!(settings.contains("--password-words 3 "));
+        assert!(settings.contains("--password-separator \"\" "));
+        assert!(settings.contains("--seed … "));
+        assert!(!settings.contains("4711"));
+        assert_ne!(
+            args.options_hash(),
+            self::args(&["-f", "a.csv"]).options_hash()
+        );
+    }
+    #[test]
+    fn username_umlauts_can_be_set_in_the_config_file() {
+        let path = std::env::temp_dir().join(format!("isit-{}-umlauts.toml", std::process::id()));
+        fs::write(&path, "username_umlauts = \"strip\"\n").unwrap();
+        let config = config_args(&[OsString::from("--config"), path.clone().into()]).unwrap();
+        fs::remove_file(&path).unwrap();
+        let config: Vec<_> = config.iter().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(config, ["--username-umlauts=strip"]);
+        let args = args(&[&["-f", "a.csv"], &config[..]].concat());
+        assert_eq!(args.usernames.username_umlauts, UmlautPolicy::Strip);
+        assert!(args.settings().contains("--username-umlauts strip"));
+    }
+
+    #[test]
+    fn validate_takes_no_output_options() {
+        let parse = |arguments: &[&str]| Cli::try_parse_from([&["isit"], arguments].concat());
+        assert!(parse(&["validate", "-f", "a.csv", "--password-words", "3"]).is_ok());
+        assert!(parse(&["validate", "-f", "a.csv", "-o", "x.csv"]).is_err());
+        let cli = parse(&["passwords", "generate", "-n", "2", "--password-words", "3"]).unwrap();
+        match cli.command {
+            Some(Command::Passwords {
+                command:
+                    PasswordsCommand::Generate {
+                        count, passwords, ..
+                    },
+            }) => assert_eq!((count, passwords.password_words), (2, 3)),
+            command => panic!("{:?}", command),
+        }
+    }
+
+    #[test]
+    fn config_options_apply_only_to_subcommands_that_know_them() {
+        let path = std::env::temp_dir().join(format!("isit-{}-shared.toml", std::process::id()));
+        fs::write(&path, "output_path = \"x.csv\"\npassword_words = 3\n").unwrap();
+        let config = |subcommand: &str| {
+            let cli = [
+                subcommand.into(),
+                "--config".into(),
+                path.clone().into_os_string(),
+            ];
+            config_args(&cli).unwrap()
+        };
+        assert_eq!(
+            config("convert"),
+            ["--output-path=x.csv", "--password-words=3"]
+        );
+        assert_eq!(config("validate"), ["--password-words=3"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn regen_renews_only_the_listed_accounts() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let creds = dir.join(format!("isit-{}-creds.csv", id));
+        let accounts = dir.join(format!("isit-{}-erneuern.csv", id));
+        let output = dir.join(format!("isit-{}-erneuert.csv", id));
+        fs::write(
+            &creds,
+            "Nachname;Vorname;Klasse;Import-ID;Password\n\
+             Muster;Max;5a;1;alt-eins\n\
+             Muster;Erika;6b;2;alt-zwei\n",
+        )
+        .unwrap();
+        fs::write(
+            &accounts,
+            "Nachname;Vorname;Klasse;Import-ID;Grund\nMuster;Erika;6b;2;kurz\n",
+        )
+        .unwrap();
+        let passwords = match Cli::try_parse_from([
+            "isit",
+            "passwords",
+            "regen",
+            "creds.csv",
+            "--accounts",
+            "erneuern.csv",
+        ])
+        .unwrap()
+        .command
+        {
+            Some(Command::Passwords {
+                command: PasswordsCommand::Regen { passwords, .. },
+            }) => passwords,
+            command => panic!("{:?}", command),
+        };
+        regen_passwords(
+            &creds,
+            &accounts,
+            output.to_string_lossy().into_owned(),
+            &OutputOptions::default(),
+            &passwords,
+            Encoding::Auto,
+        )
+        .unwrap();
+        let renewed = read_credentials(&output).unwrap();
+        assert_eq!(renewed.len(), 1);
+        assert_eq!(renewed[0].import_id, "2");
+        assert!(!renewed[0].password.is_empty());
+        assert_ne!(renewed[0].password, "alt-zwei");
+        for path in [creds, accounts, output] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
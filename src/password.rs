@@ -0,0 +1,963 @@
+//! Erzeugen neuer Passwörter, Passwortrichtlinie und Maskierung von Passwörtern in der Anzeige.
+
+use clap::ValueEnum;
+use log::{debug, error, info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::error::IsitError;
+use crate::read::*;
+use crate::records::*;
+
+/// Art der neuen Passwörter.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PasswordStyle {
+    /// Wörter aus der Wortliste, durch das Trennzeichen verbunden.
+    #[default]
+    Words,
+    /// Nur Ziffern, z.B. für Tablets in der Grundschule.
+    Pin,
+}
+
+/// Schreibweise der Wortanfänge in neuen Passwörtern.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capitalize {
+    /// Wie in der Wortliste, in der deutschen also Substantive groß.
+    #[default]
+    Keep,
+    /// Jedes Wort mit großem Anfangsbuchstaben, z.B. "Wort.Wort".
+    Always,
+    /// Alle Wörter klein.
+    Never,
+    /// Jedes Wort zufällig groß oder klein, ein Bit Entropie mehr je Wort.
+    Random,
+}
+
+impl Capitalize {
+    fn apply<R: Rng>(&self, word: &str, rng: &mut R) -> String {
+        let upper = match self {
+            Capitalize::Keep => return word.to_owned(),
+            Capitalize::Always => true,
+            Capitalize::Never => false,
+            Capitalize::Random => rng.gen(),
+        };
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) if upper => first.to_uppercase().chain(chars).collect(),
+            Some(first) => first.to_lowercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    }
+}
+
+/// Wie neue Passwörter gebildet werden: Wörter aus der Wortliste, durch `separator` verbunden,
+/// auf Wunsch mit angehängten Ziffern und einem Sonderzeichen für strengere Richtlinien, oder
+/// eine PIN aus `pin_length` Ziffern.
+#[derive(Clone, Debug)]
+pub struct PasswordOptions {
+    pub style: PasswordStyle,
+    pub words: usize,
+    pub wordlist: Vec<String>,
+    pub separator: String,
+    pub capitalize: Capitalize,
+    pub digits: usize,
+    pub symbol: bool,
+    pub pin_length: usize,
+    /// Startwert, aus dem zusammen mit der Import-ID jedes Passwort folgt; ohne ihn zufällig.
+    pub seed: Option<u64>,
+}
+
+impl Default for PasswordOptions {
+    fn default() -> Self {
+        PasswordOptions {
+            style: PasswordStyle::Words,
+            words: 2,
+            wordlist: WordlistPreset::Deutsch.words(),
+            separator: "-".to_owned(),
+            capitalize: Capitalize::Keep,
+            digits: 0,
+            symbol: false,
+            pin_length: 6,
+            seed: None,
+        }
+    }
+}
+
+/// Eingebaute Wortlisten für --wordlist-preset.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WordlistPreset {
+    /// Deutsche Alltagswörter (575), die bisherige Liste.
+    #[default]
+    Deutsch,
+    /// Die kurzen Wörter daraus ohne Umlaute und ß (399), z.B. für Willkommensklassen und
+    /// Tastaturen ohne Umlaute.
+    DeutschEinfach,
+    /// Kurze englische Wörter (1295), die kurze Liste der EFF.
+    Englisch,
+    /// Englische Wörter (7772), die große Liste der EFF, für Lehrer- und Verwaltungskonten.
+    EnglischLang,
+}
+
+impl WordlistPreset {
+    pub fn text(&self) -> &'static str {
+        match self {
+            WordlistPreset::Deutsch => WORDLIST,
+            WordlistPreset::DeutschEinfach => include_str!("../res/words_einfach.txt"),
+            WordlistPreset::Englisch => include_str!("../res/words_englisch.txt"),
+            WordlistPreset::EnglischLang => include_str!("../res/words_englisch_lang.txt"),
+        }
+    }
+
+    pub fn words(&self) -> Vec<String> {
+        self.text().lines().map(str::to_owned).collect()
+    }
+}
+
+/// So viele verschiedene Wörter braucht eine eigene Wortliste mindestens, sonst wären zwei
+/// Wörter zu leicht zu erraten.
+pub const MIN_WORDLIST_LEN: usize = 200;
+
+/// Liest eine eigene Wortliste, ein Wort je Zeile. Leere Zeilen und Zeilen mit `#` am Anfang
+/// zählen nicht, doppelte Wörter nur einmal.
+pub fn load_wordlist(path: &Path, encoding: Encoding) -> Result<Vec<String>, IsitError> {
+    let options = ReadOptions {
+        encoding,
+        ..ReadOptions::default()
+    };
+    let text = read_decoded(path.to_path_buf(), &options)?;
+    let mut seen = HashSet::new();
+    let mut words = Vec::new();
+    let mut duplicates = 0;
+    for (index, line) in text.lines().enumerate() {
+        let word = line.trim();
+        if word.is_empty() || word.starts_with('#') {
+            continue;
+        }
+        if word.contains(|c: char| c == '-' || c.is_whitespace()) {
+            return Err(IsitError::config(path)(format!(
+                "Zeile {}: \"{}\" enthält Bindestrich oder Leerzeichen, das trennt im Passwort \
+                 die Wörter.",
+                index + 1,
+                word
+            )));
+        }
+        if seen.insert(word.to_owned()) {
+            words.push(word.to_owned());
+        } else {
+            duplicates += 1;
+        }
+    }
+    if duplicates > 0 {
+        warn!(
+            "{}: {} doppelte Wörter werden nur einmal verwendet.",
+            path.display(),
+            duplicates
+        );
+    }
+    if words.len() < MIN_WORDLIST_LEN {
+        return Err(IsitError::config(path)(format!(
+            "Nur {} verschiedene Wörter, mindestens {} sind nötig.",
+            words.len(),
+            MIN_WORDLIST_LEN
+        )));
+    }
+    info!("{} Wörter aus {} gelesen.", words.len(), path.display());
+    Ok(words)
+}
+
+/// Liest eine Sperrliste, ein Eintrag je Zeile wie bei der Wortliste, in Kleinschreibung.
+pub fn load_blocklist(path: &Path, encoding: Encoding) -> Result<Vec<String>, IsitError> {
+    let options = ReadOptions {
+        encoding,
+        ..ReadOptions::default()
+    };
+    let text = read_decoded(path.to_path_buf(), &options)?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+/// Entfernt Wörter mit weniger als `min_length` Zeichen und Wörter, die einen Eintrag der
+/// Sperrliste enthalten, ohne Rücksicht auf Groß- und Kleinschreibung. Danach müssen noch
+/// [`MIN_WORDLIST_LEN`] Wörter übrig sein.
+pub fn filter_wordlist(
+    words: Vec<String>,
+    min_length: usize,
+    blocklist: &[String],
+) -> Result<Vec<String>, IsitError> {
+    let before = words.len();
+    let words: Vec<String> = words
+        .into_iter()
+        .filter(|word| word.chars().count() >= min_length)
+        .filter(|word| {
+            let lower = word.to_lowercase();
+            !blocklist.iter().any(|entry| lower.contains(entry.as_str()))
+        })
+        .collect();
+    if words.len() < before {
+        info!(
+            "{} von {} Wörtern aus der Wortliste herausgefiltert.",
+            before - words.len(),
+            before
+        );
+    }
+    if words.len() < MIN_WORDLIST_LEN {
+        return Err(IsitError::Usage(format!(
+            "Nach dem Filtern bleiben nur {} Wörter, mindestens {} sind nötig.",
+            words.len(),
+            MIN_WORDLIST_LEN
+        )));
+    }
+    Ok(words)
+}
+
+impl PasswordOptions {
+    /// Entropie eines neuen Passworts in Bit: Wörter gleichverteilt aus der Wortliste, dazu die
+    /// angehängten Ziffern und das Sonderzeichen.
+    pub fn entropy_bits(&self) -> f64 {
+        if self.style == PasswordStyle::Pin {
+            return self.pin_length as f64 * 10f64.log2();
+        }
+        let mut bits = self.words as f64 * (self.wordlist.len() as f64).log2();
+        if self.capitalize == Capitalize::Random {
+            bits += self.words as f64;
+        }
+        bits += self.digits as f64 * 10f64.log2();
+        if self.symbol {
+            bits += (POLICY_SYMBOLS.len() as f64).log2();
+        }
+        bits
+    }
+
+    pub fn generate<R: Rng>(&self, rng: &mut R) -> String {
+        match self.style {
+            PasswordStyle::Words => {
+                let words: Vec<String> = (0..self.words)
+                    .map(|_| {
+                        let word = &self.wordlist[rng.gen_range(0..self.wordlist.len())];
+                        self.capitalize.apply(word, rng)
+                    })
+                    .collect();
+                words.join(&self.separator) + &self.suffix(rng)
+            }
+            PasswordStyle::Pin => random_digits(self.pin_length, rng),
+        }
+    }
+
+    /// Aufbau der neuen Passwörter in Worten, für den Bericht.
+    pub fn describe(&self) -> String {
+        if self.style == PasswordStyle::Pin {
+            return format!("PIN aus {} Ziffern", self.pin_length);
+        }
+        let mut parts = vec![format!(
+            "{} Wörter aus einer Liste von {}",
+            self.words,
+            self.wordlist.len()
+        )];
+        if self.separator != "-" {
+            parts.push(format!("Trennzeichen \"{}\"", self.separator));
+        }
+        match self.capitalize {
+            Capitalize::Keep => {}
+            Capitalize::Always => parts.push("Wörter groß".to_owned()),
+            Capitalize::Never => parts.push("Wörter klein".to_owned()),
+            Capitalize::Random => parts.push("Wörter zufällig groß oder klein".to_owned()),
+        }
+        if self.digits > 0 {
+            parts.push(format!("{} Ziffern", self.digits));
+        }
+        if self.symbol {
+            parts.push("ein Sonderzeichen".to_owned());
+        }
+        parts.join(", ")
+    }
+
+    /// Ziffern und Sonderzeichen, die an die Wörter angehängt werden.
+    pub fn suffix<R: Rng>(&self, rng: &mut R) -> String {
+        let mut suffix = random_digits(self.digits, rng);
+        if self.symbol {
+            suffix.push(char::from(
+                POLICY_SYMBOLS[rng.gen_range(0..POLICY_SYMBOLS.len())],
+            ));
+        }
+        suffix
+    }
+}
+
+fn random_digits<R: Rng>(count: usize, rng: &mut R) -> String {
+    (0..count)
+        .map(|_| char::from(rng.gen_range(b'0'..=b'9')))
+        .collect()
+}
+
+/// Startwert für einen Datensatz: FNV-1a über Startwert und Import-ID, damit ein Schüler bei
+/// gleichem --seed unabhängig von Reihenfolge und übrigen Zeilen dasselbe Passwort bekommt.
+fn record_seed(seed: u64, import_id: &str) -> u64 {
+    seed.to_le_bytes()
+        .iter()
+        .chain(import_id.as_bytes())
+        .fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Für welche Konten ein Passwortverfahren gilt.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchemeKey {
+    Alle,
+    Rolle(Rolle),
+    /// Schüler einer Klasse ("5a") oder eines ganzen Jahrgangs ("5").
+    Klasse(String),
+}
+
+impl SchemeKey {
+    pub fn matches(&self, record: &RecordIserv) -> bool {
+        match self {
+            SchemeKey::Alle => true,
+            SchemeKey::Rolle(rolle) => record.rolle == *rolle,
+            SchemeKey::Klasse(klasse) => {
+                let (key, record_klasse) =
+                    (comparable_class(klasse), comparable_class(&record.klasse));
+                let jahrgang: String = record_klasse
+                    .chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect();
+                record.rolle == Rolle::Schueler && (record_klasse == key || jahrgang == key)
+            }
+        }
+    }
+
+    /// Bezeichnung im Bericht, z.B. "Lehrer".
+    pub fn label(&self) -> String {
+        match self {
+            SchemeKey::Alle => "Übrige".to_owned(),
+            SchemeKey::Rolle(Rolle::Schueler) => "Schüler".to_owned(),
+            SchemeKey::Rolle(Rolle::Lehrer) => "Lehrer".to_owned(),
+            SchemeKey::Klasse(klasse) => format!("Klasse {}", klasse),
+        }
+    }
+
+    /// Zusatz für Meldungen, z.B. " für Lehrer"; leer, wenn das Verfahren für alle gilt.
+    pub fn for_whom(&self) -> String {
+        match self {
+            SchemeKey::Alle => String::new(),
+            key => format!(" für {}", key.label()),
+        }
+    }
+}
+
+/// Klasse ohne Leerzeichen, führende Nullen und Großbuchstaben, damit "07 B" zu "7b" passt.
+pub fn comparable_class(klasse: &str) -> String {
+    let klasse: String = klasse
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    klasse.trim_start_matches('0').to_owned()
+}
+
+/// Abweichungen vom Passwortverfahren der Kommandozeile; was fehlt, bleibt wie dort.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchemeOverride {
+    pub style: Option<PasswordStyle>,
+    pub words: Option<usize>,
+    pub wordlist_preset: Option<WordlistPreset>,
+    pub separator: Option<String>,
+    pub capitalize: Option<Capitalize>,
+    pub digits: Option<usize>,
+    pub symbol: Option<bool>,
+    pub pin_length: Option<usize>,
+}
+
+impl SchemeOverride {
+    /// `base` mit diesen Abweichungen; `wordlist` liefert die gefilterte eingebaute Wortliste.
+    pub fn apply(
+        &self,
+        base: &PasswordOptions,
+        wordlist: impl FnOnce(WordlistPreset) -> Result<Vec<String>, IsitError>,
+    ) -> Result<PasswordOptions, IsitError> {
+        let mut options = base.clone();
+        options.style = self.style.unwrap_or(options.style);
+        options.words = self.words.unwrap_or(options.words);
+        if let Some(preset) = self.wordlist_preset {
+            options.wordlist = wordlist(preset)?;
+        }
+        if let Some(separator) = &self.separator {
+            options.separator = separator.clone();
+        }
+        options.capitalize = self.capitalize.unwrap_or(options.capitalize);
+        options.digits = self.digits.unwrap_or(options.digits);
+        options.symbol = self.symbol.unwrap_or(options.symbol);
+        options.pin_length = self.pin_length.unwrap_or(options.pin_length);
+        if options.words == 0 {
+            return Err(IsitError::Usage("words muss mindestens 1 sein.".to_owned()));
+        }
+        if options.style == PasswordStyle::Pin && options.pin_length < 4 {
+            return Err(IsitError::Usage(
+                "pin_length muss mindestens 4 sein.".to_owned(),
+            ));
+        }
+        Ok(options)
+    }
+}
+
+/// Passwortverfahren je Rolle und Klasse für --password-schemes, z.B. längere Passwörter für
+/// Lehrkräfte in einer gemeinsamen Datei mit Schülern und PINs für den Jahrgang 5:
+///
+/// ```toml
+/// [lehrer]
+/// words = 4
+/// wordlist_preset = "englisch-lang"
+/// digits = 2
+///
+/// [klassen.5]
+/// style = "pin"
+/// ```
+///
+/// Eine Klasse geht vor ihrem Jahrgang und beide vor der Rolle.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchemeTable {
+    pub schueler: Option<SchemeOverride>,
+    pub lehrer: Option<SchemeOverride>,
+    pub klassen: BTreeMap<String, SchemeOverride>,
+}
+
+impl SchemeTable {
+    pub fn load(path: &Path) -> Result<Self, IsitError> {
+        let text = fs::read_to_string(path).map_err(IsitError::io(path))?;
+        toml::from_str(&text).map_err(IsitError::config(path))
+    }
+}
+
+/// Die Passwortverfahren eines Laufs; für jedes Konto gilt das erste passende, zuletzt steht
+/// das Verfahren für alle.
+#[derive(Clone, Debug)]
+pub struct PasswordSchemes(pub Vec<(SchemeKey, PasswordOptions)>);
+
+impl PasswordSchemes {
+    pub fn new(default: PasswordOptions) -> Self {
+        PasswordSchemes(vec![(SchemeKey::Alle, default)])
+    }
+
+    /// Setzt ein Verfahren vor die bisherigen.
+    pub fn add(&mut self, key: SchemeKey, options: PasswordOptions) {
+        self.0.insert(0, (key, options));
+    }
+
+    fn index_for(&self, record: &RecordIserv) -> usize {
+        self.0
+            .iter()
+            .position(|(key, _)| key.matches(record))
+            .unwrap_or(self.0.len() - 1)
+    }
+}
+
+impl From<PasswordOptions> for PasswordSchemes {
+    fn from(options: PasswordOptions) -> Self {
+        PasswordSchemes::new(options)
+    }
+}
+
+/// Wie viele Passwörter ein Verfahren erzeugt hat und wie stark sie sind.
+#[derive(Clone, Debug)]
+pub struct SchemeUsage {
+    pub key: SchemeKey,
+    pub generated: usize,
+    pub entropy_bits: f64,
+    pub scheme: String,
+}
+
+/// Wie viele Passwörter neu erzeugt wurden und wie stark sie sind, z.B. für den
+/// Datenschutzbeauftragten; je Verfahren, das tatsächlich gebraucht wurde.
+#[derive(Clone, Debug)]
+pub struct PasswordReport {
+    pub schemes: Vec<SchemeUsage>,
+}
+
+impl PasswordReport {
+    pub fn generated(&self) -> usize {
+        self.schemes.iter().map(|usage| usage.generated).sum()
+    }
+
+    /// Das Verfahren, nach dem das Passwort von `record` erzeugt wurde.
+    pub fn usage_for(&self, record: &RecordIserv) -> Option<&SchemeUsage> {
+        record.entropie?;
+        self.schemes.iter().find(|usage| usage.key.matches(record))
+    }
+
+    /// Derselbe Bericht, gezählt nur über `records`, z.B. die tatsächlich geschriebenen.
+    pub fn recount(&self, records: &[RecordIserv]) -> Self {
+        let mut schemes = self.schemes.clone();
+        for usage in schemes.iter_mut() {
+            usage.generated = 0;
+        }
+        for record in records.iter().filter(|r| r.entropie.is_some()) {
+            if let Some(usage) = schemes.iter_mut().find(|usage| usage.key.matches(record)) {
+                usage.generated += 1;
+            }
+        }
+        schemes.retain(|usage| usage.generated > 0);
+        PasswordReport { schemes }
+    }
+}
+
+/// So oft wird ein Passwort neu erzeugt, bevor die Richtlinie als unerfüllbar gilt.
+const MAX_ATTEMPTS: usize = 1000;
+
+/// Vergibt allen Datensätzen ohne Passwort ein neues nach dem ersten passenden Verfahren aus
+/// `schemes`; Passwörter aus der Eingabe, z.B. einer bestehenden IServ-Datei, bleiben. Mit Richtlinie wird jedes neue Passwort so lange neu
+/// erzeugt, bis es sie erfüllt. Ziffern und Sonderzeichen, die die Richtlinie verlangt, werden
+/// immer angehängt, weil die Wortliste keine enthält.
+///
+/// Die Entropie im Bericht und an den Datensätzen ist die des Verfahrens: alle Möglichkeiten
+/// aus Wortliste, Wortanzahl, Ziffern und Sonderzeichen, ohne den kleinen Abzug durch
+/// verworfene Passwörter.
+pub fn generate_passwords(
+    records: &mut [RecordIserv],
+    schemes: &PasswordSchemes,
+    policy: Option<&PasswordPolicy>,
+) -> Result<PasswordReport, IsitError> {
+    let mut schemes = schemes.clone();
+    let mut needed = vec![false; schemes.0.len()];
+    for record in records.iter().filter(|r| r.password.is_empty()) {
+        needed[schemes.index_for(record)] = true;
+    }
+    if let Some(policy) = policy {
+        let needed = schemes
+            .0
+            .iter_mut()
+            .zip(&needed)
+            .filter(|(_, needed)| **needed);
+        for ((key, options), _) in needed {
+            adjust_to_policy(key, options, policy)?;
+        }
+    }
+    let mut usage: Vec<SchemeUsage> = schemes
+        .0
+        .iter()
+        .map(|(key, options)| SchemeUsage {
+            key: key.clone(),
+            generated: 0,
+            entropy_bits: options.entropy_bits(),
+            scheme: options.describe(),
+        })
+        .collect();
+    let mut thread_rng = rand::thread_rng();
+    for record in records.iter_mut().filter(|r| r.password.is_empty()) {
+        let index = schemes.index_for(record);
+        let options = &schemes.0[index].1;
+        let mut rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(record_seed(seed, &record.import_id)),
+            None => StdRng::from_rng(&mut thread_rng).expect("thread_rng schlägt nicht fehl"),
+        };
+        record.password = generate_valid(options, policy, &mut rng)?;
+        record.entropie = Some(usage[index].entropy_bits);
+        usage[index].generated += 1;
+    }
+    usage.retain(|usage| usage.generated > 0);
+    Ok(PasswordReport { schemes: usage })
+}
+
+/// Erzeugt `count` Beispielpasswörter je Verfahren, z.B. um die Optionen vor einem Lauf zu
+/// prüfen; mit Richtlinie angepasst wie in [`generate_passwords`].
+pub fn sample_passwords(
+    schemes: &PasswordSchemes,
+    policy: Option<&PasswordPolicy>,
+    count: usize,
+) -> Result<Vec<(SchemeUsage, Vec<String>)>, IsitError> {
+    let mut rng = rand::thread_rng();
+    let mut samples = Vec::new();
+    for (key, options) in &schemes.0 {
+        let mut options = options.clone();
+        if let Some(policy) = policy {
+            adjust_to_policy(key, &mut options, policy)?;
+        }
+        let passwords = (0..count)
+            .map(|_| generate_valid(&options, policy, &mut rng))
+            .collect::<Result<Vec<_>, _>>()?;
+        let usage = SchemeUsage {
+            key: key.clone(),
+            generated: passwords.len(),
+            entropy_bits: options.entropy_bits(),
+            scheme: options.describe(),
+        };
+        samples.push((usage, passwords));
+    }
+    Ok(samples)
+}
+
+/// Erzeugt so lange neue Passwörter, bis eines die Richtlinie erfüllt.
+fn generate_valid<R: Rng>(
+    options: &PasswordOptions,
+    policy: Option<&PasswordPolicy>,
+    rng: &mut R,
+) -> Result<String, IsitError> {
+    let mut violations = Vec::new();
+    for _ in 0..MAX_ATTEMPTS {
+        let password = options.generate(rng);
+        violations = policy.map_or_else(Vec::new, |policy| policy.violations(&password));
+        if violations.is_empty() {
+            return Ok(password);
+        }
+    }
+    Err(IsitError::conversion(format!(
+        "Nach {} Versuchen erfüllt kein Passwort aus der Wortliste die Richtlinie ({}). Mehr \
+         Wörter (--password-words), eine andere Wortliste (--wordlist) oder \
+         --password-capitalize always wählen.",
+        MAX_ATTEMPTS,
+        violations.join(", ")
+    )))
+}
+
+/// Ergänzt Ziffern und Sonderzeichen, die die Richtlinie verlangt, und prüft die Entropie.
+fn adjust_to_policy(
+    key: &SchemeKey,
+    options: &mut PasswordOptions,
+    policy: &PasswordPolicy,
+) -> Result<(), IsitError> {
+    if policy.require_digit && options.digits == 0 {
+        options.digits = 1;
+    }
+    options.symbol |= policy.require_symbol;
+    let bits = options.entropy_bits();
+    if bits >= policy.min_entropy_bits {
+        return Ok(());
+    }
+    let hint = match options.style {
+        PasswordStyle::Words => {
+            "Mehr Wörter (--password-words), eine größere Wortliste (--wordlist) oder \
+             --password-digits helfen."
+        }
+        PasswordStyle::Pin => "Eine längere PIN (--pin-length) hilft.",
+    };
+    Err(IsitError::conversion(format!(
+        "Neue Passwörter{} hätten nur {:.1} Bit Entropie, die Richtlinie verlangt {:.1}. {}",
+        key.for_whom(),
+        bits,
+        policy.min_entropy_bits,
+        hint
+    )))
+}
+
+/// Passwortrichtlinie einer IServ-Instanz, z.B.:
+///
+/// ```toml
+/// min_length = 12
+/// require_uppercase = true
+/// require_digit = true
+/// min_entropy_bits = 40.0
+/// ```
+///
+/// `min_entropy_bits` gilt nur für neu erzeugte Passwörter und wird aus Wortliste, Wortanzahl,
+/// Ziffern und Sonderzeichen berechnet.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    pub min_entropy_bits: f64,
+}
+
+pub const POLICY_SYMBOLS: &[u8] = b"!#$%&*+?";
+
+impl PasswordPolicy {
+    pub fn load(path: &Path) -> Result<Self, IsitError> {
+        let text = fs::read_to_string(path).map_err(IsitError::io(path))?;
+        toml::from_str(&text).map_err(IsitError::config(path))
+    }
+
+    /// Liefert die Regeln, die das Passwort verletzt.
+    pub fn violations(&self, password: &str) -> Vec<&'static str> {
+        let mut violations = Vec::new();
+        if password.chars().count() < self.min_length {
+            violations.push("zu kurz");
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            violations.push("kein Kleinbuchstabe");
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            violations.push("kein Großbuchstabe");
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push("keine Ziffer");
+        }
+        if self.require_symbol && !password.bytes().any(|b| POLICY_SYMBOLS.contains(&b)) {
+            violations.push("kein Sonderzeichen");
+        }
+        violations
+    }
+
+    /// Ergänzt ein Passwort so, dass es die Richtlinie erfüllt: Großbuchstabe am Anfang,
+    /// angehängte Ziffern und Sonderzeichen.
+    pub fn enforce<R: Rng>(&self, password: &str, rng: &mut R) -> String {
+        let mut password = password.to_owned();
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            password.push(char::from(rng.gen_range(b'a'..=b'z')));
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            let mut chars = password.chars();
+            password = match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => char::from(rng.gen_range(b'A'..=b'Z')).to_string(),
+            };
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            password.push(char::from(rng.gen_range(b'0'..=b'9')));
+        }
+        if self.require_symbol && !password.bytes().any(|b| POLICY_SYMBOLS.contains(&b)) {
+            password.push(char::from(
+                POLICY_SYMBOLS[rng.gen_range(0..POLICY_SYMBOLS.len())],
+            ));
+        }
+        while password.chars().count() < self.min_length {
+            password.push(char::from(rng.gen_range(b'0'..=b'9')));
+        }
+        password
+    }
+}
+
+/// Wie Passwörter in Anzeigen verdeckt werden, damit geteilte Bildschirmfotos keine
+/// Zugangsdaten verraten.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum PasswordMask {
+    /// Passwort vollständig durch Sterne ersetzen.
+    Full,
+    /// Nur Anfang und Ende zeigen (ha***ke).
+    Partial,
+    /// Passwort im Klartext zeigen.
+    None,
+}
+
+impl PasswordMask {
+    pub fn apply(&self, password: &str) -> String {
+        let chars: Vec<char> = password.chars().collect();
+        match self {
+            PasswordMask::None => password.to_owned(),
+            PasswordMask::Partial if chars.len() > 6 => {
+                let start: String = chars[..2].iter().collect();
+                let end: String = chars[chars.len() - 2..].iter().collect();
+                format!("{}***{}", start, end)
+            }
+            PasswordMask::Partial | PasswordMask::Full => "*".repeat(8),
+        }
+    }
+}
+
+/// Ergänzt alle Passwörter, die gegen die Richtlinie verstoßen, und prüft danach jedes erneut.
+pub fn apply_password_policy(
+    records: &mut [RecordIserv],
+    policy: &PasswordPolicy,
+) -> Result<(), IsitError> {
+    let mut rng = rand::thread_rng();
+    let mut adjusted = 0;
+    for record in records.iter_mut() {
+        if !policy.violations(&record.password).is_empty() {
+            record.password = policy.enforce(&record.password, &mut rng);
+            debug!("Passwort für Datensatz {} angepasst.", record.id);
+            adjusted += 1;
+        }
+    }
+    if adjusted > 0 {
+        info!(
+            "{} Passwörter wurden an die Passwortrichtlinie angepasst.",
+            adjusted
+        );
+    }
+    let mut invalid = 0;
+    for record in records.iter() {
+        let violations = policy.violations(&record.password);
+        if !violations.is_empty() {
+            invalid += 1;
+            error!(
+                "Datensatz {}, {} {}: Passwort verletzt die Richtlinie: {}.",
+                record.id,
+                record.vorname,
+                record.nachname,
+                violations.join(", ")
+            );
+        }
+    }
+    if invalid > 0 {
+        return Err(IsitError::conversion(format!(
+            "{} Passwörter erfüllen die Richtlinie nicht, es wurde keine Datei geschrieben.",
+            invalid
+        )));
+    }
+    Ok(())
+}
+
+/// Konto aus einer bestehenden Zugangsdatendatei, dessen Passwort erneuert werden sollte.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordRotation {
+    pub nachname: String,
+    pub vorname: String,
+    pub klasse: String,
+    #[serde(rename = "Import-ID")]
+    pub import_id: String,
+    pub grund: String,
+}
+
+/// Prüft bereits ausgegebene Passwörter gegen die Richtlinie. Außerdem fallen leere,
+/// mehrfach vergebene und den Namen enthaltende Passwörter auf.
+pub fn audit_passwords(records: &[RecordIserv], policy: &PasswordPolicy) -> Vec<RecordRotation> {
+    let mut usage: HashMap<&str, usize> = HashMap::new();
+    for record in records {
+        *usage.entry(record.password.as_str()).or_default() += 1;
+    }
+    let mut rotations = Vec::new();
+    for record in records {
+        let mut reasons: Vec<String> = Vec::new();
+        if record.password.is_empty() {
+            reasons.push("kein Passwort".to_owned());
+        } else {
+            reasons.extend(
+                policy
+                    .violations(&record.password)
+                    .into_iter()
+                    .map(str::to_owned),
+            );
+            let count = usage[record.password.as_str()];
+            if count > 1 {
+                reasons.push(format!("{} Konten mit gleichem Passwort", count));
+            }
+            let password = record.password.to_lowercase();
+            if [&record.vorname, &record.nachname]
+                .iter()
+                .any(|name| name.chars().count() > 2 && password.contains(&name.to_lowercase()))
+            {
+                reasons.push("enthält den Namen".to_owned());
+            }
+        }
+        if !reasons.is_empty() {
+            rotations.push(RecordRotation {
+                nachname: record.nachname.clone(),
+                vorname: record.vorname.clone(),
+                klasse: record.klasse.clone(),
+                import_id: record.import_id.clone(),
+                grund: reasons.join(", "),
+            });
+        }
+    }
+    rotations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(import_id: &str, rolle: Rolle) -> RecordIserv {
+        let mut record = RecordIserv::new(
+            "Meier".to_owned(),
+            "Tom".to_owned(),
+            "5a".to_owned(),
+            import_id.to_owned(),
+        );
+        record.rolle = rolle;
+        record
+    }
+
+    #[test]
+    fn teachers_and_students_get_their_own_scheme() {
+        let default = PasswordOptions {
+            seed: Some(4711),
+            ..PasswordOptions::default()
+        };
+        let mut schemes = PasswordSchemes::new(default.clone());
+        let pin = SchemeOverride {
+            style: Some(PasswordStyle::Pin),
+            pin_length: Some(8),
+            ..SchemeOverride::default()
+        };
+        schemes.add(
+            SchemeKey::Rolle(Rolle::Lehrer),
+            pin.apply(&default, |_| unreachable!()).unwrap(),
+        );
+        let mut records = [
+            record("1", Rolle::Schueler),
+            record("2", Rolle::Lehrer),
+            record("3", Rolle::Schueler),
+        ];
+        let report = generate_passwords(&mut records, &schemes, None).unwrap();
+        assert!(records[0].password.contains('-'));
+        assert_eq!(records[1].password.len(), 8);
+        assert!(records[1].password.chars().all(|c| c.is_ascii_digit()));
+        let counts: Vec<_> = report
+            .schemes
+            .iter()
+            .map(|usage| (usage.key.label(), usage.generated))
+            .collect();
+        assert_eq!(counts, [("Lehrer".to_owned(), 1), ("Übrige".to_owned(), 2)]);
+        assert_eq!(report.generated(), 3);
+        let recounted = report.recount(&records[..1]);
+        assert_eq!(recounted.schemes.len(), 1);
+        assert_eq!(recounted.generated(), 1);
+    }
+
+    #[test]
+    fn a_class_goes_before_its_year_and_the_role() {
+        // Wörter der Liste können selbst "-" enthalten.
+        let words = |words| PasswordOptions {
+            words,
+            separator: "_".to_owned(),
+            ..PasswordOptions::default()
+        };
+        let mut schemes = PasswordSchemes::new(words(2));
+        schemes.add(SchemeKey::Rolle(Rolle::Schueler), words(3));
+        schemes.add(SchemeKey::Klasse("5".to_owned()), words(4));
+        schemes.add(SchemeKey::Klasse("5a".to_owned()), words(5));
+        let in_class = |klasse: &str| RecordIserv {
+            klasse: klasse.to_owned(),
+            ..record("1", Rolle::Schueler)
+        };
+        let mut records = [
+            in_class("05 A"),
+            in_class("5b"),
+            in_class("15b"),
+            in_class("7c"),
+            record("2", Rolle::Lehrer),
+        ];
+        let report = generate_passwords(&mut records, &schemes, None).unwrap();
+        let words: Vec<_> = records
+            .iter()
+            .map(|record| record.password.split('_').count())
+            .collect();
+        assert_eq!(words, [5, 4, 3, 3, 2]);
+        let label = |record| report.usage_for(record).unwrap().key.label();
+        assert_eq!(label(&records[1]), "Klasse 5");
+        assert_eq!(label(&records[4]), "Übrige");
+    }
+
+    #[test]
+    fn samples_follow_the_policy() {
+        let policy = PasswordPolicy {
+            require_digit: true,
+            ..PasswordPolicy::default()
+        };
+        let schemes = PasswordSchemes::new(PasswordOptions::default());
+        let samples = sample_passwords(&schemes, Some(&policy), 3).unwrap();
+        let [(usage, passwords)] = &samples[..] else {
+            panic!("ein Verfahren erwartet");
+        };
+        assert_eq!(passwords.len(), 3);
+        assert!(passwords
+            .iter()
+            .all(|password| policy.violations(password).is_empty()));
+        assert!(usage.scheme.ends_with("1 Ziffern"));
+    }
+}
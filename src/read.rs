@@ -0,0 +1,1140 @@
+//! Einlesen von CSV- und Excel-Dateien, auch komprimiert oder aus ZIP-Archiven.
+
+use calamine::{DataType, Ods, Range, RangeDeserializerBuilder, Reader, Sheets, Xls, Xlsx};
+use clap::ValueEnum;
+use encoding_rs::{UTF_8, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::GzDecoder;
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::ZipArchive;
+
+use crate::error::IsitError;
+use crate::formats::*;
+use crate::records::*;
+
+/// Pfad für die Standardeingabe bzw. -ausgabe.
+pub const STDIO: &str = "-";
+
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO)
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum FileType {
+    Csv,
+    Excel,
+    /// Altes Excel-Format (.xls), wie es ältere SchILD-Versionen erzeugen.
+    Xls,
+    /// OpenDocument-Tabelle, z.B. aus LibreOffice.
+    Ods,
+    /// An der Dateiendung erkennen, im Zweifel CSV.
+    #[default]
+    Auto,
+}
+
+impl FileType {
+    /// Der Dateityp für diese Datei; bei `Auto` aus der Endung bestimmt. Bei `.gz` zählt die
+    /// Endung davor, bei `.zip` die der mit --zip-member gewählten Datei.
+    pub fn resolve(self, path: &Path, zip_member: Option<&str>) -> FileType {
+        if self != FileType::Auto {
+            return self;
+        }
+        let from_extension = |path: &Path| {
+            let extension = path.extension()?.to_string_lossy().to_lowercase();
+            match extension.as_str() {
+                "csv" | "txt" => Some(FileType::Csv),
+                "xlsx" => Some(FileType::Excel),
+                "xls" => Some(FileType::Xls),
+                "ods" => Some(FileType::Ods),
+                _ => None,
+            }
+        };
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let file_type = match extension.as_deref() {
+            Some("gz") => path
+                .file_stem()
+                .and_then(|stem| from_extension(Path::new(stem))),
+            Some("zip") => zip_member.and_then(|member| from_extension(Path::new(member))),
+            _ => from_extension(path),
+        };
+        let file_type = file_type.unwrap_or(FileType::Csv);
+        info!("Dateityp: {:?}", file_type);
+        file_type
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Encoding {
+    Utf8,
+    Windows,
+    /// Am Byte Order Mark erkennen, sonst UTF-8 und bei ungültigem UTF-8 Windows-1252.
+    #[default]
+    Auto,
+}
+
+/// Trennzeichen der CSV-Eingabe.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Delimiter {
+    /// An den ersten Zeilen erkennen, im Zweifel `;`.
+    #[default]
+    Auto,
+    Semicolon,
+    Comma,
+    Tab,
+}
+
+impl Delimiter {
+    /// Das Trennzeichen für diesen Text; bei `Auto` aus den ersten Zeilen bestimmt.
+    pub fn resolve(&self, text: &str) -> u8 {
+        match self {
+            Delimiter::Auto => {
+                let detected = detect_delimiter(text);
+                if detected != b';' {
+                    info!("Erkanntes Trennzeichen: {:?}", char::from(detected));
+                }
+                detected
+            }
+            Delimiter::Semicolon => b';',
+            Delimiter::Comma => b',',
+            Delimiter::Tab => b'\t',
+        }
+    }
+}
+
+/// Sucht unter `;`, `,` und Tab das Trennzeichen, das in den ersten Zeilen gleich oft und am
+/// häufigsten vorkommt. Zeichen in Anführungszeichen und Kommentarzeilen zählen nicht.
+pub fn detect_delimiter(text: &str) -> u8 {
+    // Bei Gleichstand gewinnt das letzte, also `;`.
+    const CANDIDATES: [u8; 3] = [b'\t', b',', b';'];
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .take(5)
+        .collect();
+    let count = |line: &str, delimiter: u8| {
+        let mut quoted = false;
+        line.bytes()
+            .filter(|&b| {
+                if b == b'"' {
+                    quoted = !quoted;
+                }
+                !quoted && b == delimiter
+            })
+            .count()
+    };
+    let score = |delimiter: u8| {
+        let counts: Vec<usize> = lines.iter().map(|line| count(line, delimiter)).collect();
+        let header = counts.first().copied().unwrap_or_default();
+        let consistent = counts.iter().all(|&c| c == header);
+        (header > 0 && consistent, header)
+    };
+    CANDIDATES
+        .iter()
+        .copied()
+        .filter(|&delimiter| score(delimiter).1 > 0)
+        .max_by_key(|&delimiter| score(delimiter))
+        .unwrap_or(b';')
+}
+
+/// Wie eine Eingabedatei gelesen wird.
+#[derive(Debug, Default)]
+pub struct ReadOptions {
+    pub encoding: Encoding,
+    pub delimiter: Delimiter,
+    /// Datei innerhalb eines ZIP-Archivs, die gelesen werden soll.
+    pub zip_member: Option<String>,
+    /// Zeilen, in denen in Excel-Dateien nach der Kopfzeile gesucht wird; 0 sucht nicht.
+    pub header_scan_rows: usize,
+    pub on_error: OnError,
+    pub limits: ReadLimits,
+    /// Tabellenblatt nach Name oder Nummer (ab 1); ohne Angabe das erste.
+    pub sheet: Option<String>,
+    /// Alle Tabellenblätter nacheinander lesen statt nur eines.
+    pub all_sheets: bool,
+    /// Zeilen am Anfang, die vor der Kopfzeile übersprungen werden, z.B. ein Titelblock.
+    pub skip_rows: usize,
+}
+
+/// Was mit Zeilen geschieht, die sich nicht lesen oder prüfen lassen.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OnError {
+    /// Den Lauf abbrechen.
+    #[default]
+    Abort,
+    /// Die Zeile in die Datei der abgelehnten Zeilen schreiben und weitermachen.
+    Skip,
+}
+
+/// Abgelehnte Eingabezeile mit Grund, für die Datei --rejects-path.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Reject {
+    pub zeile: usize,
+    pub grund: String,
+    pub inhalt: String,
+}
+
+impl OnError {
+    /// Merkt sich im Modus `skip` die fehlerhafte Zeile, sonst wird der Fehler weitergegeben.
+    pub fn handle(
+        &self,
+        error: IsitError,
+        zeile: usize,
+        inhalt: String,
+        rejects: &mut Vec<Reject>,
+    ) -> Result<(), IsitError> {
+        match self {
+            OnError::Abort => Err(error),
+            OnError::Skip => {
+                let grund = error.reason();
+                warn!("Zeile {} wird übersprungen: {}", zeile, grund);
+                rejects.push(Reject {
+                    zeile,
+                    grund,
+                    inhalt,
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Grenzen, mit denen beschädigte oder bösartige Dateien abgelehnt werden, bevor sie den
+/// Speicher füllen.
+#[derive(Debug, Default)]
+pub struct ReadLimits {
+    pub max_rows: Option<usize>,
+    pub max_field_len: Option<usize>,
+    pub max_file_size: Option<u64>,
+}
+
+impl ReadLimits {
+    pub fn check_file(&self, path: &Path) -> Result<(), IsitError> {
+        if let Some(max) = self.max_file_size {
+            let size = fs::metadata(path).map_err(IsitError::io(path))?.len();
+            if size > max {
+                return Err(IsitError::input(
+                    path,
+                    format!(
+                        "Datei ist {} Bytes groß, erlaubt sind höchstens {}.",
+                        size, max
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Liest alles aus `reader`, bricht aber ab, sobald mehr als erlaubt anfällt.
+    pub fn read_all<R: Read>(&self, path: &Path, reader: R) -> Result<Vec<u8>, IsitError> {
+        let mut bytes = Vec::new();
+        match self.max_file_size {
+            Some(max) => {
+                reader
+                    .take(max + 1)
+                    .read_to_end(&mut bytes)
+                    .map_err(IsitError::io(path))?;
+                if bytes.len() as u64 > max {
+                    return Err(IsitError::input(
+                        path,
+                        format!(
+                            "Der entpackte Inhalt ist größer als {} Bytes, die Datei wird \
+                             abgelehnt.",
+                            max
+                        ),
+                    ));
+                }
+            }
+            None => {
+                let mut reader = reader;
+                reader
+                    .read_to_end(&mut bytes)
+                    .map_err(IsitError::io(path))?;
+            }
+        }
+        Ok(bytes)
+    }
+
+    pub fn check_row_count(&self, path: &Path, rows: usize) -> Result<(), IsitError> {
+        match self.max_rows {
+            Some(max) if rows > max => Err(IsitError::input(
+                path,
+                format!("Mehr als {} Datenzeilen, die Datei wird abgelehnt.", max),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_field(&self, path: &Path, row: usize, field: &str) -> Result<(), IsitError> {
+        match self.max_field_len {
+            Some(max) if field.chars().count() > max => Err(IsitError::Input {
+                path: path.to_path_buf(),
+                line: Some(row),
+                message: format!(
+                    "Feld ist länger als {} Zeichen, die Datei wird abgelehnt.",
+                    max
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn get_all_xlsx_records_in_file(
+    path: PathBuf,
+    file_type: FileType,
+    record_type: Option<RecordType>,
+    options: &ReadOptions,
+    rejects: &mut Vec<Reject>,
+) -> Result<Vec<Record>, IsitError> {
+    let mut records: Vec<Record> = Vec::new();
+    let worksheets = load_worksheets(&path, file_type, options)?;
+    info!("Tabellendatei geöffnet.");
+    for (sheet, range) in worksheets {
+        if options.all_sheets {
+            info!("Lese Tabellenblatt \"{}\".", sheet);
+        }
+        read_worksheet(
+            &path,
+            &sheet,
+            range,
+            record_type,
+            options,
+            &mut records,
+            rejects,
+        )?;
+    }
+    Ok(records)
+}
+
+/// Hängt die Datensätze eines Tabellenblatts an `records` an.
+fn read_worksheet(
+    path: &Path,
+    sheet: &str,
+    range: Range<DataType>,
+    record_type: Option<RecordType>,
+    options: &ReadOptions,
+    records: &mut Vec<Record>,
+    rejects: &mut Vec<Reject>,
+) -> Result<(), IsitError> {
+    let path = path.to_path_buf();
+    let limits = &options.limits;
+    let range = skip_rows(range, options.skip_rows);
+    let range = skip_to_header_row(range, record_type, options.header_scan_rows);
+    let header: Vec<String> = range
+        .rows()
+        .next()
+        .map(|header| header.iter().map(|cell| cell.to_string()).collect())
+        .unwrap_or_default();
+    let record_type = resolve_record_type(&path, record_type, &header)?;
+    limits.check_row_count(&path, records.len() + range.height().saturating_sub(1))?;
+    for (row, _, cell) in range.used_cells() {
+        if let DataType::String(field) = cell {
+            limits.check_field(&path, row + 1, field)?;
+        }
+    }
+    let builder = RangeDeserializerBuilder::new();
+    // Zeile für Zeile umwandeln, damit nicht alle Zeilen zugleich als Zwischenergebnis im
+    // Speicher liegen.
+    let rows: Box<dyn Iterator<Item = Result<Record, calamine::DeError>>> = match record_type {
+        RecordType::Schild => Box::new(
+            builder
+                .from_range(&range)
+                .map_err(IsitError::excel(&path))?
+                .map(|row| row.map(Record::RecordSchild)),
+        ),
+        RecordType::Gastschueler => Box::new(
+            builder
+                .from_range(&range)
+                .map_err(IsitError::excel(&path))?
+                .map(|row| row.map(Record::RecordGastschueler)),
+        ),
+        RecordType::Lehrer => Box::new(
+            builder
+                .from_range(&range)
+                .map_err(IsitError::excel(&path))?
+                .map(|row| row.map(Record::RecordLehrer)),
+        ),
+        RecordType::Logineo => Box::new(
+            builder
+                .from_range(&range)
+                .map_err(IsitError::excel(&path))?
+                .map(|row| row.map(Record::RecordLogineo)),
+        ),
+        RecordType::Asv => Box::new(
+            builder
+                .from_range(&range)
+                .map_err(IsitError::excel(&path))?
+                .map(|row| row.map(Record::RecordAsv)),
+        ),
+        RecordType::Lusd => Box::new(
+            builder
+                .from_range(&range)
+                .map_err(IsitError::excel(&path))?
+                .map(|row| row.map(Record::RecordLusd)),
+        ),
+        RecordType::WebUntis => Box::new(
+            builder
+                .from_range(&range)
+                .map_err(IsitError::excel(&path))?
+                .map(|row| row.map(Record::RecordWebUntis)),
+        ),
+        RecordType::Iserv => Box::new(
+            builder
+                .from_range(&range)
+                .map_err(IsitError::excel(&path))?
+                .map(|row| row.map(Record::RecordIserv)),
+        ),
+        RecordType::Custom => unreachable!("resolve_record_type lehnt custom ab"),
+    };
+    // Die erste Datenzeile steht direkt unter der Kopfzeile.
+    let header_row = range.start().map(|(row, _)| row as usize + 1).unwrap_or(1);
+    for (index, row) in rows.enumerate() {
+        let zeile = header_row + index + 1;
+        match row {
+            Ok(mut record) => {
+                let id = RecordId {
+                    nummer: records.len() + 1,
+                    zeile,
+                };
+                if options.all_sheets {
+                    debug!("Datensatz {} aus Tabellenblatt \"{}\".", id, sheet);
+                }
+                record.set_id(id);
+                records.push(record);
+            }
+            Err(e) => {
+                let inhalt = range
+                    .rows()
+                    .nth(index + 1)
+                    .map(|cells| {
+                        let fields: Vec<String> = cells.iter().map(|c| c.to_string()).collect();
+                        fields.join(";")
+                    })
+                    .unwrap_or_default();
+                let error = IsitError::Excel {
+                    path: path.clone(),
+                    line: Some(zeile),
+                    source: e.into(),
+                };
+                options.on_error.handle(error, zeile, inhalt, rejects)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Schneidet die ersten `count` Zeilen ab.
+pub fn skip_rows(range: Range<DataType>, count: usize) -> Range<DataType> {
+    match (range.start(), range.end()) {
+        (Some(start), Some(end)) if count > 0 => {
+            range.range((start.0 + count as u32, start.1), end)
+        }
+        _ => range,
+    }
+}
+
+/// Der Text ab der Zeile nach den ersten `count` Zeilen.
+pub fn skip_lines(text: &str, count: usize) -> &str {
+    let mut rest = text;
+    for _ in 0..count {
+        match rest.find('\n') {
+            Some(end) => rest = &rest[end + 1..],
+            None => return "",
+        }
+    }
+    rest
+}
+
+/// Sucht in den ersten `scan_rows` Zeilen nach der Kopfzeile des Formats, falls sie nicht in
+/// der ersten Zeile steht (z.B. wegen eines Titels über der Tabelle), und schneidet die
+/// Zeilen davor ab. Ohne Datensatztyp zählt die Kopfzeile jedes erkennbaren Formats.
+pub fn skip_to_header_row(
+    range: Range<DataType>,
+    record_type: Option<RecordType>,
+    scan_rows: usize,
+) -> Range<DataType> {
+    let is_header = |row: &[DataType]| {
+        let row: Vec<String> = row
+            .iter()
+            .map(|cell| match cell {
+                DataType::String(s) => s.clone(),
+                _ => String::new(),
+            })
+            .collect();
+        match record_type {
+            Some(record_type) => record_type.matches_header(&row),
+            None => RecordType::detect(&row).is_some(),
+        }
+    };
+    let (start, end) = match (range.start(), range.end()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return range,
+    };
+    match range.rows().take(scan_rows).position(is_header) {
+        Some(0) | None => range,
+        Some(offset) => {
+            warn!(
+                "Kopfzeile in Zeile {} statt in Zeile 1 gefunden, die Zeilen davor werden \
+                 übersprungen.",
+                start.0 as usize + offset + 1
+            );
+            range.range((start.0 + offset as u32, start.1), end)
+        }
+    }
+}
+
+/// Öffnet die Arbeitsmappe passend zum Dateityp.
+pub fn open_workbook<R: io::Read + io::Seek>(
+    reader: R,
+    file_type: FileType,
+    path: &Path,
+) -> Result<Sheets<R>, IsitError> {
+    match file_type {
+        FileType::Xls => Xls::new(reader)
+            .map(Sheets::Xls)
+            .map_err(IsitError::excel(path)),
+        FileType::Ods => Ods::new(reader)
+            .map(Sheets::Ods)
+            .map_err(IsitError::excel(path)),
+        _ => Xlsx::new(reader)
+            .map(Sheets::Xlsx)
+            .map_err(IsitError::excel(path)),
+    }
+}
+
+/// Lädt das mit `options.sheet` gewählte Tabellenblatt, sonst das erste, oder mit
+/// `options.all_sheets` alle Blätter samt Namen.
+pub fn load_worksheets(
+    path: &Path,
+    file_type: FileType,
+    options: &ReadOptions,
+) -> Result<Vec<(String, Range<DataType>)>, IsitError> {
+    let file_type = file_type.resolve(path, options.zip_member.as_deref());
+    let bytes = read_input(path, file_type, options)?;
+    let mut workbook = open_workbook(io::Cursor::new(bytes), file_type, path)?;
+    worksheets(&mut workbook, options, path)
+}
+
+fn worksheets<R: io::Read + io::Seek>(
+    workbook: &mut Sheets<R>,
+    options: &ReadOptions,
+    path: &Path,
+) -> Result<Vec<(String, Range<DataType>)>, IsitError> {
+    if !options.all_sheets {
+        let range = select_worksheet(workbook, options.sheet.as_deref(), path)?;
+        let name = options.sheet.clone().unwrap_or_default();
+        return Ok(vec![(name, range)]);
+    }
+    let mut worksheets = Vec::new();
+    for name in workbook.sheet_names().to_owned() {
+        if let Some(range) = workbook.worksheet_range(&name) {
+            worksheets.push((name, range.map_err(IsitError::excel(path))?));
+        }
+    }
+    Ok(worksheets)
+}
+
+/// Das Tabellenblatt mit diesem Namen oder dieser Nummer (ab 1), ohne Angabe das erste. Ein
+/// Blatt, das wie eine Zahl heißt, geht der Nummer vor.
+pub fn select_worksheet<R: io::Read + io::Seek>(
+    workbook: &mut Sheets<R>,
+    sheet: Option<&str>,
+    path: &Path,
+) -> Result<Range<DataType>, IsitError> {
+    let sheets = workbook.sheet_names().to_owned();
+    let name = match sheet {
+        None => sheets.first(),
+        Some(sheet) => sheets.iter().find(|name| *name == sheet).or_else(|| {
+            sheet
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| index.checked_sub(1))
+                .and_then(|index| sheets.get(index))
+        }),
+    };
+    match name.and_then(|name| workbook.worksheet_range(name)) {
+        Some(range) => range.map_err(IsitError::excel(path)),
+        None if sheets.is_empty() => Err(IsitError::input(
+            path,
+            "Die Arbeitsmappe enthält kein Tabellenblatt.".to_owned(),
+        )),
+        None => Err(IsitError::input(
+            path,
+            format!(
+                "Tabellenblatt \"{}\" gibt es nicht. Vorhanden sind: {}.",
+                sheet.unwrap_or_default(),
+                sheets
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| format!("{} \"{}\"", index + 1, name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )),
+    }
+}
+
+/// Liest die Eingabe vollständig ein, bei `-` von der Standardeingabe. Dateien mit der Endung
+/// `.gz` oder `.zip` werden dabei entpackt.
+pub fn read_input(
+    path: &Path,
+    file_type: FileType,
+    options: &ReadOptions,
+) -> Result<Vec<u8>, IsitError> {
+    if is_stdio(path) {
+        info!("Lese von der Standardeingabe.");
+        return options.limits.read_all(path, io::stdin().lock());
+    }
+    options.limits.check_file(path)?;
+    let file = File::open(path).map_err(IsitError::io(path))?;
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("gz") => {
+            info!("Entpacke {}.", path.display());
+            options.limits.read_all(path, GzDecoder::new(file))
+        }
+        Some("zip") => {
+            let mut archive = ZipArchive::new(file).map_err(IsitError::archive(path))?;
+            let member = match &options.zip_member {
+                Some(member) => member.clone(),
+                None => find_zip_member(path, &archive, file_type)?,
+            };
+            info!("Entpacke {} aus {}.", member, path.display());
+            let entry = archive.by_name(&member).map_err(IsitError::archive(path))?;
+            options.limits.read_all(path, entry)
+        }
+        _ => options.limits.read_all(path, file),
+    }
+}
+
+/// Sucht im Archiv die einzige Datei, deren Endung zum Dateityp passt.
+pub fn find_zip_member(
+    path: &Path,
+    archive: &ZipArchive<File>,
+    file_type: FileType,
+) -> Result<String, IsitError> {
+    let extensions: &[&str] = match file_type {
+        FileType::Csv => &["csv", "txt"],
+        FileType::Excel => &["xlsx"],
+        FileType::Xls => &["xls"],
+        FileType::Ods => &["ods"],
+        FileType::Auto => &["csv", "txt", "xlsx", "xls", "ods"],
+    };
+    let names: Vec<&str> = archive.file_names().collect();
+    let candidates: Vec<&str> = names
+        .iter()
+        .copied()
+        .filter(|name| {
+            Path::new(name)
+                .extension()
+                .map(|extension| {
+                    extensions.contains(&extension.to_string_lossy().to_lowercase().as_str())
+                })
+                .unwrap_or(false)
+        })
+        .collect();
+    match candidates.as_slice() {
+        [name] => Ok((*name).to_owned()),
+        _ => Err(IsitError::input(
+            path,
+            format!(
+                "Im Archiv ist nicht genau eine passende Datei, bitte mit --zip-member wählen: {}",
+                names.join(", ")
+            ),
+        )),
+    }
+}
+
+/// Liest die Datei ein und dekodiert sie. Mit `Encoding::Auto` entscheidet ein Byte Order Mark
+/// (UTF-8 oder UTF-16), sonst wird UTF-8 gelesen, wenn die Datei gültiges UTF-8 ist, und
+/// andernfalls Windows-1252.
+pub fn read_decoded(path: PathBuf, options: &ReadOptions) -> Result<String, IsitError> {
+    let bytes = read_input(&path, FileType::Csv, options)?;
+    let encoding = options.encoding;
+    info!("CSV-Datei geöffnet.");
+    info!("Checke Encoding.");
+    let decode = |encoding: &'static encoding_rs::Encoding| {
+        decode(&bytes, encoding).map_err(|source| IsitError::Encoding {
+            path: path.clone(),
+            encoding: encoding.name(),
+            source,
+        })
+    };
+    let text = match encoding {
+        Encoding::Utf8 => decode(UTF_8)?,
+        Encoding::Windows => decode(WINDOWS_1252)?,
+        Encoding::Auto => {
+            let detected = detect_encoding(&bytes);
+            if detected == WINDOWS_1252 {
+                warn!(
+                    "{} ist kein gültiges UTF-8, lese die Datei als Windows-1252.",
+                    path.display()
+                );
+            } else {
+                info!("Lese die Datei als {}.", detected.name());
+            }
+            decode(detected)?
+        }
+    };
+    report_replacement_characters(&text, encoding);
+    Ok(text)
+}
+
+/// Encoding für `Encoding::Auto`: das des Byte Order Mark, sonst UTF-8, wenn die Bytes gültiges
+/// UTF-8 sind, und andernfalls Windows-1252.
+pub fn detect_encoding(bytes: &[u8]) -> &'static encoding_rs::Encoding {
+    match encoding_rs::Encoding::for_bom(bytes) {
+        Some((bom, _)) => bom,
+        None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+        None => WINDOWS_1252,
+    }
+}
+
+pub fn decode(bytes: &[u8], encoding: &'static encoding_rs::Encoding) -> io::Result<String> {
+    let mut text = String::new();
+    DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .build(bytes)
+        .read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Warnt, wenn beim Dekodieren Zeichen ersetzt wurden, denn dann war das Encoding vermutlich falsch.
+pub fn report_replacement_characters(text: &str, encoding: Encoding) {
+    let affected: Vec<(usize, usize)> = text
+        .lines()
+        .enumerate()
+        .map(|(row, line)| {
+            let count = line.matches(char::REPLACEMENT_CHARACTER).count();
+            (row + 1, count)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    if affected.is_empty() {
+        return;
+    }
+    let total: usize = affected.iter().map(|(_, count)| count).sum();
+    let examples: Vec<String> = affected
+        .iter()
+        .take(5)
+        .map(|(row, count)| format!("Zeile {} ({}×)", row, count))
+        .collect();
+    let hint = match encoding {
+        Encoding::Utf8 => " Vermutlich ist die Datei Windows-1252-kodiert, versuche --encoding windows oder --encoding auto.",
+        _ => "",
+    };
+    warn!(
+        "{} Zeichen in {} Zeilen konnten nicht dekodiert werden, z.B. {}.{}",
+        total,
+        affected.len(),
+        examples.join(", "),
+        hint
+    );
+}
+
+/// Eine Eingabedatei ohne festes Format: Kopfzeile und alle Zeilen als Text.
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+pub fn read_table(
+    path: PathBuf,
+    file_type: FileType,
+    options: &ReadOptions,
+) -> Result<Table, IsitError> {
+    let file_type = file_type.resolve(&path, options.zip_member.as_deref());
+    let mut rows: Vec<Vec<String>> = match file_type {
+        FileType::Csv => {
+            let text = read_decoded(path.clone(), options)?;
+            let text = skip_lines(&text, options.skip_rows);
+            let mut rdr = csv::ReaderBuilder::new()
+                .delimiter(options.delimiter.resolve(text))
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(text.as_bytes());
+            let mut rows = Vec::new();
+            for result in rdr.records() {
+                let row = result.map_err(IsitError::csv(&path))?;
+                rows.push(row.iter().map(|f| f.to_owned()).collect());
+            }
+            rows
+        }
+        _ => {
+            let mut rows = Vec::new();
+            for (index, (_, range)) in load_worksheets(&path, file_type, options)?
+                .into_iter()
+                .enumerate()
+            {
+                // Jedes Blatt hat seine Kopfzeile, gebraucht wird nur die des ersten.
+                let skip = usize::from(index > 0);
+                rows.extend(
+                    skip_rows(range, options.skip_rows)
+                        .rows()
+                        .skip(skip)
+                        .map(|row| row.iter().map(|cell| cell.to_string()).collect()),
+                );
+            }
+            rows
+        }
+    };
+    let headers = if rows.is_empty() {
+        Vec::new()
+    } else {
+        rows.remove(0)
+    };
+    Ok(Table { headers, rows })
+}
+
+/// Liest eine Datei in einem Format aus formats.d/ und ordnet die Spalten über ihre Aliasse zu.
+pub fn get_all_mapped_records_in_file(
+    path: PathBuf,
+    file_type: FileType,
+    format: &FormatDefinition,
+    options: &ReadOptions,
+) -> Result<Vec<Record>, IsitError> {
+    let Table { headers, rows } = read_table(path.clone(), file_type, options)?;
+    let mut positions = Vec::new();
+    for (field, aliases) in format.columns.fields() {
+        let position = headers.iter().position(|header| {
+            aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(header.trim()))
+        });
+        match position {
+            Some(position) => positions.push(position),
+            None => {
+                return Err(IsitError::input(
+                    &path,
+                    format!(
+                        "Format {}: keine Spalte für {} gefunden, erwartet wird eine von: {}.",
+                        format.name,
+                        field,
+                        aliases.join(", ")
+                    ),
+                ))
+            }
+        }
+    }
+    let limits = &options.limits;
+    limits.check_row_count(&path, rows.len())?;
+    let mut records = Vec::new();
+    for (index, row) in rows.iter().enumerate() {
+        if row.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        for field in row {
+            limits.check_field(&path, options.skip_rows + index + 2, field)?;
+        }
+        let value = |column: usize| {
+            row.get(column)
+                .map(|f| f.trim().to_owned())
+                .unwrap_or_default()
+        };
+        let field = |field: usize| {
+            format.transforms.fields()[field]
+                .iter()
+                .fold(value(positions[field]), |value, transform| {
+                    transform.apply(&value)
+                })
+        };
+        let extra = headers
+            .iter()
+            .enumerate()
+            .filter(|(column, _)| !positions.contains(column))
+            .map(|(column, header)| (header.clone(), FieldValue(value(column))))
+            .collect();
+        records.push(Record::RecordMapped(RecordMapped {
+            nachname: field(0),
+            vorname: field(1),
+            klasse: field(2),
+            import_id: field(3),
+            extra,
+            id: RecordId {
+                nummer: records.len() + 1,
+                zeile: options.skip_rows + index + 2,
+            },
+        }));
+    }
+    Ok(records)
+}
+
+/// Bricht ab, wenn die Eingabe eine von isit erzeugte IServ-Datei ist, aber als anderer
+/// Datensatztyp gelesen werden soll, z.B. die Ausgabe des letzten Laufs als SchILD-Export.
+pub fn refuse_own_output(
+    path: &Path,
+    record_type: Option<RecordType>,
+    header: &[String],
+) -> Result<(), IsitError> {
+    if record_type != Some(RecordType::Iserv) && header.iter().any(|h| h.trim() == "Import-ID") {
+        return Err(own_output_error(path, record_type));
+    }
+    Ok(())
+}
+
+pub fn own_output_error(path: &Path, record_type: Option<RecordType>) -> IsitError {
+    let expected = match record_type {
+        Some(record_type) => format!(
+            "kein Export vom Typ {}",
+            record_type.to_possible_value().unwrap().get_name()
+        ),
+        None => "kein Export aus der Schulverwaltung".to_owned(),
+    };
+    let message = format!(
+        "Die Eingabe ist eine IServ-Importdatei, wie isit sie schreibt, und {}. Ist --file-path \
+         die Ausgabe eines früheren Laufs? Zum bewussten Weiterverarbeiten -r iserv angeben.",
+        expected
+    );
+    IsitError::input(path, message)
+}
+
+/// Der angegebene Datensatztyp oder, ohne Angabe, der an der Kopfzeile erkannte.
+pub fn resolve_record_type(
+    path: &Path,
+    record_type: Option<RecordType>,
+    header: &[String],
+) -> Result<RecordType, IsitError> {
+    refuse_own_output(path, record_type, header)?;
+    if record_type == Some(RecordType::Custom) {
+        return Err(IsitError::Usage(
+            "--record-type custom braucht eine Zuordnungsdatei (--mapping).".to_owned(),
+        ));
+    }
+    if let Some(record_type) = record_type {
+        return Ok(record_type);
+    }
+    // Eine leere Datei hat keine Kopfzeile; sie wird später als leere Eingabe gemeldet.
+    if header.iter().all(|h| h.trim().is_empty()) {
+        return Ok(RecordType::Schild);
+    }
+    match RecordType::detect(header) {
+        Some(detected) => {
+            info!(
+                "Datensatztyp {} an der Kopfzeile erkannt.",
+                detected.to_possible_value().unwrap().get_name()
+            );
+            Ok(detected)
+        }
+        None => Err(IsitError::input(
+            path,
+            format!(
+                "Datensatztyp nicht an der Kopfzeile erkennbar ({}), bitte mit --record-type \
+                 angeben, siehe isit list-formats.",
+                header.join(";")
+            ),
+        )),
+    }
+}
+
+pub fn get_all_csv_records_in_file(
+    path: PathBuf,
+    record_type: Option<RecordType>,
+    options: &ReadOptions,
+    rejects: &mut Vec<Reject>,
+) -> Result<Vec<Record>, IsitError> {
+    let text = read_decoded(path.clone(), options)?;
+    parse_csv_records(&text, &path, record_type, options, rejects)
+}
+
+/// Liest die Datensätze aus bereits dekodiertem Text; `path` dient nur den Fehlermeldungen.
+pub fn parse_csv_records(
+    text: &str,
+    path: &Path,
+    record_type: Option<RecordType>,
+    options: &ReadOptions,
+    rejects: &mut Vec<Reject>,
+) -> Result<Vec<Record>, IsitError> {
+    let limits = &options.limits;
+    let mut records: Vec<Record> = Vec::new();
+    let is_stamped = text.starts_with("# isit ");
+    let text = skip_lines(text, options.skip_rows);
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter.resolve(text))
+        .comment((record_type == Some(RecordType::Iserv)).then_some(b'#'))
+        .from_reader(text.as_bytes());
+    let headers = rdr.headers().map_err(IsitError::csv(path))?.clone();
+    if is_stamped && record_type != Some(RecordType::Iserv) {
+        return Err(own_output_error(path, record_type));
+    }
+    let header: Vec<String> = headers.iter().map(|h| h.to_owned()).collect();
+    let record_type = resolve_record_type(path, record_type, &header)?;
+    for result in rdr.records() {
+        let raw = match result {
+            Ok(raw) => raw,
+            // Zeilen mit falscher Spaltenzahl, der Leser macht danach mit der nächsten weiter.
+            Err(e) => {
+                let zeile = e
+                    .position()
+                    .map(|p| options.skip_rows + p.line() as usize)
+                    .unwrap_or_default();
+                options
+                    .on_error
+                    .handle(IsitError::csv(path)(e), zeile, String::new(), rejects)?;
+                continue;
+            }
+        };
+        limits.check_row_count(path, records.len() + 1)?;
+        let row = raw
+            .position()
+            .map(|p| options.skip_rows + p.line() as usize)
+            .unwrap_or_default();
+        for field in raw.iter() {
+            limits.check_field(path, row, field)?;
+        }
+        let record = match record_type {
+            RecordType::Schild => raw.deserialize(Some(&headers)).map(Record::RecordSchild),
+            RecordType::Gastschueler => raw
+                .deserialize(Some(&headers))
+                .map(Record::RecordGastschueler),
+            RecordType::Lehrer => raw.deserialize(Some(&headers)).map(Record::RecordLehrer),
+            RecordType::Logineo => raw.deserialize(Some(&headers)).map(Record::RecordLogineo),
+            RecordType::Asv => raw.deserialize(Some(&headers)).map(Record::RecordAsv),
+            RecordType::Lusd => raw.deserialize(Some(&headers)).map(Record::RecordLusd),
+            RecordType::WebUntis => raw.deserialize(Some(&headers)).map(Record::RecordWebUntis),
+            RecordType::Iserv => raw.deserialize(Some(&headers)).map(Record::RecordIserv),
+            RecordType::Custom => unreachable!("resolve_record_type lehnt custom ab"),
+        };
+        let mut record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let inhalt: Vec<&str> = raw.iter().collect();
+                options
+                    .on_error
+                    .handle(IsitError::csv(path)(e), row, inhalt.join(";"), rejects)?;
+                continue;
+            }
+        };
+        record.set_id(RecordId {
+            nummer: records.len() + 1,
+            zeile: row,
+        });
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Datum des Exports: aus einer Spalte "Exportdatum" oder "Stand", wie sie manche
+/// SchILD-Exporte enthalten, sonst das Änderungsdatum der Datei.
+pub fn export_date(path: &Path, records: &[Record]) -> String {
+    let embedded = records.first().and_then(|record| {
+        ["Exportdatum", "Stand"]
+            .iter()
+            .find_map(|column| record.extra_field(column))
+            .and_then(|field| parse_date(&field.0))
+    });
+    if let Some(date) = embedded {
+        return date;
+    }
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or_else(|_| SystemTime::now());
+    let secs = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    format_date(secs)
+}
+
+/// Liest "TT.MM.JJJJ" oder "JJJJ-MM-TT", gefolgt von einer beliebigen Uhrzeit.
+pub fn parse_date(value: &str) -> Option<String> {
+    let date = value.split_whitespace().next()?;
+    let parts: Vec<u32> = date
+        .split(['.', '-'])
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (year, month, day) = match (date.contains('.'), parts.as_slice()) {
+        (true, [day, month, year]) => (*year, *month, *day),
+        (false, [year, month, day]) => (*year, *month, *day),
+        _ => return None,
+    };
+    if year < 1000 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+/// Formatiert Sekunden seit 1970 als Datum (UTC), nach Howard Hinnants `civil_from_days`.
+pub fn format_date(secs: u64) -> String {
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHILD: &str = include_str!("../res/selftest/schild.csv");
+    const GASTSCHUELER: &str = include_str!("../res/selftest/gastschueler.csv");
+
+    #[test]
+    fn delimiter_is_detected() {
+        assert_eq!(detect_delimiter(SCHILD), b';');
+        assert_eq!(detect_delimiter(GASTSCHUELER), b';');
+        assert_eq!(detect_delimiter(&SCHILD.replace(';', ",")), b',');
+        assert_eq!(detect_delimiter(&SCHILD.replace(';', "\t")), b'\t');
+    }
+
+    #[test]
+    fn teacher_email_is_read_from_the_dienst_e_mail_column() {
+        let text = "Nachname;Vorname;Kürzel;Dienst-E-Mail\n\
+                    Meier;Eva;MEI; eva.meier@schule.nrw.de \n\
+                    Schulz;Jan;SCZ;\n";
+        let records = parse_csv_records(
+            text,
+            Path::new("lehrer.csv"),
+            Some(RecordType::Lehrer),
+            &ReadOptions::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        let records: Vec<RecordIserv> = records.into_iter().map(RecordIserv::from).collect();
+        assert_eq!(records[0].rolle, Rolle::Lehrer);
+        assert_eq!(records[0].email.as_deref(), Some("eva.meier@schule.nrw.de"));
+        assert_eq!(records[1].email, None);
+    }
+
+    fn assert_detected(bytes: &[u8], expected: &'static encoding_rs::Encoding) {
+        let detected = detect_encoding(bytes);
+        assert_eq!(detected, expected);
+        assert_eq!(decode(bytes, detected).unwrap(), SCHILD);
+    }
+
+    #[test]
+    fn utf8_is_detected() {
+        assert_detected(SCHILD.as_bytes(), UTF_8);
+    }
+
+    #[test]
+    fn utf16_is_detected_by_its_bom() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(SCHILD.encode_utf16().flat_map(u16::to_le_bytes));
+        assert_detected(&bytes, encoding_rs::UTF_16LE);
+    }
+
+    #[test]
+    fn windows_1252_is_the_fallback() {
+        let (bytes, _, _) = WINDOWS_1252.encode(SCHILD);
+        assert_detected(&bytes, WINDOWS_1252);
+    }
+}
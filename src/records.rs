@@ -0,0 +1,945 @@
+//! Datensätze der Eingabeformate und der IServ-Import-Datensatz.
+
+use clap::ValueEnum;
+use log::warn;
+use serde::de::{Deserializer, Visitor};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::transliterate::*;
+
+pub const WORDLIST: &str = include_str!("../res/words.txt");
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Deserialize)]
+pub enum Record {
+    RecordSchild(RecordSchild),
+    RecordGastschueler(RecordGastschueler),
+    RecordLehrer(RecordLehrer),
+    RecordLogineo(RecordLogineo),
+    RecordAsv(RecordAsv),
+    RecordLusd(RecordLusd),
+    RecordWebUntis(RecordWebUntis),
+    #[serde(skip)]
+    RecordMapped(RecordMapped),
+    RecordIserv(RecordIserv),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum RecordType {
+    Schild,
+    Gastschueler,
+    /// Lehrerexport aus SchILD-NRW.
+    Lehrer,
+    /// Schülerexport aus WebUntis.
+    #[clap(name = "webuntis")]
+    WebUntis,
+    /// Schülerexport aus LUSD (Hessen).
+    Lusd,
+    /// Schülerexport aus ASV-BW (Baden-Württemberg).
+    Asv,
+    /// Schülerliste aus LOGINEO NRW.
+    Logineo,
+    /// Eine von isit erzeugte IServ-Importdatei, z.B. um sie erneut aufzuteilen.
+    Iserv,
+    /// Beliebiger Export, dessen Spalten eine Zuordnungsdatei (--mapping) beschreibt.
+    Custom,
+}
+
+/// Beschreibung eines Eingabeformats, aus der `isit list-formats` erzeugt wird.
+pub struct FormatInfo {
+    pub description: &'static str,
+    pub required_columns: &'static [&'static str],
+    pub class_normalization: &'static str,
+    pub example: &'static str,
+}
+
+impl RecordType {
+    /// Ob alle Pflichtspalten des Typs in der Kopfzeile stehen.
+    pub fn matches_header(&self, header: &[String]) -> bool {
+        self.format_info()
+            .required_columns
+            .iter()
+            .all(|column| header.iter().any(|h| h.trim() == *column))
+    }
+
+    /// Der Exporttyp, dessen Pflichtspalten alle in der Kopfzeile stehen. IServ-Dateien werden
+    /// nie erkannt, sie müssen ausdrücklich mit `-r iserv` gelesen werden, ebenso wenig
+    /// `custom`.
+    pub fn detect(header: &[String]) -> Option<RecordType> {
+        RecordType::value_variants()
+            .iter()
+            .copied()
+            .filter(|record_type| !matches!(record_type, RecordType::Iserv | RecordType::Custom))
+            .find(|record_type| record_type.matches_header(header))
+    }
+
+    pub fn format_info(&self) -> FormatInfo {
+        match self {
+            RecordType::Schild => FormatInfo {
+                description: "Schülerexport aus SchILD-NRW",
+                required_columns: &["Nachname", "Vorname", "Klasse", "eindeutige Nummer (GUID)"],
+                class_normalization:
+                    "Klassen der Oberstufe (11*, 12*, 13*) werden zur Jahrgangsstufe",
+                example: "isit -f schild.csv -r schild -e windows",
+            },
+            RecordType::Gastschueler => FormatInfo {
+                description: "Gastschülerliste mit \"Nachname, Vorname (G)\" in einer Spalte",
+                required_columns: &["NAME, VORNAME", "KLASSE", "SCHÜLERNR"],
+                class_normalization: "keine, die Klasse wird übernommen",
+                example: "isit -f gastschueler.xlsx -t excel",
+            },
+            RecordType::Lehrer => FormatInfo {
+                description: "Lehrerexport aus SchILD-NRW, das Kürzel wird zur Import-ID",
+                required_columns: &["Nachname", "Vorname", "Kürzel"],
+                class_normalization: "keine, Lehrkräfte bekommen keine Klasse",
+                example: "isit -f lehrer.csv -r lehrer -o import_lehrer.csv",
+            },
+            RecordType::WebUntis => FormatInfo {
+                description: "Schülerexport aus WebUntis",
+                required_columns: &["Familienname", "Vorname", "Klasse", "Schülernummer"],
+                class_normalization: "wie bei SchILD, Oberstufenklassen werden zur Jahrgangsstufe",
+                example: "isit -f webuntis.csv -r webuntis",
+            },
+            RecordType::Lusd => FormatInfo {
+                description: "Schülerexport aus der LUSD (Hessen)",
+                required_columns: &["Nachname", "Vorname", "Schüler-ID", "Klassenbezeichnung"],
+                class_normalization: "keine, Oberstufenkurse wie E1 oder Q3 werden übernommen",
+                example: "isit -f lusd.csv -r lusd -e windows",
+            },
+            RecordType::Asv => FormatInfo {
+                description: "Schülerexport aus ASV-BW (Baden-Württemberg)",
+                required_columns: &["Familienname", "Rufname", "Klasse", "Lokale ID"],
+                class_normalization: "keine",
+                example: "isit -f asv.csv -r asv -e windows",
+            },
+            RecordType::Logineo => FormatInfo {
+                description: "Schülerliste aus LOGINEO NRW",
+                required_columns: &["Nachname", "Vorname", "Lerngruppe", "ID"],
+                class_normalization: "\"Klasse 05a\" wird zu 5a, \"Jahrgangsstufe Q1\" zu Q1, 11-13 werden zusammengefasst",
+                example: "isit -f logineo.csv -r logineo",
+            },
+            RecordType::Iserv => FormatInfo {
+                description:
+                    "IServ-Importdatei, wie isit sie schreibt; Passwörter bleiben erhalten",
+                required_columns: &["Nachname", "Vorname", "Klasse", "Import-ID"],
+                class_normalization: "wie bei der ursprünglichen Umwandlung",
+                example: "isit -f import_iserv_ready.csv -r iserv --split-by-class klassen",
+            },
+            RecordType::Custom => FormatInfo {
+                description:
+                    "Eigene Spaltenzuordnung, wie in formats.d/ mit optionalen Umformungen",
+                required_columns: &[],
+                class_normalization: "wie bei den übrigen Exporten",
+                example: "isit -f export.csv -r custom --mapping zuordnung.toml",
+            },
+        }
+    }
+}
+
+/// Inhalt einer beliebigen Spalte, egal ob CSV oder Excel sie als Zahl, Text oder leer liefert.
+/// Text wird dabei von umgebenden Leerzeichen befreit.
+#[derive(Debug, Default)]
+pub struct FieldValue(pub String);
+
+impl<'de> Deserialize<'de> for FieldValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldValueVisitor;
+
+        impl<'de> Visitor<'de> for FieldValueVisitor {
+            type Value = FieldValue;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a cell value")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(FieldValue(v.trim().to_owned()))
+            }
+
+            fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(FieldValue(v.to_string()))
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(FieldValue(v.to_string()))
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(FieldValue(v.to_string()))
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(FieldValue(v.to_string()))
+            }
+
+            fn visit_unit<E>(self) -> Result<Self::Value, E> {
+                Ok(FieldValue::default())
+            }
+        }
+
+        deserializer.deserialize_any(FieldValueVisitor)
+    }
+}
+
+/// Für `deserialize_with`: übernimmt Textfelder ohne führende und folgende Leerzeichen,
+/// die Excel-Exporte gern mitliefern.
+pub fn trimmed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    FieldValue::deserialize(deserializer).map(|value| value.0)
+}
+
+impl FieldValue {
+    /// Liest den Wert als Ja/Nein-Angabe, siehe [`parse_flag`].
+    pub fn as_flag(&self) -> Option<bool> {
+        parse_flag(&self.0)
+    }
+}
+
+/// Versteht die Schreibweisen, mit denen Quellsysteme Ja/Nein-Spalten füllen:
+/// "ja/nein", "x/leer", "1/0", "wahr/falsch" und "true/false". Unbekannte Werte ergeben `None`.
+pub fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "ja" | "j" | "yes" | "y" | "x" | "1" | "wahr" | "true" => Some(true),
+        "nein" | "n" | "no" | "" | "-" | "0" | "falsch" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+impl Record {
+    /// Liefert den Wert einer Spalte, die nicht fest zum Format gehört.
+    pub fn extra_field(&self, column: &str) -> Option<&FieldValue> {
+        let extra = match self {
+            Record::RecordSchild(record) => &record.extra,
+            Record::RecordGastschueler(record) => &record.extra,
+            Record::RecordLehrer(record) => &record.extra,
+            Record::RecordLogineo(record) => &record.extra,
+            Record::RecordAsv(record) => &record.extra,
+            Record::RecordLusd(record) => &record.extra,
+            Record::RecordWebUntis(record) => &record.extra,
+            Record::RecordMapped(record) => &record.extra,
+            Record::RecordIserv(_) => return None,
+        };
+        extra.get(column)
+    }
+
+    pub fn id(&self) -> RecordId {
+        match self {
+            Record::RecordSchild(record) => record.id,
+            Record::RecordGastschueler(record) => record.id,
+            Record::RecordLehrer(record) => record.id,
+            Record::RecordLogineo(record) => record.id,
+            Record::RecordAsv(record) => record.id,
+            Record::RecordLusd(record) => record.id,
+            Record::RecordWebUntis(record) => record.id,
+            Record::RecordMapped(record) => record.id,
+            Record::RecordIserv(record) => record.id,
+        }
+    }
+
+    pub fn set_id(&mut self, id: RecordId) {
+        match self {
+            Record::RecordSchild(record) => record.id = id,
+            Record::RecordGastschueler(record) => record.id = id,
+            Record::RecordLehrer(record) => record.id = id,
+            Record::RecordLogineo(record) => record.id = id,
+            Record::RecordAsv(record) => record.id = id,
+            Record::RecordLusd(record) => record.id = id,
+            Record::RecordWebUntis(record) => record.id = id,
+            Record::RecordMapped(record) => record.id = id,
+            Record::RecordIserv(record) => record.id = id,
+        }
+    }
+
+    /// Klasse, wie sie in der Eingabe steht.
+    pub fn klasse(&self) -> &str {
+        match self {
+            Record::RecordSchild(record) => &record.klasse,
+            Record::RecordGastschueler(record) => &record.klasse,
+            Record::RecordLehrer(_) => "",
+            Record::RecordLogineo(record) => &record.lerngruppe,
+            Record::RecordAsv(record) => &record.klasse,
+            Record::RecordLusd(record) => &record.klasse,
+            Record::RecordWebUntis(record) => &record.klasse,
+            Record::RecordMapped(record) => &record.klasse,
+            Record::RecordIserv(record) => &record.klasse,
+        }
+    }
+
+    pub fn has_consent(&self, column: &str, value: Option<&str>) -> bool {
+        let field = match self.extra_field(column) {
+            Some(field) => field,
+            None => return false,
+        };
+        match value {
+            Some(value) => field.0.trim().eq_ignore_ascii_case(value.trim()),
+            None => match field.as_flag() {
+                Some(consent) => consent,
+                None => {
+                    warn!(
+                        "Datensatz {}: Unbekannter Wert \"{}\" in Spalte \"{}\", werte als keine \
+                         Einwilligung.",
+                        self.id(),
+                        field.0,
+                        column
+                    );
+                    false
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordSchild {
+    #[serde(deserialize_with = "trimmed")]
+    pub nachname: String,
+    #[serde(deserialize_with = "trimmed")]
+    pub vorname: String,
+    #[serde(deserialize_with = "trimmed")]
+    pub klasse: String,
+    #[serde(rename = "eindeutige Nummer (GUID)", deserialize_with = "trimmed")]
+    pub guid: String,
+    /// Namenszusatz wie "von" oder "zu", steht in SchILD in einer eigenen Spalte.
+    #[serde(default, deserialize_with = "trimmed")]
+    pub namenszusatz: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, FieldValue>,
+    #[serde(skip)]
+    pub id: RecordId,
+}
+
+/// Datensatz eines Formats aus formats.d/.
+#[derive(Debug)]
+pub struct RecordMapped {
+    pub nachname: String,
+    pub vorname: String,
+    pub klasse: String,
+    pub import_id: String,
+    pub extra: HashMap<String, FieldValue>,
+    pub id: RecordId,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct RecordGastschueler {
+    #[serde(rename = "NAME, VORNAME", deserialize_with = "trimmed")]
+    pub name: String,
+    #[serde(deserialize_with = "trimmed")]
+    pub klasse: String,
+    #[serde(rename = "SCHÜLERNR", deserialize_with = "trimmed")]
+    pub schuelernr: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, FieldValue>,
+    #[serde(skip)]
+    pub id: RecordId,
+}
+
+/// Lehrkraft aus dem SchILD-Lehrerexport. Die Dienst-E-Mail ist optional, weitere Spalten
+/// bleiben als Zusatzspalten erhalten.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordLehrer {
+    #[serde(deserialize_with = "trimmed")]
+    pub nachname: String,
+    #[serde(deserialize_with = "trimmed")]
+    pub vorname: String,
+    #[serde(rename = "Kürzel", deserialize_with = "trimmed")]
+    pub kuerzel: String,
+    #[serde(
+        rename = "Dienst-E-Mail",
+        alias = "E-Mail",
+        default,
+        deserialize_with = "trimmed"
+    )]
+    pub email: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, FieldValue>,
+    #[serde(skip)]
+    pub id: RecordId,
+}
+
+/// Schüler aus dem WebUntis-Export (Stammdaten → Schüler → Export).
+#[derive(Debug, Deserialize)]
+pub struct RecordWebUntis {
+    #[serde(rename = "Familienname", deserialize_with = "trimmed")]
+    pub nachname: String,
+    #[serde(rename = "Vorname", deserialize_with = "trimmed")]
+    pub vorname: String,
+    #[serde(rename = "Klasse", deserialize_with = "trimmed")]
+    pub klasse: String,
+    #[serde(rename = "Schülernummer", deserialize_with = "trimmed")]
+    pub schuelernummer: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, FieldValue>,
+    #[serde(skip)]
+    pub id: RecordId,
+}
+
+/// Schüler aus dem Schülerexport der hessischen LUSD.
+#[derive(Debug, Deserialize)]
+pub struct RecordLusd {
+    #[serde(rename = "Nachname", deserialize_with = "trimmed")]
+    pub nachname: String,
+    #[serde(rename = "Vorname", deserialize_with = "trimmed")]
+    pub vorname: String,
+    #[serde(rename = "Klassenbezeichnung", deserialize_with = "trimmed")]
+    pub klasse: String,
+    #[serde(rename = "Schüler-ID", deserialize_with = "trimmed")]
+    pub schueler_id: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, FieldValue>,
+    #[serde(skip)]
+    pub id: RecordId,
+}
+
+/// Schüler aus dem CSV-Export von ASV-BW. Statt einer GUID gibt es nur die lokale ID der Schule.
+#[derive(Debug, Deserialize)]
+pub struct RecordAsv {
+    #[serde(rename = "Familienname", deserialize_with = "trimmed")]
+    pub nachname: String,
+    #[serde(rename = "Rufname", deserialize_with = "trimmed")]
+    pub vorname: String,
+    #[serde(rename = "Klasse", deserialize_with = "trimmed")]
+    pub klasse: String,
+    #[serde(rename = "Lokale ID", deserialize_with = "trimmed")]
+    pub lokale_id: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, FieldValue>,
+    #[serde(skip)]
+    pub id: RecordId,
+}
+
+/// Schüler aus einer LOGINEO-NRW-Liste. Die Lerngruppe heißt dort z.B. "Klasse 05a" oder
+/// "Jahrgangsstufe EF".
+#[derive(Debug, Deserialize)]
+pub struct RecordLogineo {
+    #[serde(rename = "Nachname", deserialize_with = "trimmed")]
+    pub nachname: String,
+    #[serde(rename = "Vorname", deserialize_with = "trimmed")]
+    pub vorname: String,
+    #[serde(rename = "Lerngruppe", deserialize_with = "trimmed")]
+    pub lerngruppe: String,
+    #[serde(rename = "ID", deserialize_with = "trimmed")]
+    pub logineo_id: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, FieldValue>,
+    #[serde(skip)]
+    pub id: RecordId,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordIserv {
+    pub nachname: String,
+    pub vorname: String,
+    pub klasse: String,
+    #[serde(rename = "Import-ID")]
+    pub import_id: String,
+    #[serde(default)]
+    pub password: String,
+    /// Zweiter Vorname, der nur mit --include-middle-names in den Vornamen übernommen wird.
+    #[serde(skip)]
+    pub zusatzname: Option<String>,
+    /// Namenszusatz, der mit --name-suffix-policy separate-column eine eigene Spalte bekommt.
+    #[serde(default, skip_serializing)]
+    pub namenszusatz: Option<String>,
+    #[serde(skip)]
+    pub id: RecordId,
+    /// Entropie des Passworts in Bit, wenn isit es erzeugt hat.
+    #[serde(skip)]
+    pub entropie: Option<f64>,
+    /// Schüler oder Lehrkraft, je nach Datensatztyp der Eingabe.
+    #[serde(skip)]
+    pub rolle: Rolle,
+    /// Dienst-E-Mail einer Lehrkraft.
+    #[serde(skip)]
+    pub email: Option<String>,
+}
+
+/// Rolle eines Kontos in IServ; Lehrkräfte und Schüler werden oft mit verschiedenen
+/// Importprofilen angelegt.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rolle {
+    #[default]
+    Schueler,
+    Lehrer,
+}
+
+impl Rolle {
+    /// Kennung für Dateinamen, z.B. "import_lehrer.csv".
+    pub fn name(self) -> &'static str {
+        match self {
+            Rolle::Schueler => "schueler",
+            Rolle::Lehrer => "lehrer",
+        }
+    }
+}
+
+/// Wohin ein Namenszusatz ("von", "zu") aus der Eingabe übernommen wird.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum NameSuffixPolicy {
+    /// Vor den Nachnamen setzen ("von Müller").
+    PrependSurname,
+    /// In eine eigene Spalte "Namenszusatz" schreiben.
+    SeparateColumn,
+    /// Weglassen.
+    Drop,
+}
+
+impl NameSuffixPolicy {
+    pub fn apply(&self, record: &mut RecordIserv) {
+        match self {
+            NameSuffixPolicy::PrependSurname => {
+                if let Some(zusatz) = record.namenszusatz.take() {
+                    record.nachname = format!("{} {}", zusatz, record.nachname);
+                }
+            }
+            NameSuffixPolicy::SeparateColumn => (),
+            NameSuffixPolicy::Drop => record.namenszusatz = None,
+        }
+    }
+}
+
+/// Laufweite Kennung eines Eingabedatensatzes, mit der sich Meldungen aus Einlesen, Prüfung
+/// und Ausgabe demselben Datensatz zuordnen lassen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecordId {
+    pub nummer: usize,
+    /// Zeile in der Eingabedatei, beginnend bei 1.
+    pub zeile: usize,
+}
+
+impl fmt::Display for RecordId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#R{} (Zeile {})", self.nummer, self.zeile)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordExcluded {
+    pub nachname: String,
+    pub vorname: String,
+    pub klasse: String,
+    #[serde(rename = "Import-ID")]
+    pub import_id: String,
+}
+
+impl From<RecordIserv> for RecordExcluded {
+    fn from(record: RecordIserv) -> Self {
+        RecordExcluded {
+            nachname: record.nachname,
+            vorname: record.vorname,
+            klasse: record.klasse,
+            import_id: record.import_id,
+        }
+    }
+}
+
+impl RecordIserv {
+    /// Neuer Datensatz ohne Passwort; das vergibt später [`generate_passwords`] nach den
+    /// Passwort-Optionen des Laufs.
+    ///
+    /// [`generate_passwords`]: crate::password::generate_passwords
+    pub fn new(nachname: String, vorname: String, klasse: String, import_id: String) -> Self {
+        RecordIserv {
+            nachname,
+            vorname,
+            klasse,
+            import_id,
+            password: String::new(),
+            zusatzname: None,
+            namenszusatz: None,
+            id: RecordId::default(),
+            entropie: None,
+            rolle: Rolle::Schueler,
+            email: None,
+        }
+    }
+}
+
+/// Fasst Klassen der Oberstufe (11*, 12*, 13*) zur Jahrgangsstufe zusammen.
+pub fn oberstufe(klasse: String) -> String {
+    if klasse.starts_with("11") {
+        "11".to_string()
+    } else if klasse.starts_with("12") {
+        "12".to_string()
+    } else if klasse.starts_with("13") {
+        "13".to_string()
+    } else {
+        klasse
+    }
+}
+
+impl From<RecordSchild> for RecordIserv {
+    fn from(record: RecordSchild) -> Self {
+        let klasse = oberstufe(record.klasse);
+        let mut iserv = RecordIserv::new(record.nachname, record.vorname, klasse, record.guid);
+        iserv.namenszusatz = Some(record.namenszusatz).filter(|zusatz| !zusatz.is_empty());
+        iserv
+    }
+}
+
+impl From<RecordGastschueler> for RecordIserv {
+    fn from(record: RecordGastschueler) -> Self {
+        // "*Nachname, Vorname (G)"; fehlt der Vorname, bleibt er leer.
+        let (nachname, vorname) = record.name.split_once(',').unwrap_or((&record.name, ""));
+        let nachname = nachname.trim().trim_start_matches('*').trim().to_owned();
+        let vorname: String = vorname.split(" (G)").collect();
+        let (vorname, zusatzname) = split_middle_name(&vorname);
+        let mut iserv = RecordIserv::new(nachname, vorname, record.klasse, record.schuelernr);
+        iserv.zusatzname = zusatzname;
+        iserv
+    }
+}
+
+impl From<RecordLehrer> for RecordIserv {
+    fn from(record: RecordLehrer) -> Self {
+        let mut iserv = RecordIserv::new(
+            record.nachname,
+            record.vorname,
+            String::new(),
+            record.kuerzel,
+        );
+        iserv.rolle = Rolle::Lehrer;
+        iserv.email = Some(record.email).filter(|email| !email.is_empty());
+        iserv
+    }
+}
+
+impl From<RecordWebUntis> for RecordIserv {
+    fn from(record: RecordWebUntis) -> Self {
+        RecordIserv::new(
+            record.nachname,
+            record.vorname,
+            oberstufe(record.klasse),
+            record.schuelernummer,
+        )
+    }
+}
+
+impl From<RecordLusd> for RecordIserv {
+    fn from(record: RecordLusd) -> Self {
+        RecordIserv::new(
+            record.nachname,
+            record.vorname,
+            record.klasse,
+            record.schueler_id,
+        )
+    }
+}
+
+impl From<RecordAsv> for RecordIserv {
+    fn from(record: RecordAsv) -> Self {
+        RecordIserv::new(
+            record.nachname,
+            record.vorname,
+            record.klasse,
+            record.lokale_id,
+        )
+    }
+}
+
+/// Klassenname wie in SchILD aus einer LOGINEO-Lerngruppe: Vorsilbe und führende Null weg.
+pub fn logineo_klasse(lerngruppe: &str) -> String {
+    let klasse = ["Klasse ", "Jahrgangsstufe ", "Stufe "]
+        .iter()
+        .find_map(|prefix| lerngruppe.strip_prefix(prefix))
+        .unwrap_or(lerngruppe)
+        .trim();
+    let klasse = match klasse.strip_prefix('0') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => rest,
+        _ => klasse,
+    };
+    oberstufe(klasse.to_string())
+}
+
+impl From<RecordLogineo> for RecordIserv {
+    fn from(record: RecordLogineo) -> Self {
+        let klasse = logineo_klasse(&record.lerngruppe);
+        RecordIserv::new(record.nachname, record.vorname, klasse, record.logineo_id)
+    }
+}
+
+/// Trennt einen zweiten Vornamen ab, der in Klammern ("Anna (Maria)") oder nach einem
+/// Schrägstrich ("Anna/Maria") steht.
+pub fn split_middle_name(vorname: &str) -> (String, Option<String>) {
+    let split = vorname.split_once('/').or_else(|| {
+        vorname
+            .strip_suffix(')')
+            .and_then(|rest| rest.rsplit_once('('))
+    });
+    match split {
+        Some((vorname, zusatz)) if !vorname.trim().is_empty() && !zusatz.trim().is_empty() => {
+            (vorname.trim().to_owned(), Some(zusatz.trim().to_owned()))
+        }
+        _ => (vorname.trim().to_owned(), None),
+    }
+}
+
+impl From<Record> for RecordIserv {
+    fn from(record: Record) -> Self {
+        let id = record.id();
+        let mut iserv: RecordIserv = match record {
+            Record::RecordSchild(record) => record.into(),
+            Record::RecordGastschueler(record) => record.into(),
+            Record::RecordLehrer(record) => record.into(),
+            Record::RecordLogineo(record) => record.into(),
+            Record::RecordAsv(record) => record.into(),
+            Record::RecordLusd(record) => record.into(),
+            Record::RecordWebUntis(record) => record.into(),
+            Record::RecordMapped(record) => RecordIserv::new(
+                record.nachname,
+                record.vorname,
+                record.klasse,
+                record.import_id,
+            ),
+            Record::RecordIserv(record) => record,
+        };
+        iserv.id = id;
+        iserv
+    }
+}
+
+/// Was mit Apostrophen ("O'Brien") und Leerzeichen ("De la Cruz") in Benutzernamen geschieht,
+/// denn IServ lehnt beide in Logins ab.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum NameSeparator {
+    /// Zeichen entfernen ("obrien", "delacruz").
+    Remove,
+    /// Durch einen Bindestrich ersetzen ("o-brien", "de-la-cruz").
+    Hyphen,
+}
+
+impl NameSeparator {
+    fn apply(&self, username: &mut String) {
+        if *self == NameSeparator::Hyphen && !username.ends_with(['-', '.']) && !username.is_empty()
+        {
+            username.push('-');
+        }
+    }
+}
+
+/// Zeichen, die in Namen als Apostroph vorkommen ("O'Brien", "O’Brien").
+const APOSTROPHES: [char; 5] = ['\'', '’', '‘', '`', '´'];
+
+/// Wie Umlaute und ß in Benutzernamen geschrieben werden. Manche IServ-Installationen
+/// erlauben sie in Logins, andere nicht.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum UmlautPolicy {
+    /// Wie im Deutschen umschreiben ("jörg.weiß" → "joerg.weiss").
+    #[default]
+    AeOeUe,
+    /// Nur die Punkte weglassen ("jorg.weiss").
+    Strip,
+    /// Unverändert übernehmen ("jörg.weiß").
+    Keep,
+}
+
+impl UmlautPolicy {
+    /// Schreibt ein Umlaut oder ß nach dieser Regel an; `false`, wenn die Umschrift zuständig ist.
+    fn apply(self, c: char, username: &mut String) -> bool {
+        match (self, c) {
+            (UmlautPolicy::AeOeUe, _) => return false,
+            (UmlautPolicy::Keep, c) => username.push(c),
+            (UmlautPolicy::Strip, 'ä') => username.push('a'),
+            (UmlautPolicy::Strip, 'ö') => username.push('o'),
+            (UmlautPolicy::Strip, 'ü') => username.push('u'),
+            (UmlautPolicy::Strip, _) => username.push_str("ss"),
+        }
+        true
+    }
+}
+
+/// Regeln, nach denen aus Vor- und Nachnamen ein Benutzername wird.
+#[derive(Clone)]
+pub struct UsernameOptions {
+    pub transliterator: Transliterator,
+    pub apostrophe: NameSeparator,
+    pub space: NameSeparator,
+    pub umlauts: UmlautPolicy,
+    /// Höchstlänge in Zeichen, z.B. für Systeme, die lange Logins abschneiden.
+    pub max_length: Option<usize>,
+}
+
+impl Default for UsernameOptions {
+    fn default() -> Self {
+        UsernameOptions {
+            transliterator: Transliterator::default(),
+            apostrophe: NameSeparator::Remove,
+            space: NameSeparator::Hyphen,
+            umlauts: UmlautPolicy::AeOeUe,
+            max_length: None,
+        }
+    }
+}
+
+/// Account-Name so, wie IServ ihn beim Import standardmäßig bildet: "vorname.nachname" in
+/// Kleinbuchstaben, andere Schriften umschrieben, Umlaute, Apostrophe und Leerzeichen nach
+/// `options` behandelt, andere Zeichen entfernt.
+pub fn iserv_username(vorname: &str, nachname: &str, options: &UsernameOptions) -> String {
+    let mut username = String::new();
+    for c in format!("{}.{}", vorname.trim(), nachname.trim()).chars() {
+        let c = c.to_lowercase().next().unwrap_or(c);
+        match c {
+            c if APOSTROPHES.contains(&c) => options.apostrophe.apply(&mut username),
+            ' ' => options.space.apply(&mut username),
+            '-' => username.push('-'),
+            c @ ('a'..='z' | '0'..='9' | '.') => username.push(c),
+            c @ ('ä' | 'ö' | 'ü' | 'ß') if options.umlauts.apply(c, &mut username) => {}
+            c => {
+                if let Some(ascii) = options.transliterator.transliterate(c) {
+                    username.extend(
+                        ascii
+                            .chars()
+                            .filter(char::is_ascii_alphanumeric)
+                            .map(|c| c.to_ascii_lowercase()),
+                    );
+                }
+            }
+        }
+    }
+    shorten_username(&username, options.max_length)
+}
+
+/// Ein Name mit Apostrophen und Leerzeichen nach `options`, sonst unverändert, für Vorlagen, die
+/// aus Namen eine E-Mail-Adresse oder Import-ID bilden ("De la Cruz" → "De-la-Cruz").
+pub fn login_name(name: &str, options: &UsernameOptions) -> String {
+    let mut login = String::new();
+    for c in name.trim().chars() {
+        match c {
+            c if APOSTROPHES.contains(&c) => options.apostrophe.apply(&mut login),
+            ' ' => options.space.apply(&mut login),
+            c => login.push(c),
+        }
+    }
+    login
+}
+
+/// Kürzt einen Benutzernamen auf `max_length` Zeichen: zuerst den Vornamen bis auf einen
+/// Buchstaben, dann den Nachnamen von hinten ("annalena.wagenknecht-niederhausen" →
+/// "a.wagenknecht-nieder" bei 20). So ergibt derselbe Name immer dasselbe Kürzel.
+pub fn shorten_username(username: &str, max_length: Option<usize>) -> String {
+    let length = username.chars().count();
+    let max_length = match max_length {
+        Some(max_length) if length > max_length => max_length,
+        _ => return username.to_owned(),
+    };
+    let cut = |part: &str, keep: usize| {
+        let part: String = part.chars().take(keep).collect();
+        part.trim_end_matches('-').to_owned()
+    };
+    let Some((vorname, nachname)) = username.split_once('.') else {
+        return cut(username, max_length);
+    };
+    let excess = length - max_length;
+    let vorname_length = vorname.chars().count();
+    let from_vorname = excess.min(vorname_length.saturating_sub(1));
+    let nachname_length = nachname.chars().count();
+    let nachname_keep = nachname_length.saturating_sub(excess - from_vorname);
+    let vorname = cut(vorname, vorname_length - from_vorname);
+    if nachname_keep == 0 {
+        return cut(&vorname, max_length);
+    }
+    format!("{}.{}", vorname, cut(nachname, nachname_keep))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn username(nachname: &str, apostrophe: NameSeparator, space: NameSeparator) -> String {
+        let options = UsernameOptions {
+            apostrophe,
+            space,
+            ..UsernameOptions::default()
+        };
+        iserv_username("Sean", nachname, &options)
+    }
+
+    #[test]
+    fn umlauts_follow_the_policy() {
+        let username = |umlauts| {
+            let options = UsernameOptions {
+                umlauts,
+                ..UsernameOptions::default()
+            };
+            iserv_username("Jörg Ünal", "Weiß-Öztürk", &options)
+        };
+        assert_eq!(username(UmlautPolicy::AeOeUe), "joerg-uenal.weiss-oeztuerk");
+        assert_eq!(username(UmlautPolicy::Strip), "jorg-unal.weiss-ozturk");
+        assert_eq!(username(UmlautPolicy::Keep), "jörg-ünal.weiß-öztürk");
+    }
+
+    #[test]
+    fn long_usernames_are_shortened_first_name_first() {
+        let username = |max_length| {
+            let options = UsernameOptions {
+                max_length,
+                ..UsernameOptions::default()
+            };
+            iserv_username("Annalena", "Wagenknecht-Niederhausen", &options)
+        };
+        assert_eq!(username(None), "annalena.wagenknecht-niederhausen");
+        assert_eq!(username(Some(33)), "annalena.wagenknecht-niederhausen");
+        assert_eq!(username(Some(30)), "annal.wagenknecht-niederhausen");
+        assert_eq!(username(Some(20)), "a.wagenknecht-nieder");
+        assert_eq!(username(Some(14)), "a.wagenknecht");
+        assert_eq!(username(Some(1)), "a");
+    }
+
+    #[test]
+    fn apostrophes_are_removed_or_replaced() {
+        let (remove, hyphen) = (NameSeparator::Remove, NameSeparator::Hyphen);
+        assert_eq!(username("O'Brien", remove, hyphen), "sean.obrien");
+        assert_eq!(username("O'Brien", hyphen, hyphen), "sean.o-brien");
+        assert_eq!(username("O’Brien", remove, hyphen), "sean.obrien");
+    }
+
+    #[test]
+    fn spaces_are_removed_or_replaced() {
+        let (remove, hyphen) = (NameSeparator::Remove, NameSeparator::Hyphen);
+        assert_eq!(username("De la Cruz", remove, hyphen), "sean.de-la-cruz");
+        assert_eq!(username("De la Cruz", remove, remove), "sean.delacruz");
+    }
+
+    #[test]
+    fn adjacent_separators_give_one_hyphen() {
+        let hyphen = NameSeparator::Hyphen;
+        assert_eq!(username("D' Angelo", hyphen, hyphen), "sean.d-angelo");
+    }
+
+    fn gastschueler(name: &str) -> RecordIserv {
+        RecordIserv::from(RecordGastschueler {
+            name: name.to_owned(),
+            klasse: "8a".to_owned(),
+            schuelernr: "4711".to_owned(),
+            extra: HashMap::new(),
+            id: RecordId::default(),
+        })
+    }
+
+    #[test]
+    fn gastschueler_names_are_split_without_panicking() {
+        let names = |name| {
+            let record = gastschueler(name);
+            (record.nachname, record.vorname, record.zusatzname)
+        };
+        let name = |nachname: &str, vorname: &str| (nachname.to_owned(), vorname.to_owned(), None);
+        assert_eq!(names("*Meier, Tom (G)"), name("Meier", "Tom"));
+        assert_eq!(
+            names("*Meier, Anna (Maria) (G)"),
+            (
+                "Meier".to_owned(),
+                "Anna".to_owned(),
+                Some("Maria".to_owned())
+            )
+        );
+        assert_eq!(names("Öztürk,Ayşe"), name("Öztürk", "Ayşe"));
+        assert_eq!(names("*Meier"), name("Meier", ""));
+        assert_eq!(names(", Tom"), name("", "Tom"));
+        assert_eq!(names(""), name("", ""));
+    }
+}
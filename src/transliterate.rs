@@ -0,0 +1,253 @@
+//! Umschrift von Namen in ASCII, z.B. für Benutzernamen.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::IsitError;
+use crate::read::*;
+use crate::records::*;
+
+/// Ein Verfahren, das einzelne Zeichen (in Kleinschreibung) in ASCII umschreibt.
+pub trait Transliterate: Send + Sync {
+    /// Umschrift des Zeichens; `None`, wenn das Verfahren das Zeichen nicht kennt.
+    fn transliterate(&self, c: char) -> Option<&str>;
+}
+
+/// Eingebaute Umschrift: Umlaute wie im Deutschen, sonstige lateinische Buchstaben ohne
+/// diakritische Zeichen, Kyrillisch nach ICAO 9303 (wie im Reisepass) und Arabisch vereinfacht
+/// nach DIN 31635 ohne Vokalzeichen.
+pub struct Builtin;
+
+impl Transliterate for Builtin {
+    fn transliterate(&self, c: char) -> Option<&str> {
+        let ascii = match c {
+            'ä' => "ae",
+            'ö' => "oe",
+            'ü' => "ue",
+            'ß' => "ss",
+            // Lateinisch, u.a. Französisch, Polnisch, Tschechisch und Türkisch.
+            'à' | 'á' | 'â' | 'ã' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'æ' => "ae",
+            'ç' | 'č' | 'ć' => "c",
+            'ď' | 'đ' => "d",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+            'ğ' => "g",
+            'ì' | 'í' | 'î' | 'ï' | 'ı' | 'ī' | 'į' => "i",
+            'ł' | 'ľ' | 'ĺ' => "l",
+            'ñ' | 'ń' | 'ň' => "n",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ø' | 'ő' | 'ō' => "o",
+            'œ' => "oe",
+            'ř' | 'ŕ' => "r",
+            'š' | 'ś' | 'ş' | 'ș' => "s",
+            'ť' | 'ţ' | 'ț' => "t",
+            'ù' | 'ú' | 'û' | 'ů' | 'ű' | 'ū' | 'ų' => "u",
+            'ý' | 'ÿ' => "y",
+            'ž' | 'ź' | 'ż' => "z",
+            // Kyrillisch.
+            'а' => "a",
+            'б' => "b",
+            'в' => "v",
+            'г' => "g",
+            'ґ' => "g",
+            'д' => "d",
+            'е' | 'ё' | 'є' | 'э' => "e",
+            'ж' => "zh",
+            'з' => "z",
+            'и' | 'і' | 'ї' | 'й' => "i",
+            'к' => "k",
+            'л' => "l",
+            'м' => "m",
+            'н' => "n",
+            'о' => "o",
+            'п' => "p",
+            'р' => "r",
+            'с' => "s",
+            'т' => "t",
+            'у' => "u",
+            'ф' => "f",
+            'х' => "kh",
+            'ц' => "ts",
+            'ч' => "ch",
+            'ш' => "sh",
+            'щ' => "shch",
+            'ъ' => "ie",
+            'ы' => "y",
+            'ь' => "",
+            'ю' => "iu",
+            'я' => "ia",
+            // Arabisch.
+            'ا' | 'أ' | 'آ' | 'ى' | 'ة' => "a",
+            'إ' => "i",
+            'ب' => "b",
+            'ت' | 'ط' => "t",
+            'ث' => "th",
+            'ج' => "j",
+            'ح' | 'ه' => "h",
+            'خ' => "kh",
+            'د' | 'ض' => "d",
+            'ذ' => "dh",
+            'ر' => "r",
+            'ز' | 'ظ' => "z",
+            'س' | 'ص' => "s",
+            'ش' => "sh",
+            'غ' => "gh",
+            'ف' => "f",
+            'ق' => "q",
+            'ك' => "k",
+            'ل' => "l",
+            'م' => "m",
+            'ن' => "n",
+            'و' | 'ؤ' => "w",
+            'ي' | 'ئ' => "y",
+            'ع' | 'ء' => "",
+            _ => return None,
+        };
+        Some(ascii)
+    }
+}
+
+/// Umschrift aller übrigen Schriften mit any_ascii, z.B. Griechisch, Vietnamesisch, Persisch
+/// oder Chinesisch; als letztes Verfahren hinter der eingebauten Umschrift.
+pub struct AnyAscii;
+
+impl Transliterate for AnyAscii {
+    fn transliterate(&self, c: char) -> Option<&str> {
+        Some(any_ascii::any_ascii_char(c)).filter(|ascii| !ascii.is_empty())
+    }
+}
+
+/// Eigene Umschriften aus einer CSV-Datei mit Spalten Zeichen;Umschrift, z.B. "ş;sch", wenn
+/// eine Schule türkische Namen lieber lautgetreu schreibt. Sie gehen der eingebauten Umschrift vor.
+#[derive(Debug, Default)]
+pub struct OverrideTable(pub HashMap<char, String>);
+
+#[derive(Debug, Deserialize)]
+pub struct OverrideRule {
+    #[serde(rename = "Zeichen", deserialize_with = "trimmed")]
+    pub zeichen: String,
+    #[serde(rename = "Umschrift", deserialize_with = "trimmed")]
+    pub umschrift: String,
+}
+
+impl OverrideTable {
+    pub fn load(path: &Path, encoding: Encoding) -> Result<Self, IsitError> {
+        let options = ReadOptions {
+            encoding,
+            ..ReadOptions::default()
+        };
+        let text = read_decoded(path.to_path_buf(), &options)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_reader(text.as_bytes());
+        let mut table = HashMap::new();
+        for rule in reader.deserialize() {
+            let rule: OverrideRule = rule.map_err(IsitError::csv(path))?;
+            let mut chars = rule.zeichen.chars();
+            let c = match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => {
+                    return Err(IsitError::config(path)(format!(
+                        "\"{}\" ist nicht genau ein Zeichen.",
+                        rule.zeichen
+                    )))
+                }
+            };
+            if !rule.umschrift.is_ascii() {
+                return Err(IsitError::config(path)(format!(
+                    "Umschrift \"{}\" für \"{}\" ist kein ASCII.",
+                    rule.umschrift, rule.zeichen
+                )));
+            }
+            for lower in c.to_lowercase() {
+                table.insert(lower, rule.umschrift.to_lowercase());
+            }
+        }
+        Ok(OverrideTable(table))
+    }
+}
+
+impl Transliterate for OverrideTable {
+    fn transliterate(&self, c: char) -> Option<&str> {
+        self.0.get(&c).map(String::as_str)
+    }
+}
+
+/// Fragt die Verfahren der Reihe nach, das erste, das ein Zeichen kennt, gewinnt. Die Umschrift
+/// kann Großbuchstaben und Satzzeichen enthalten, der Aufrufer filtert sie.
+#[derive(Clone)]
+pub struct Transliterator {
+    pub backends: Vec<Arc<dyn Transliterate>>,
+}
+
+impl Default for Transliterator {
+    fn default() -> Self {
+        Transliterator {
+            backends: vec![Arc::new(Builtin), Arc::new(AnyAscii)],
+        }
+    }
+}
+
+impl Transliterator {
+    /// Eingebaute Umschrift und any_ascii, auf Wunsch mit einer Tabelle eigener Umschriften
+    /// davor.
+    pub fn with_overrides(overrides: Option<OverrideTable>) -> Self {
+        let mut transliterator = Transliterator::default();
+        if let Some(overrides) = overrides {
+            transliterator.backends.insert(0, Arc::new(overrides));
+        }
+        transliterator
+    }
+
+    pub fn transliterate(&self, c: char) -> Option<&str> {
+        self.backends
+            .iter()
+            .find_map(|backend| backend.transliterate(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn username(vorname: &str, nachname: &str) -> String {
+        iserv_username(vorname, nachname, &UsernameOptions::default())
+    }
+
+    #[test]
+    fn german_umlauts() {
+        assert_eq!(username("Jörg", "Weiß"), "joerg.weiss");
+    }
+
+    #[test]
+    fn cyrillic_follows_icao() {
+        assert_eq!(username("Алексей", "Щукин"), "aleksei.shchukin");
+    }
+
+    #[test]
+    fn turkish_letters() {
+        assert_eq!(username("Şükrü", "Yılmaz"), "suekrue.yilmaz");
+    }
+
+    #[test]
+    fn arabic_without_vowel_signs() {
+        assert_eq!(username("محمد", "حسن"), "mhmd.hsn");
+    }
+
+    #[test]
+    fn other_scripts_fall_back_to_any_ascii() {
+        assert_eq!(username("Νίκος", "Παπαδόπουλος"), "nikos.papadopoylos");
+        assert_eq!(username("Nguyễn", "Thị"), "nguyen.thi");
+        assert_eq!(username("پری", "چمران"), "pry.chmran");
+    }
+
+    #[test]
+    fn override_table_goes_first() {
+        let mut table = HashMap::new();
+        table.insert('ş', "sch".to_owned());
+        let transliterator = Transliterator::with_overrides(Some(OverrideTable(table)));
+        assert_eq!(transliterator.transliterate('ş'), Some("sch"));
+        assert_eq!(transliterator.transliterate('ü'), Some("ue"));
+    }
+}
@@ -0,0 +1,492 @@
+//! Prüfungen der gelesenen Datensätze vor dem Schreiben.
+
+use log::{error, info, warn};
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::error::IsitError;
+use crate::password::comparable_class;
+use crate::read::*;
+use crate::records::*;
+
+/// Zeichen, die IServ-Importprofile üblicherweise in der Import-ID zulassen.
+pub const DEFAULT_ID_REGEX: &str = r"^[A-Za-z0-9{}._-]+$";
+
+/// Namen mit Semikolon werden beim Schreiben in Anführungszeichen gesetzt. Das bleibt korrekt,
+/// aber nicht jedes Importprofil liest es richtig, deshalb wird darauf hingewiesen.
+pub fn warn_semicolons_in_names(records: &[RecordIserv]) {
+    let affected: Vec<String> = names_with_semicolons(records)
+        .iter()
+        .map(RecordId::to_string)
+        .collect();
+    if !affected.is_empty() {
+        warn!(
+            "Namen mit Semikolon, bitte den Import prüfen: {}",
+            affected.join(", ")
+        );
+    }
+}
+
+/// Die Datensätze, in deren Vor- oder Nachname ein Semikolon steht.
+pub fn names_with_semicolons(records: &[RecordIserv]) -> Vec<RecordId> {
+    records
+        .iter()
+        .filter(|r| r.nachname.contains(';') || r.vorname.contains(';'))
+        .map(|r| r.id)
+        .collect()
+}
+
+/// Die Lehrkräfte ohne gültige Dienst-E-Mail, jeweils mit dem Grund. Mit `domain` muss die
+/// Adresse in dieser Domain liegen, z.B. "schule.nrw.de".
+pub fn invalid_emails<'a>(
+    records: &'a [RecordIserv],
+    domain: Option<&str>,
+) -> Vec<(&'a RecordIserv, String)> {
+    let syntax =
+        Regex::new(r"^[^@\s]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)+$").expect("gültiger Ausdruck");
+    records
+        .iter()
+        .filter(|r| r.rolle == Rolle::Lehrer)
+        .filter_map(|r| {
+            let reason = match r.email.as_deref() {
+                None => "keine Dienst-E-Mail".to_owned(),
+                Some(email) if !syntax.is_match(email) => format!("\"{}\" ist ungültig", email),
+                Some(email) => match domain {
+                    Some(domain)
+                        if !email
+                            .rsplit_once('@')
+                            .is_some_and(|(_, host)| host.eq_ignore_ascii_case(domain)) =>
+                    {
+                        format!("\"{}\" liegt nicht in {}", email, domain)
+                    }
+                    _ => return None,
+                },
+            };
+            Some((r, reason))
+        })
+        .collect()
+}
+
+/// Der LDAP-Abgleich in IServ braucht die Dienst-E-Mail der Lehrkräfte. Fehlende oder ungültige
+/// Adressen werden gemeldet, mit `require` (--require-email) brechen sie den Lauf ab.
+pub fn check_emails(
+    records: &[RecordIserv],
+    domain: Option<&str>,
+    require: bool,
+) -> Result<(), IsitError> {
+    let invalid = invalid_emails(records, domain);
+    if invalid.is_empty() {
+        return Ok(());
+    }
+    let reasons: Vec<String> = invalid
+        .into_iter()
+        .map(|(r, reason)| format!("{} {} {}: {}", r.vorname, r.nachname, r.id, reason))
+        .collect();
+    let message = format!(
+        "Lehrkräfte ohne gültige Dienst-E-Mail: {}",
+        reasons.join("; ")
+    );
+    if require {
+        return Err(IsitError::conversion(message));
+    }
+    warn!("{}", message);
+    Ok(())
+}
+
+/// Viele verschiedene Klassen deuten auf eine falsch eingestellte Klassennormalisierung oder
+/// Regeldatei hin.
+pub fn check_class_count(
+    records: &[RecordIserv],
+    max_classes: usize,
+    strict: bool,
+) -> Result<(), IsitError> {
+    let classes: BTreeSet<&str> = records.iter().map(|r| r.klasse.as_str()).collect();
+    if classes.len() <= max_classes {
+        return Ok(());
+    }
+    let message = format!(
+        "Die Ausgabe enthält {} verschiedene Klassen (erlaubt: {}). Passen --class-style und \
+         --class-rules zur Eingabe?",
+        classes.len(),
+        max_classes
+    );
+    if strict {
+        return Err(IsitError::conversion(message));
+    }
+    warn!("{}", message);
+    Ok(())
+}
+
+/// Prüft alle Import-IDs. Mit `--on-error skip` werden ungültige Datensätze entfernt und als
+/// abgelehnt vermerkt, sonst führen sie zum Abbruch.
+pub fn validate_import_ids(
+    records: &mut Vec<RecordIserv>,
+    id_regex: &Regex,
+    max_length: usize,
+    on_error: OnError,
+    rejects: &mut Vec<Reject>,
+) -> Result<(), IsitError> {
+    let mut invalid = 0;
+    let mut valid = Vec::with_capacity(records.len());
+    for record in records.drain(..) {
+        let problem = if record.import_id.chars().count() > max_length {
+            format!("ist länger als {} Zeichen", max_length)
+        } else if !id_regex.is_match(&record.import_id) {
+            format!("entspricht nicht dem Muster {}", id_regex)
+        } else {
+            valid.push(record);
+            continue;
+        };
+        if on_error == OnError::Skip {
+            let inhalt = format!(
+                "{};{};{};{}",
+                record.nachname, record.vorname, record.klasse, record.import_id
+            );
+            let error = format!("Import-ID \"{}\" {}", record.import_id, problem);
+            on_error.handle(
+                IsitError::conversion(error),
+                record.id.zeile,
+                inhalt,
+                rejects,
+            )?;
+            continue;
+        }
+        invalid += 1;
+        error!(
+            "Datensatz {}, {} {}: Import-ID \"{}\" {}.",
+            record.id, record.vorname, record.nachname, record.import_id, problem
+        );
+    }
+    *records = valid;
+    if invalid > 0 {
+        return Err(IsitError::conversion(format!(
+            "{} ungültige Import-IDs gefunden, es wurde keine Datei geschrieben.",
+            invalid
+        )));
+    }
+    Ok(())
+}
+
+/// Vergleichsschlüssel für Gastschüler und SchILD-Datensätze: Name und Klasse ohne Rücksicht
+/// auf Groß- und Kleinschreibung, die Klasse auch ohne führende Nullen.
+fn name_and_class(record: &RecordIserv) -> (String, String, String) {
+    let comparable = |s: &str| s.trim().to_lowercase();
+    (
+        comparable(&record.nachname),
+        comparable(&record.vorname),
+        comparable_class(&record.klasse),
+    )
+}
+
+/// Gibt Gastschülern, die mit einer anderen ID auch im SchILD-Export stehen, die SchILD-GUID,
+/// damit [`merge_duplicate_ids`] sie zu einem Datensatz zusammenführt. Erkannt werden sie an
+/// Nachname, Vorname und Klasse; passen mehrere SchILD-Datensätze, bleibt der Gastschüler
+/// unverändert. `origin` nennt den Datensatztyp eines Datensatzes.
+///
+/// isit führt keine Historie, deshalb wird jede ersetzte Gastschüler-ID gemeldet: Ein Konto,
+/// das IServ unter ihr angelegt hat, muss von Hand übernommen oder gelöscht werden. Zurück
+/// kommen die Paare aus Gastschüler-ID und SchILD-GUID.
+pub fn reconcile_guests(
+    records: &mut [RecordIserv],
+    origin: impl Fn(&RecordId) -> Option<RecordType>,
+) -> Vec<(String, String)> {
+    let mut schild: HashMap<(String, String, String), Vec<String>> = HashMap::new();
+    for record in records
+        .iter()
+        .filter(|r| origin(&r.id) == Some(RecordType::Schild))
+    {
+        schild
+            .entry(name_and_class(record))
+            .or_default()
+            .push(record.import_id.clone());
+    }
+    let mut aliases = Vec::new();
+    for record in records
+        .iter_mut()
+        .filter(|r| origin(&r.id) == Some(RecordType::Gastschueler))
+    {
+        let guids = match schild.get(&name_and_class(record)) {
+            Some(guids) => guids,
+            None => continue,
+        };
+        if guids.contains(&record.import_id) {
+            continue;
+        }
+        if guids.len() > 1 {
+            warn!(
+                "Gastschüler {} {} ({}) passt zu {} SchILD-Datensätzen und wird nicht \
+                 zugeordnet.",
+                record.vorname,
+                record.nachname,
+                record.klasse,
+                guids.len()
+            );
+            continue;
+        }
+        warn!(
+            "Gastschüler {} {} ({}) steht auch im SchILD-Export: ID \"{}\" wird durch die \
+             SchILD-GUID \"{}\" ersetzt. Ein IServ-Konto mit der alten ID bitte von Hand \
+             übernehmen oder löschen.",
+            record.vorname, record.nachname, record.klasse, record.import_id, guids[0]
+        );
+        let alias = std::mem::replace(&mut record.import_id, guids[0].clone());
+        aliases.push((alias, guids[0].clone()));
+    }
+    if !aliases.is_empty() {
+        info!(
+            "{} Gastschüler dem SchILD-Export zugeordnet.",
+            aliases.len()
+        );
+    }
+    aliases
+}
+
+/// Wie viele Angaben ein Datensatz enthält; beim Zusammenführen gewinnt der reichere.
+fn richness(record: &RecordIserv) -> usize {
+    [
+        &record.nachname,
+        &record.vorname,
+        &record.klasse,
+        &record.password,
+    ]
+    .iter()
+    .filter(|field| !field.is_empty())
+    .count()
+        + usize::from(record.namenszusatz.is_some())
+        + usize::from(record.zusatzname.is_some())
+}
+
+/// Führt Datensätze mit derselben Import-ID zusammen, z.B. Schüler, die im SchILD-Export und in
+/// der Gastschülerliste stehen. Behalten wird der Datensatz mit den meisten Angaben an der
+/// Stelle des ersten; widersprechen sich Name oder Klasse, wird gewarnt. `source` nennt die
+/// Eingabedatei eines Datensatzes.
+///
+/// Innerhalb einer Datei ist eine doppelte Import-ID ein Fehler im Export, z.B. nach einem
+/// Klassenwechsel während des Exports. Die verworfene Zeile kommt dann mit der behaltenen in
+/// Grund zu den abgelehnten Zeilen.
+pub fn merge_duplicate_ids(
+    records: &mut Vec<RecordIserv>,
+    source: impl Fn(&RecordId) -> String,
+    rejects: &mut Vec<Reject>,
+) {
+    let mut merged: Vec<RecordIserv> = Vec::with_capacity(records.len());
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut duplicates = 0;
+    for record in records.drain(..) {
+        let kept = match index.get(&record.import_id) {
+            Some(&kept) => &mut merged[kept],
+            None => {
+                index.insert(record.import_id.clone(), merged.len());
+                merged.push(record);
+                continue;
+            }
+        };
+        duplicates += 1;
+        let conflicts: Vec<&str> = [
+            ("Nachname", &kept.nachname, &record.nachname),
+            ("Vorname", &kept.vorname, &record.vorname),
+            ("Klasse", &kept.klasse, &record.klasse),
+        ]
+        .iter()
+        .filter(|(_, a, b)| !a.is_empty() && !b.is_empty() && a != b)
+        .map(|(field, _, _)| *field)
+        .collect();
+        let richer = richness(&record) > richness(kept);
+        let (winner, loser) = if richer {
+            (&record, &*kept)
+        } else {
+            (&*kept, &record)
+        };
+        if source(&winner.id) == source(&loser.id) {
+            let mut grund = format!(
+                "Import-ID \"{}\" doppelt in {}, behalten wird Zeile {}",
+                record.import_id,
+                source(&winner.id),
+                winner.id.zeile
+            );
+            if !conflicts.is_empty() {
+                grund.push_str(&format!(" (abweichend: {})", conflicts.join(", ")));
+            }
+            warn!("Zeile {} wird übersprungen: {}", loser.id.zeile, grund);
+            rejects.push(Reject {
+                zeile: loser.id.zeile,
+                grund,
+                inhalt: format!(
+                    "{};{};{};{}",
+                    loser.nachname, loser.vorname, loser.klasse, loser.import_id
+                ),
+            });
+        } else if !conflicts.is_empty() {
+            warn!(
+                "Import-ID \"{}\" steht mehrfach in der Eingabe mit abweichenden Angaben ({}): \
+                 übernommen \"{} {} ({})\" aus {} Zeile {}, verworfen \"{} {} ({})\" aus {} \
+                 Zeile {}.",
+                record.import_id,
+                conflicts.join(", "),
+                winner.vorname,
+                winner.nachname,
+                winner.klasse,
+                source(&winner.id),
+                winner.id.zeile,
+                loser.vorname,
+                loser.nachname,
+                loser.klasse,
+                source(&loser.id),
+                loser.id.zeile
+            );
+        }
+        if richer {
+            *kept = record;
+        }
+    }
+    if duplicates > 0 {
+        info!(
+            "{} doppelte Import-IDs zusammengeführt, {} Datensätze bleiben.",
+            duplicates,
+            merged.len()
+        );
+    }
+    *records = merged;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(import_id: &str, klasse: &str, nummer: usize, zeile: usize) -> RecordIserv {
+        let mut record = RecordIserv::new(
+            "Meier".to_owned(),
+            "Tom".to_owned(),
+            klasse.to_owned(),
+            import_id.to_owned(),
+        );
+        record.id = RecordId { nummer, zeile };
+        record
+    }
+
+    #[test]
+    fn semicolons_in_names_are_listed() {
+        let mut records = vec![
+            record("4711", "8a", 1, 2),
+            record("4712", "8a", 2, 3),
+            record("4713", "8a", 3, 4),
+        ];
+        records[1].nachname = "Nguyen;Tran".to_owned();
+        records[2].vorname = "Anh;Thi".to_owned();
+        let lines: Vec<usize> = names_with_semicolons(&records)
+            .iter()
+            .map(|id| id.zeile)
+            .collect();
+        assert_eq!(lines, [3, 4]);
+    }
+
+    fn teacher(kuerzel: &str, email: Option<&str>, zeile: usize) -> RecordIserv {
+        let mut record = record(kuerzel, "", 1, zeile);
+        record.rolle = Rolle::Lehrer;
+        record.email = email.map(str::to_owned);
+        record
+    }
+
+    #[test]
+    fn teacher_emails_are_checked_for_syntax_and_domain() {
+        let records = vec![
+            teacher("MEI", Some("tom.meier@Schule.NRW.de"), 2),
+            teacher("SCZ", None, 3),
+            teacher("ABC", Some("tom meier@schule.nrw.de"), 4),
+            teacher("DEF", Some("tom.meier@gmail.com"), 5),
+            record("4711", "5a", 1, 6),
+        ];
+        let invalid = |domain| -> Vec<usize> {
+            invalid_emails(&records, domain)
+                .iter()
+                .map(|(r, _)| r.id.zeile)
+                .collect()
+        };
+        assert_eq!(invalid(None), [3, 4]);
+        assert_eq!(invalid(Some("schule.nrw.de")), [3, 4, 5]);
+        assert!(check_emails(&records, None, false).is_ok());
+        assert!(check_emails(&records, None, true).is_err());
+        assert!(check_emails(&records[..1], Some("schule.nrw.de"), true).is_ok());
+    }
+
+    #[test]
+    fn duplicate_in_one_file_is_rejected_with_its_line() {
+        let mut records = vec![
+            record("4711", "8a", 1, 2),
+            record("4712", "9c", 2, 3),
+            record("4711", "8b", 3, 7),
+        ];
+        let mut rejects = Vec::new();
+        merge_duplicate_ids(&mut records, |_| "schild.csv".to_owned(), &mut rejects);
+        let kept: Vec<(&str, &str)> = records
+            .iter()
+            .map(|r| (r.import_id.as_str(), r.klasse.as_str()))
+            .collect();
+        assert_eq!(kept, [("4711", "8a"), ("4712", "9c")]);
+        assert_eq!(rejects.len(), 1);
+        assert_eq!(rejects[0].zeile, 7);
+        assert!(rejects[0].grund.contains("schild.csv"));
+        assert!(rejects[0].grund.contains("Zeile 2"));
+        assert_eq!(rejects[0].inhalt, "Meier;Tom;8b;4711");
+    }
+
+    #[test]
+    fn guests_found_in_schild_take_the_schild_guid() {
+        let schild = record("{6F9619FF-8B86-D011-B42D-00C04FC964FF}", "8a", 1, 2);
+        let mut twins = record("{0A1B2C3D-0000-4000-8000-000000000001}", "9c", 2, 3);
+        twins.vorname = "Lea".to_owned();
+        let mut other_twin = twins.clone();
+        other_twin.import_id = "{0A1B2C3D-0000-4000-8000-000000000002}".to_owned();
+        other_twin.id = RecordId {
+            nummer: 3,
+            zeile: 4,
+        };
+        let mut guest = record("4711", "08A", 4, 2);
+        guest.nachname = "MEIER".to_owned();
+        let mut guest_twin = twins.clone();
+        guest_twin.import_id = "4712".to_owned();
+        guest_twin.id = RecordId {
+            nummer: 5,
+            zeile: 3,
+        };
+        let unknown = record("4713", "5b", 6, 4);
+        let mut records = vec![schild, twins, other_twin, guest, guest_twin, unknown];
+        let aliases = reconcile_guests(&mut records, |id| {
+            Some(if id.nummer <= 3 {
+                RecordType::Schild
+            } else {
+                RecordType::Gastschueler
+            })
+        });
+        assert_eq!(
+            aliases,
+            [(
+                "4711".to_owned(),
+                "{6F9619FF-8B86-D011-B42D-00C04FC964FF}".to_owned()
+            )]
+        );
+        assert_eq!(records[4].import_id, "4712");
+        assert_eq!(records[5].import_id, "4713");
+        let mut rejects = Vec::new();
+        merge_duplicate_ids(&mut records, |_| "eingabe".to_owned(), &mut rejects);
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0].klasse, "8a");
+    }
+
+    #[test]
+    fn duplicate_across_files_keeps_the_richer_record() {
+        let poorer = record("4711", "", 1, 2);
+        let richer = record("4711", "8a", 2, 2);
+        let mut records = vec![poorer, richer];
+        let mut rejects = Vec::new();
+        let sources = ["schild.csv", "gast.csv"];
+        merge_duplicate_ids(
+            &mut records,
+            |id| sources[id.nummer - 1].to_owned(),
+            &mut rejects,
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].klasse, "8a");
+        assert!(rejects.is_empty());
+    }
+}
@@ -0,0 +1,1722 @@
+//! Ausgabeformate, Manifest und das Schreiben der Dateien eines Laufs.
+
+use clap::ValueEnum;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{self, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::IsitError;
+use crate::letters::*;
+use crate::read::{detect_delimiter, is_stdio, read_decoded, ReadOptions};
+use crate::records::*;
+
+#[derive(Clone, Debug)]
+pub struct OutputSpec {
+    pub format: OutputFormat,
+    pub path: String,
+}
+
+pub fn parse_output_spec(arg: &str) -> Result<OutputSpec, String> {
+    if let Some((format, path)) = arg.split_once('=') {
+        if let Ok(format) = OutputFormat::from_str(format, true) {
+            return Ok(OutputSpec {
+                format,
+                path: path.to_owned(),
+            });
+        }
+    }
+    Ok(OutputSpec {
+        format: OutputFormat::Csv,
+        path: arg.to_owned(),
+    })
+}
+
+/// Eine Spalte der IServ-Datei für --column: Überschrift und Inhalt, in dem {nachname},
+/// {vorname}, {namenszusatz}, {klasse}, {import_id}, {passwort}, {benutzername}, {email}
+/// (Dienst-E-Mail von Lehrkräften) und {entropie} (Bit des neu erzeugten Passworts, sonst leer)
+/// ersetzt werden. Fester Text ohne Platzhalter ergibt eine Spalte mit gleichem Wert in jeder
+/// Zeile. Bildet die Spalte eine E-Mail-Adresse, Import-ID oder ein Login, werden Apostrophe und
+/// Leerzeichen in den Namen wie bei Benutzernamen behandelt.
+#[derive(Clone, Debug)]
+pub struct OutputColumn {
+    pub header: String,
+    pub value: String,
+}
+
+const COLUMN_FIELDS: [&str; 9] = [
+    "nachname",
+    "vorname",
+    "namenszusatz",
+    "klasse",
+    "import_id",
+    "passwort",
+    "benutzername",
+    "email",
+    "entropie",
+];
+
+/// "Überschrift=Inhalt", oder nur der Name eines Felds wie "Klasse", dann ist er zugleich die
+/// Überschrift.
+pub fn parse_output_column(arg: &str) -> Result<OutputColumn, String> {
+    let (header, value) = match arg.split_once('=') {
+        Some((header, value)) => (header.trim(), value.to_owned()),
+        None => {
+            let field = arg.trim().to_lowercase().replace('-', "_");
+            if !COLUMN_FIELDS.contains(&field.as_str()) {
+                return Err(format!(
+                    "\"{}\" ist kein Feld, möglich sind {}; sonst \"Überschrift=Inhalt\".",
+                    arg,
+                    COLUMN_FIELDS.join(", ")
+                ));
+            }
+            (arg.trim(), format!("{{{}}}", field))
+        }
+    };
+    if header.is_empty() {
+        return Err(format!("Spalte \"{}\" hat keine Überschrift.", arg));
+    }
+    let mut rest = value.as_str();
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Nicht geschlossener Platzhalter in \"{}\".", value))?;
+        let field = &rest[start + 1..start + end];
+        if !COLUMN_FIELDS.contains(&field) {
+            return Err(format!(
+                "Unbekannter Platzhalter {{{}}}, möglich sind {}.",
+                field,
+                COLUMN_FIELDS.join(", ")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(OutputColumn {
+        header: header.to_owned(),
+        value,
+    })
+}
+
+/// Überschriften von Spalten, die ein Login bilden, klein und mit "_" statt "-".
+const LOGIN_HEADERS: [&str; 8] = [
+    "email",
+    "e_mail",
+    "mail",
+    "import_id",
+    "importkennung",
+    "id",
+    "login",
+    "benutzername",
+];
+
+impl OutputColumn {
+    /// Ob die Spalte eine E-Mail-Adresse, Import-ID oder ein Login bildet: nach der Überschrift
+    /// oder, bei Adressen, nach dem "@" im Inhalt.
+    fn builds_login(&self) -> bool {
+        let header = self.header.to_lowercase().replace('-', "_");
+        LOGIN_HEADERS.contains(&header.as_str()) || self.value.contains('@')
+    }
+
+    /// Inhalt der Spalte für `record`; `benutzername` ist der schon eindeutig vergebene Name.
+    pub fn fill(
+        &self,
+        record: &RecordIserv,
+        benutzername: &str,
+        username: &UsernameOptions,
+    ) -> String {
+        let name = |name: &str| match self.builds_login() {
+            true => login_name(name, username),
+            false => name.to_owned(),
+        };
+        self.value
+            .replace("{nachname}", &name(&record.nachname))
+            .replace("{vorname}", &name(&record.vorname))
+            .replace(
+                "{namenszusatz}",
+                &name(record.namenszusatz.as_deref().unwrap_or_default()),
+            )
+            .replace("{klasse}", &record.klasse)
+            .replace("{import_id}", &record.import_id)
+            .replace("{email}", record.email.as_deref().unwrap_or_default())
+            .replace("{passwort}", &record.password)
+            .replace(
+                "{entropie}",
+                &record
+                    .entropie
+                    .map(|bits| format!("{:.1}", bits))
+                    .unwrap_or_default(),
+            )
+            .replace("{benutzername}", benutzername)
+    }
+}
+
+/// Eine Zeile mit den Spalten aus --column. Einen Benutzernamen vergibt sie nur, wenn eine Spalte
+/// ihn enthält, dann aber wie die übrigen Ausgaben eindeutig.
+fn column_row(
+    columns: &[OutputColumn],
+    record: &RecordIserv,
+    username: &UsernameOptions,
+    usernames: &mut UniqueUsernames,
+) -> Vec<String> {
+    let benutzername = if columns
+        .iter()
+        .any(|column| column.value.contains("{benutzername}"))
+    {
+        usernames.claim(
+            iserv_username(&record.vorname, &record.nachname, username),
+            username.max_length,
+        )
+    } else {
+        String::new()
+    };
+    columns
+        .iter()
+        .map(|column| column.fill(record, &benutzername, username))
+        .collect()
+}
+
+/// Einstellungen, die für alle geschriebenen Dateien gelten.
+#[derive(Default)]
+pub struct OutputOptions {
+    /// Kommentarzeile, die vor die Kopfzeile geschrieben wird.
+    pub stamp: Option<String>,
+    pub line_ending: LineEnding,
+    pub encoding: OutputEncoding,
+    pub delimiter: OutputDelimiter,
+    pub quoting: Quoting,
+    /// IServ-Dateien mit eigener Spalte für den Namenszusatz schreiben.
+    pub name_suffix_column: bool,
+    /// Wie die Benutzernamen in der Klassenliste gebildet werden.
+    pub username: UsernameOptions,
+    pub ldap: LdapOptions,
+    /// Vorlage der Passwortbriefe.
+    pub letter_template: LetterTemplate,
+    /// Datum des Exports für {export_date} in den Passwortbriefen.
+    pub export_date: String,
+    /// Spalten der IServ-Datei (CSV und Excel) statt Nachname;Vorname;Klasse;Import-ID;Password.
+    pub columns: Vec<OutputColumn>,
+}
+
+/// Einstellungen für die LDIF-Ausgabe.
+#[derive(Clone, Debug, Default)]
+pub struct LdapOptions {
+    /// DN, unter dem die Einträge angelegt werden, z.B. "ou=schueler,dc=schule,dc=de".
+    pub base_dn: Option<String>,
+    pub object_classes: Vec<String>,
+}
+
+/// Zeilenende der geschriebenen Dateien.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    /// Windows-Zeilenenden, für IServ-Instanzen, die reine LF-Dateien ablehnen.
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub fn terminator(&self) -> csv::Terminator {
+        match self {
+            LineEnding::Lf => csv::Terminator::Any(b'\n'),
+            LineEnding::Crlf => csv::Terminator::CRLF,
+        }
+    }
+}
+
+/// Trennzeichen der geschriebenen CSV-Dateien; IServ erwartet `;`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OutputDelimiter {
+    #[default]
+    Semicolon,
+    Comma,
+    Tab,
+}
+
+impl OutputDelimiter {
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            OutputDelimiter::Semicolon => b';',
+            OutputDelimiter::Comma => b',',
+            OutputDelimiter::Tab => b'\t',
+        }
+    }
+}
+
+/// Wann Felder der geschriebenen CSV-Dateien in Anführungszeichen stehen.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Quoting {
+    /// Nur Felder mit Trennzeichen, Anführungszeichen oder Zeilenumbruch.
+    #[default]
+    Necessary,
+    Always,
+    /// Alle Felder, die keine Zahl sind.
+    NonNumeric,
+    /// Nie; nur für Systeme, die keine Anführungszeichen verstehen, ein Feld mit Trennzeichen
+    /// zerfällt dann in zwei.
+    Never,
+}
+
+impl Quoting {
+    pub fn style(&self) -> csv::QuoteStyle {
+        match self {
+            Quoting::Necessary => csv::QuoteStyle::Necessary,
+            Quoting::Always => csv::QuoteStyle::Always,
+            Quoting::NonNumeric => csv::QuoteStyle::NonNumeric,
+            Quoting::Never => csv::QuoteStyle::Never,
+        }
+    }
+}
+
+/// Zeichenkodierung der geschriebenen CSV-Dateien.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OutputEncoding {
+    #[default]
+    Utf8,
+    /// Für Excel im Sekretariat, das UTF-8 ohne Nachfrage als ANSI öffnet und Umlaute zerlegt.
+    Windows1252,
+}
+
+/// Datei, in die der CSV-Writer UTF-8 schreibt und die bei Windows-1252 beim Schreiben
+/// umkodiert. Zeichen, die es in Windows-1252 nicht gibt, werden zu "?" und gesammelt.
+pub struct EncodedFile {
+    pub file: File,
+    pub encoding: OutputEncoding,
+    /// Angefangenes UTF-8-Zeichen vom Ende des letzten Aufrufs.
+    pub pending: Vec<u8>,
+    pub unmappable: BTreeSet<char>,
+}
+
+impl EncodedFile {
+    pub fn new(file: File, encoding: OutputEncoding) -> Self {
+        EncodedFile {
+            file,
+            encoding,
+            pending: Vec::new(),
+            unmappable: BTreeSet::new(),
+        }
+    }
+}
+
+impl Write for EncodedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding == OutputEncoding::Utf8 {
+            return self.file.write(buf);
+        }
+        self.pending.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let text = std::str::from_utf8(&self.pending[..valid]).expect("gültiges UTF-8");
+        let mut encoded = Vec::with_capacity(valid);
+        for c in text.chars() {
+            let mut buffer = [0u8; 4];
+            let (bytes, _, unmappable) =
+                encoding_rs::WINDOWS_1252.encode(c.encode_utf8(&mut buffer));
+            if unmappable {
+                self.unmappable.insert(c);
+                encoded.push(b'?');
+            } else {
+                encoded.extend_from_slice(&bytes);
+            }
+        }
+        self.file.write_all(&encoded)?;
+        self.pending.drain(..valid);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Eintrag im Manifest: eine erzeugte Datei und wann sie gelöscht werden muss.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ManifestEntry {
+    pub datei: String,
+    pub erstellt: u64,
+    pub ablauf: u64,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub optionen: String,
+    /// Die Einstellungen hinter `optionen` als Optionen für die Kommandozeile.
+    #[serde(default)]
+    pub einstellungen: String,
+    #[serde(default)]
+    pub exportdatum: String,
+}
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const MANIFEST_EXTENSION: &str = "manifest";
+
+/// IServ-Datensatz mit eigener Spalte für den Namenszusatz.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordIservWithSuffix<'a> {
+    pub nachname: &'a str,
+    pub namenszusatz: &'a str,
+    pub vorname: &'a str,
+    pub klasse: &'a str,
+    #[serde(rename = "Import-ID")]
+    pub import_id: &'a str,
+    pub password: &'a str,
+}
+
+/// Ziel, in das die fertigen IServ-Datensätze geschrieben werden.
+pub trait OutputSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError>;
+    /// Schließt die Ausgabe ab, danach ist sie vollständig geschrieben.
+    fn finish(&mut self) -> Result<(), IsitError>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OutputFormat {
+    /// IServ-Importdatei.
+    Csv,
+    /// Zugangsliste zum Austeilen: Klasse, Name und Passwort.
+    Credentials,
+    /// Klassenliste mit Zugangsdaten für Unterrichtssoftware (Klasse;Name;Username;Passwort).
+    Classroom,
+    /// IServ-Spalten als Excel-Arbeitsmappe, alle Zellen als Text.
+    Xlsx,
+    /// JSON-Array mit einem Objekt je Konto, für eigene Skripte.
+    Json,
+    /// LDIF-Einträge zum Anlegen der Konten direkt im LDAP, siehe --ldap-base-dn.
+    Ldif,
+    /// CSV für den Nutzerupload von Moodle (username,firstname,lastname,password,cohort1).
+    Moodle,
+    /// Passwortbriefe als PDF, eine Seite je Schüler, siehe --letter-template.
+    Letters,
+}
+
+pub fn open_sink(
+    format: OutputFormat,
+    path: String,
+    options: &OutputOptions,
+) -> Result<Box<dyn OutputSink>, IsitError> {
+    match format {
+        OutputFormat::Csv if !options.columns.is_empty() => {
+            let mut csv = CsvSink::create(path, options)?;
+            csv.write_record(options.columns.iter().map(|column| &column.header))?;
+            Ok(Box::new(ColumnsSink {
+                csv,
+                columns: options.columns.clone(),
+                username: options.username.clone(),
+                usernames: UniqueUsernames::default(),
+            }))
+        }
+        OutputFormat::Csv => Ok(Box::new(CsvSink::create(path, options)?)),
+        OutputFormat::Credentials => Ok(Box::new(CredentialsSink(CsvSink::create(path, options)?))),
+        OutputFormat::Classroom => Ok(Box::new(ClassroomSink {
+            csv: CsvSink::create(path, options)?,
+            rows: Vec::new(),
+            username: options.username.clone(),
+            usernames: UniqueUsernames::default(),
+        })),
+        OutputFormat::Xlsx => Ok(Box::new(XlsxSink {
+            path,
+            rows: Vec::new(),
+            name_suffix_column: options.name_suffix_column,
+            columns: options.columns.clone(),
+            username: options.username.clone(),
+            usernames: UniqueUsernames::default(),
+        })),
+        OutputFormat::Json => Ok(Box::new(JsonSink::create(path, options)?)),
+        OutputFormat::Ldif => Ok(Box::new(LdifSink::create(path, options)?)),
+        // Moodle kennt keine Kommentarzeilen, daher ohne Stempel.
+        OutputFormat::Moodle => Ok(Box::new(MoodleSink {
+            csv: CsvSink::create_with(path, options, b',', None)?,
+            username: options.username.clone(),
+            usernames: UniqueUsernames::default(),
+        })),
+        OutputFormat::Letters => Ok(Box::new(LettersSink {
+            path,
+            letters: Vec::new(),
+            template: options.letter_template.clone(),
+            export_date: options.export_date.clone(),
+            username: options.username.clone(),
+            usernames: UniqueUsernames::default(),
+        })),
+    }
+}
+
+/// Schreibt eine CSV-Datei, mit `;` als Trenner, wenn --output-delimiter nichts anderes sagt.
+pub struct CsvSink {
+    pub path: String,
+    pub writer: Option<csv::Writer<EncodedFile>>,
+    pub name_suffix_column: bool,
+}
+
+impl CsvSink {
+    pub fn create(path: String, options: &OutputOptions) -> Result<Self, IsitError> {
+        CsvSink::create_with(
+            path,
+            options,
+            options.delimiter.as_byte(),
+            options.stamp.as_deref(),
+        )
+    }
+
+    /// Wie `create`, aber mit eigenem Trenner und ohne oder mit anderer Kommentarzeile, für
+    /// Dateien, die andere Programme als IServ lesen.
+    pub fn create_with(
+        path: String,
+        options: &OutputOptions,
+        delimiter: u8,
+        stamp: Option<&str>,
+    ) -> Result<Self, IsitError> {
+        let file = File::create(&path).map_err(IsitError::io(Path::new(&path)))?;
+        let mut file = EncodedFile::new(file, options.encoding);
+        if let Some(stamp) = stamp {
+            write!(file, "{}{}", stamp, options.line_ending.as_str())
+                .map_err(IsitError::io(Path::new(&path)))?;
+        }
+        let writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .quote_style(options.quoting.style())
+            .terminator(options.line_ending.terminator())
+            .from_writer(file);
+        Ok(CsvSink {
+            path,
+            writer: Some(writer),
+            name_suffix_column: options.name_suffix_column,
+        })
+    }
+
+    pub fn serialize<T: Serialize>(&mut self, record: T) -> Result<(), IsitError> {
+        match &mut self.writer {
+            Some(writer) => writer
+                .serialize(record)
+                .map_err(IsitError::csv(Path::new(&self.path))),
+            None => Err(IsitError::Usage(format!(
+                "{} ist bereits abgeschlossen.",
+                self.path
+            ))),
+        }
+    }
+
+    pub fn write_record<I, T>(&mut self, fields: I) -> Result<(), IsitError>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        match &mut self.writer {
+            Some(writer) => writer
+                .write_record(fields)
+                .map_err(IsitError::csv(Path::new(&self.path))),
+            None => Err(IsitError::Usage(format!(
+                "{} ist bereits abgeschlossen.",
+                self.path
+            ))),
+        }
+    }
+}
+
+impl OutputSink for CsvSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        if !self.name_suffix_column {
+            return self.serialize(record);
+        }
+        self.serialize(RecordIservWithSuffix {
+            nachname: &record.nachname,
+            namenszusatz: record.namenszusatz.as_deref().unwrap_or_default(),
+            vorname: &record.vorname,
+            klasse: &record.klasse,
+            import_id: &record.import_id,
+            password: &record.password,
+        })
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        if let Some(writer) = self.writer.take() {
+            let file = writer
+                .into_inner()
+                .map_err(|e| IsitError::io(Path::new(&self.path))(e.into_error()))?;
+            if !file.unmappable.is_empty() {
+                let chars: String = file.unmappable.iter().collect();
+                warn!(
+                    "\"{}\" gibt es in Windows-1252 nicht, in der Ausgabe steht dafür \"?\".",
+                    chars
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// IServ-Datei mit den Spalten aus --column; die Kopfzeile schreibt schon `open_sink`.
+pub struct ColumnsSink {
+    pub csv: CsvSink,
+    pub columns: Vec<OutputColumn>,
+    pub username: UsernameOptions,
+    pub usernames: UniqueUsernames,
+}
+
+impl OutputSink for ColumnsSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        let row = column_row(&self.columns, record, &self.username, &mut self.usernames);
+        self.csv.write_record(row)
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        self.csv.finish()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordCredentials<'a> {
+    pub klasse: &'a str,
+    pub nachname: &'a str,
+    pub vorname: &'a str,
+    pub passwort: &'a str,
+}
+
+pub struct CredentialsSink(pub CsvSink);
+
+impl OutputSink for CredentialsSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        self.0.serialize(RecordCredentials {
+            klasse: &record.klasse,
+            nachname: &record.nachname,
+            vorname: &record.vorname,
+            passwort: &record.password,
+        })
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        self.0.finish()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RecordClassroom {
+    pub klasse: String,
+    pub name: String,
+    pub username: String,
+    pub passwort: String,
+}
+
+/// Sammelt alle Datensätze, weil die Liste nach Klassen sortiert geschrieben wird.
+pub struct ClassroomSink {
+    pub csv: CsvSink,
+    pub rows: Vec<RecordClassroom>,
+    pub username: UsernameOptions,
+    pub usernames: UniqueUsernames,
+}
+
+impl OutputSink for ClassroomSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        let username = iserv_username(&record.vorname, &record.nachname, &self.username);
+        self.rows.push(RecordClassroom {
+            klasse: record.klasse.clone(),
+            name: format!("{}, {}", record.nachname, record.vorname),
+            username: self.usernames.claim(username, self.username.max_length),
+            passwort: record.password.clone(),
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        let mut rows = std::mem::take(&mut self.rows);
+        rows.sort_by(|a, b| (&a.klasse, &a.name).cmp(&(&b.klasse, &b.name)));
+        for row in rows {
+            self.csv.serialize(row)?;
+        }
+        self.csv.finish()
+    }
+}
+
+/// Vergebene Benutzernamen einer Ausgabe; ein doppelter Name bekommt eine laufende Nummer.
+/// Bei einer Höchstlänge wird für die Nummer weiter gekürzt, sodass auch gekürzte Namen, die
+/// erst dadurch gleich werden, eindeutig bleiben.
+#[derive(Debug, Default)]
+pub struct UniqueUsernames(pub HashSet<String>);
+
+impl UniqueUsernames {
+    pub fn claim(&mut self, username: String, max_length: Option<usize>) -> String {
+        let mut unique = username.clone();
+        let mut suffix = 2;
+        while !self.0.insert(unique.clone()) {
+            let number = suffix.to_string();
+            let shorter = max_length.map(|max_length| max_length.saturating_sub(number.len()));
+            unique = format!("{}{}", shorten_username(&username, shorter), number);
+            suffix += 1;
+        }
+        if unique != username {
+            warn!(
+                "Benutzername {} ist doppelt, stattdessen wird {} verwendet.",
+                username, unique
+            );
+        }
+        unique
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecordMoodle<'a> {
+    pub username: String,
+    pub firstname: &'a str,
+    pub lastname: &'a str,
+    pub password: &'a str,
+    pub cohort1: &'a str,
+}
+
+/// Schreibt die Konten für den Nutzerupload von Moodle, mit denselben Benutzernamen wie in
+/// IServ und der Klasse als globale Gruppe (cohort1).
+pub struct MoodleSink {
+    pub csv: CsvSink,
+    pub username: UsernameOptions,
+    pub usernames: UniqueUsernames,
+}
+
+impl OutputSink for MoodleSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        let username = iserv_username(&record.vorname, &record.nachname, &self.username);
+        self.csv.serialize(RecordMoodle {
+            username: self.usernames.claim(username, self.username.max_length),
+            firstname: &record.vorname,
+            lastname: &record.nachname,
+            password: &record.password,
+            cohort1: &record.klasse,
+        })
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        self.csv.finish()
+    }
+}
+
+/// Sammelt die Briefe, das PDF entsteht erst beim Abschluss, weil der Seitenbaum alle Seiten
+/// kennen muss. Die Briefe sind nach Klasse und Name sortiert, damit sie sich klassenweise
+/// austeilen lassen.
+pub struct LettersSink {
+    pub path: String,
+    pub letters: Vec<Letter>,
+    pub template: LetterTemplate,
+    pub export_date: String,
+    pub username: UsernameOptions,
+    pub usernames: UniqueUsernames,
+}
+
+impl OutputSink for LettersSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        let username = iserv_username(&record.vorname, &record.nachname, &self.username);
+        self.letters.push(Letter {
+            vorname: record.vorname.clone(),
+            nachname: record.nachname.clone(),
+            klasse: record.klasse.clone(),
+            benutzername: self.usernames.claim(username, self.username.max_length),
+            passwort: record.password.clone(),
+            export_date: self.export_date.clone(),
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        let mut letters = std::mem::take(&mut self.letters);
+        letters.sort_by(|a, b| {
+            (&a.klasse, &a.nachname, &a.vorname).cmp(&(&b.klasse, &b.nachname, &b.vorname))
+        });
+        fs::write(&self.path, self.template.render(&letters)?)
+            .map_err(IsitError::io(Path::new(&self.path)))
+    }
+}
+
+/// Schreibt die IServ-Spalten in eine Excel-Arbeitsmappe mit einem Tabellenblatt. Alle Zellen
+/// sind Text, damit Excel weder führende Nullen der Import-ID noch Klassen wie "5-1" umdeutet.
+pub struct XlsxSink {
+    pub path: String,
+    pub rows: Vec<Vec<String>>,
+    pub name_suffix_column: bool,
+    pub columns: Vec<OutputColumn>,
+    pub username: UsernameOptions,
+    pub usernames: UniqueUsernames,
+}
+
+const XLSX_CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/></Types>"#;
+
+const XLSX_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const XLSX_WORKBOOK: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="IServ" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+
+const XLSX_WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#;
+
+// Stil 1 ist fett und als Text formatiert (für die Kopfzeile), Stil 2 nur als Text.
+const XLSX_STYLES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts><fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills><borders count="1"><border/></borders><cellStyleXfs count="1"><xf/></cellStyleXfs><cellXfs count="3"><xf/><xf numFmtId="49" fontId="1" applyFont="1" applyNumberFormat="1"/><xf numFmtId="49" applyNumberFormat="1"/></cellXfs></styleSheet>"#;
+
+/// Ersetzt die Sonderzeichen von XML und lässt Steuerzeichen weg, die XML nicht erlaubt.
+pub fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Spaltenbuchstaben wie in Excel, 0 → A, 26 → AA.
+fn column_name(mut column: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (column % 26) as u8);
+        if column < 26 {
+            break;
+        }
+        column = column / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
+
+impl XlsxSink {
+    fn header(&self) -> Vec<String> {
+        if !self.columns.is_empty() {
+            return self
+                .columns
+                .iter()
+                .map(|column| column.header.clone())
+                .collect();
+        }
+        let mut header = vec!["Nachname", "Vorname", "Klasse", "Import-ID", "Password"];
+        if self.name_suffix_column {
+            header.insert(1, "Namenszusatz");
+        }
+        header.into_iter().map(String::from).collect()
+    }
+
+    fn sheet_xml(&self) -> String {
+        let header = self.header();
+        let rows = std::iter::once(&header).chain(&self.rows);
+        let widths: Vec<usize> = (0..header.len())
+            .map(|column| {
+                std::iter::once(&header)
+                    .chain(&self.rows)
+                    .map(|row| row.get(column).map_or(0, |cell| cell.chars().count()))
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let mut xml = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetViews><sheetView workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews><cols>"#,
+        );
+        for (column, width) in widths.iter().enumerate() {
+            xml.push_str(&format!(
+                r#"<col min="{0}" max="{0}" width="{1}" customWidth="1"/>"#,
+                column + 1,
+                width + 2
+            ));
+        }
+        xml.push_str("</cols><sheetData>");
+        for (index, row) in rows.enumerate() {
+            let style = if index == 0 { 1 } else { 2 };
+            xml.push_str(&format!(r#"<row r="{}">"#, index + 1));
+            for (column, cell) in row.iter().enumerate() {
+                xml.push_str(&format!(
+                    r#"<c r="{}{}" s="{}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                    column_name(column),
+                    index + 1,
+                    style,
+                    xml_escape(cell)
+                ));
+            }
+            xml.push_str("</row>");
+        }
+        xml.push_str(&format!(
+            r#"</sheetData><autoFilter ref="A1:{}{}"/></worksheet>"#,
+            column_name(header.len() - 1),
+            self.rows.len() + 1
+        ));
+        xml
+    }
+}
+
+impl OutputSink for XlsxSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        if !self.columns.is_empty() {
+            let row = column_row(&self.columns, record, &self.username, &mut self.usernames);
+            self.rows.push(row);
+            return Ok(());
+        }
+        let mut row = vec![
+            record.nachname.clone(),
+            record.vorname.clone(),
+            record.klasse.clone(),
+            record.import_id.clone(),
+            record.password.clone(),
+        ];
+        if self.name_suffix_column {
+            row.insert(1, record.namenszusatz.clone().unwrap_or_default());
+        }
+        self.rows.push(row);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        let path = Path::new(&self.path);
+        let file = File::create(path).map_err(IsitError::io(path))?;
+        let mut zip = zip::ZipWriter::new(file);
+        let options = zip::write::FileOptions::default();
+        let sheet = self.sheet_xml();
+        let parts = [
+            ("[Content_Types].xml", XLSX_CONTENT_TYPES),
+            ("_rels/.rels", XLSX_RELS),
+            ("xl/workbook.xml", XLSX_WORKBOOK),
+            ("xl/_rels/workbook.xml.rels", XLSX_WORKBOOK_RELS),
+            ("xl/styles.xml", XLSX_STYLES),
+            ("xl/worksheets/sheet1.xml", sheet.as_str()),
+        ];
+        for (name, content) in parts {
+            zip.start_file(name, options)
+                .map_err(IsitError::archive(path))?;
+            zip.write_all(content.as_bytes())
+                .map_err(IsitError::io(path))?;
+        }
+        zip.finish().map_err(IsitError::archive(path))?;
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+/// Schreibt ein JSON-Array mit den Feldern nachname, vorname, klasse, import_id und password,
+/// bei --name-suffix-policy separate-column auch namenszusatz.
+pub struct JsonSink {
+    pub path: String,
+    pub writer: io::BufWriter<File>,
+    pub count: usize,
+    pub name_suffix_column: bool,
+}
+
+/// Text als JSON-String samt Anführungszeichen.
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl JsonSink {
+    pub fn create(path: String, options: &OutputOptions) -> Result<Self, IsitError> {
+        let file = File::create(&path).map_err(IsitError::io(Path::new(&path)))?;
+        let mut writer = io::BufWriter::new(file);
+        writer
+            .write_all(b"[")
+            .map_err(IsitError::io(Path::new(&path)))?;
+        Ok(JsonSink {
+            path,
+            writer,
+            count: 0,
+            name_suffix_column: options.name_suffix_column,
+        })
+    }
+}
+
+impl OutputSink for JsonSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        let mut fields = vec![
+            ("nachname", record.nachname.as_str()),
+            ("vorname", &record.vorname),
+            ("klasse", &record.klasse),
+            ("import_id", &record.import_id),
+            ("password", &record.password),
+        ];
+        if self.name_suffix_column {
+            let namenszusatz = record.namenszusatz.as_deref().unwrap_or_default();
+            fields.insert(1, ("namenszusatz", namenszusatz));
+        }
+        let object: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("{}: {}", json_string(key), json_string(value)))
+            .collect();
+        let separator = if self.count == 0 { "" } else { "," };
+        self.count += 1;
+        write!(self.writer, "{}\n  {{{}}}", separator, object.join(", "))
+            .map_err(IsitError::io(Path::new(&self.path)))
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        let end = if self.count == 0 { "]\n" } else { "\n]\n" };
+        self.writer
+            .write_all(end.as_bytes())
+            .and_then(|_| self.writer.flush())
+            .map_err(IsitError::io(Path::new(&self.path)))
+    }
+}
+
+/// Schreibt für jedes Konto einen LDIF-Eintrag zum Anlegen (changetype: add). Der RDN ist
+/// uid=<Benutzername>.
+pub struct LdifSink {
+    pub path: String,
+    pub writer: io::BufWriter<File>,
+    pub base_dn: String,
+    pub object_classes: Vec<String>,
+    pub username: UsernameOptions,
+    pub uids: UniqueUsernames,
+    pub line_ending: LineEnding,
+}
+
+/// Base64 nach RFC 4648 mit Auffüllung.
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Eine LDIF-Zeile `name: wert`; Werte, die RFC 2849 nicht als sicheren Text erlaubt (z.B.
+/// Umlaute oder führende Leerzeichen), werden als `name:: base64` geschrieben.
+pub fn ldif_line(name: &str, value: &str) -> String {
+    let safe = value
+        .bytes()
+        .all(|b| b.is_ascii() && b != 0 && b != b'\n' && b != b'\r')
+        && !value.starts_with([' ', ':', '<'])
+        && !value.ends_with(' ');
+    if safe {
+        format!("{}: {}", name, value)
+    } else {
+        format!("{}:: {}", name, base64(value.as_bytes()))
+    }
+}
+
+/// Maskiert die Zeichen, die in einem DN-Attributwert besondere Bedeutung haben (RFC 4514).
+pub fn dn_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (index, c) in value.chars().enumerate() {
+        match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' | ' ' if index == 0 => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl LdifSink {
+    pub fn create(path: String, options: &OutputOptions) -> Result<Self, IsitError> {
+        let base_dn = options.ldap.base_dn.clone().ok_or_else(|| {
+            IsitError::Usage("Für die LDIF-Ausgabe bitte --ldap-base-dn angeben.".to_owned())
+        })?;
+        let file = File::create(&path).map_err(IsitError::io(Path::new(&path)))?;
+        let mut writer = io::BufWriter::new(file);
+        let newline = options.line_ending.as_str();
+        let mut header = format!("version: 1{}", newline);
+        if let Some(stamp) = &options.stamp {
+            header = format!("{}{}{}", stamp, newline, header);
+        }
+        writer
+            .write_all(header.as_bytes())
+            .map_err(IsitError::io(Path::new(&path)))?;
+        Ok(LdifSink {
+            path,
+            writer,
+            base_dn,
+            object_classes: options.ldap.object_classes.clone(),
+            username: options.username.clone(),
+            uids: UniqueUsernames::default(),
+            line_ending: options.line_ending,
+        })
+    }
+}
+
+impl OutputSink for LdifSink {
+    fn write(&mut self, record: &RecordIserv) -> Result<(), IsitError> {
+        let username = iserv_username(&record.vorname, &record.nachname, &self.username);
+        let uid = self.uids.claim(username, self.username.max_length);
+        let mut lines = vec![
+            ldif_line("dn", &format!("uid={},{}", dn_escape(&uid), self.base_dn)),
+            "changetype: add".to_owned(),
+        ];
+        for object_class in &self.object_classes {
+            lines.push(ldif_line("objectClass", object_class));
+        }
+        lines.push(ldif_line("uid", &uid));
+        lines.push(ldif_line(
+            "cn",
+            &format!("{} {}", record.vorname, record.nachname),
+        ));
+        lines.push(ldif_line("sn", &record.nachname));
+        lines.push(ldif_line("givenName", &record.vorname));
+        if !record.klasse.is_empty() {
+            lines.push(ldif_line("ou", &record.klasse));
+        }
+        lines.push(ldif_line("employeeNumber", &record.import_id));
+        if let Some(email) = &record.email {
+            lines.push(ldif_line("mail", email));
+        }
+        if !record.password.is_empty() {
+            lines.push(ldif_line("userPassword", &record.password));
+        }
+        let newline = self.line_ending.as_str();
+        write!(self.writer, "{}{}{}", newline, lines.join(newline), newline)
+            .map_err(IsitError::io(Path::new(&self.path)))
+    }
+
+    fn finish(&mut self) -> Result<(), IsitError> {
+        self.writer
+            .flush()
+            .map_err(IsitError::io(Path::new(&self.path)))
+    }
+}
+
+/// Schreibt alle Datensätze in jede der Ausgaben.
+pub fn write_iserv_records(
+    records: &[RecordIserv],
+    outputs: &[OutputSpec],
+    options: &OutputOptions,
+) -> Result<(), IsitError> {
+    let mut sinks = outputs
+        .iter()
+        .map(|output| open_sink(output.format, output.path.clone(), options))
+        .collect::<Result<Vec<_>, _>>()?;
+    for record in records {
+        for sink in sinks.iter_mut() {
+            sink.write(record)?;
+        }
+    }
+    for sink in sinks.iter_mut() {
+        sink.finish()?;
+    }
+    Ok(())
+}
+
+pub fn write_records_to_file<T: Serialize>(
+    records: &Vec<T>,
+    path: String,
+    options: &OutputOptions,
+) -> Result<(), IsitError> {
+    let mut sink = CsvSink::create(path, options)?;
+    for record in records {
+        sink.serialize(record)?;
+    }
+    sink.finish()
+}
+
+/// Liest eine bestehende IServ-Datei und ergänzt die Datensätze, deren Import-ID dort noch
+/// fehlt. Das Ergebnis ist nach Klasse und Name sortiert.
+pub fn append_to_existing(
+    path: &str,
+    records: &[RecordIserv],
+) -> Result<Vec<RecordIserv>, IsitError> {
+    let mut combined: Vec<RecordIserv> = Vec::new();
+    if Path::new(path).exists() {
+        // Die Datei kann mit anderer --output-encoding oder --output-delimiter geschrieben
+        // worden sein.
+        let text = read_decoded(PathBuf::from(path), &ReadOptions::default())?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(detect_delimiter(&text))
+            .comment(Some(b'#'))
+            .from_reader(text.as_bytes());
+        for record in rdr.deserialize() {
+            combined.push(record.map_err(IsitError::csv(Path::new(path)))?);
+        }
+    } else {
+        info!("{} existiert noch nicht und wird neu angelegt.", path);
+    }
+    let mut known: HashSet<String> = combined.iter().map(|r| r.import_id.clone()).collect();
+    let mut duplicates = 0;
+    for record in records {
+        if known.insert(record.import_id.clone()) {
+            combined.push(record.clone());
+        } else {
+            duplicates += 1;
+        }
+    }
+    if duplicates > 0 {
+        warn!(
+            "{} Datensätze sind in {} schon enthalten und werden nicht angehängt.",
+            duplicates, path
+        );
+    }
+    combined.sort_by(|a, b| {
+        (&a.klasse, &a.nachname, &a.vorname).cmp(&(&b.klasse, &b.nachname, &b.vorname))
+    });
+    Ok(combined)
+}
+
+pub fn group_by_class(records: &[RecordIserv]) -> BTreeMap<&str, Vec<RecordIserv>> {
+    let mut classes: BTreeMap<&str, Vec<RecordIserv>> = BTreeMap::new();
+    for record in records {
+        classes
+            .entry(record.klasse.as_str())
+            .or_default()
+            .push(record.clone());
+    }
+    classes
+}
+
+pub fn group_by_role(records: &[RecordIserv]) -> BTreeMap<Rolle, Vec<RecordIserv>> {
+    let mut roles: BTreeMap<Rolle, Vec<RecordIserv>> = BTreeMap::new();
+    for record in records {
+        roles.entry(record.rolle).or_default().push(record.clone());
+    }
+    roles
+}
+
+/// Pfad der Datei für eine Rolle neben `output_path`, mit der Rolle vor der Endung, z.B.
+/// "import_lehrer.csv" zu "import.csv".
+pub fn role_file_path(output_path: &str, rolle: Rolle) -> String {
+    let path = Path::new(output_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, rolle.name(), extension.to_string_lossy()),
+        None => format!("{}_{}", stem, rolle.name()),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Setzt die Klasse in die Dateinamenvorlage ein. Zeichen, die in Dateinamen nicht erlaubt
+/// sind (z.B. der Schrägstrich in "7/b"), werden durch "_" ersetzt.
+pub fn class_file_path(dir: &str, template: &str, klasse: &str) -> Result<String, IsitError> {
+    if !template.contains("{klasse}") {
+        return Err(IsitError::Usage(format!(
+            "--class-file-template \"{}\" enthält keinen Platzhalter {{klasse}}.",
+            template
+        )));
+    }
+    let klasse: String = klasse
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    let klasse = if klasse.is_empty() {
+        "ohne_klasse".to_owned()
+    } else {
+        klasse
+    };
+    let name = template.replace("{klasse}", &klasse);
+    Ok(Path::new(dir).join(name).to_string_lossy().into_owned())
+}
+
+/// Zwischenverzeichnis neben der Ausgabe, in dem alle Dateien eines Laufs entstehen.
+/// Wird der Lauf nicht mit [`Staging::commit`] abgeschlossen, wird alles wieder entfernt.
+pub struct Staging {
+    pub dir: PathBuf,
+    /// Zwischendatei und ihr endgültiger Pfad.
+    pub files: Vec<(PathBuf, PathBuf)>,
+    pub secure_delete: bool,
+}
+
+impl Staging {
+    pub fn new(output_path: &str, secure_delete: bool) -> io::Result<Self> {
+        let parent = Path::new(output_path)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let dir = parent.join(format!(".isit-staging-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        Ok(Staging {
+            dir,
+            files: Vec::new(),
+            secure_delete,
+        })
+    }
+
+    /// Liefert den Pfad, unter dem die Datei für `target` geschrieben werden soll.
+    pub fn stage(&mut self, target: &str) -> String {
+        let name = Path::new(target)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let staged = self.dir.join(format!("{}-{}", self.files.len(), name));
+        self.files.push((staged.clone(), PathBuf::from(target)));
+        staged.to_string_lossy().into_owned()
+    }
+
+    pub fn commit(mut self) -> Result<(), IsitError> {
+        // Vorher prüfen, damit nicht nur ein Teil der Dateien verschoben wird.
+        for (_, target) in self.files.iter().filter(|(_, target)| !is_stdio(target)) {
+            let parent = target.parent().filter(|p| !p.as_os_str().is_empty());
+            if parent.map(|p| !p.is_dir()).unwrap_or(false) {
+                return Err(IsitError::Io {
+                    path: target.clone(),
+                    source: io::Error::new(io::ErrorKind::NotFound, "Verzeichnis existiert nicht"),
+                });
+            }
+        }
+        while let Some((staged, target)) = self.files.last().cloned() {
+            if is_stdio(&target) {
+                let mut file = File::open(&staged).map_err(IsitError::io(&staged))?;
+                io::copy(&mut file, &mut io::stdout().lock()).map_err(IsitError::io(&target))?;
+                delete_file(&staged, self.secure_delete)?;
+            } else if fs::rename(&staged, &target).is_err() {
+                // Andere Partition: kopieren und die Zwischendatei entfernen.
+                fs::copy(&staged, &target).map_err(IsitError::io(&target))?;
+                delete_file(&staged, self.secure_delete)?;
+            }
+            self.files.pop();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        for (staged, _) in &self.files {
+            if staged.exists() {
+                if let Err(e) = delete_file(staged, self.secure_delete) {
+                    error!("Konnte {} nicht löschen: {}", staged.display(), e);
+                }
+            }
+        }
+        if let Err(e) = fs::remove_dir(&self.dir) {
+            error!("Konnte {} nicht löschen: {}", self.dir.display(), e);
+        }
+    }
+}
+
+pub fn write_manifest(
+    files: &[String],
+    ttl: Duration,
+    options_hash: &str,
+    settings: &str,
+    export_date: &str,
+    path: String,
+) -> Result<(), IsitError> {
+    let created = unix_now();
+    let manifest = Path::new(&path);
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_path(manifest)
+        .map_err(IsitError::csv(manifest))?;
+    for file in files {
+        let datei = path::absolute(file).map_err(IsitError::io(Path::new(file)))?;
+        wtr.serialize(ManifestEntry {
+            datei: datei.to_string_lossy().into_owned(),
+            erstellt: created,
+            ablauf: created + ttl.as_secs(),
+            version: VERSION.to_owned(),
+            optionen: options_hash.to_owned(),
+            einstellungen: settings.to_owned(),
+            exportdatum: export_date.to_owned(),
+        })
+        .map_err(IsitError::csv(manifest))?;
+    }
+    wtr.flush().map_err(IsitError::io(manifest))?;
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn cleanup_expired_files(dir: String) -> Result<(), IsitError> {
+    let now = unix_now();
+    let pattern = PathBuf::from(&dir).join(format!("*.{}", MANIFEST_EXTENSION));
+    let manifests = glob::glob(&pattern.to_string_lossy())
+        .map_err(|e| IsitError::Usage(format!("Ungültiges Verzeichnis {}: {}", dir, e)))?;
+    for manifest in manifests {
+        let manifest = manifest.map_err(|e| IsitError::Io {
+            path: e.path().to_path_buf(),
+            source: e.into_error(),
+        })?;
+        info!("Prüfe Manifest {}.", manifest.display());
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(b';')
+            .from_path(&manifest)
+            .map_err(IsitError::csv(&manifest))?;
+        let mut remaining = Vec::new();
+        for result in rdr.deserialize() {
+            let entry: ManifestEntry = result.map_err(IsitError::csv(&manifest))?;
+            if entry.ablauf > now {
+                remaining.push(entry);
+            } else if PathBuf::from(&entry.datei).exists() {
+                info!("Lösche abgelaufene Datei {}.", entry.datei);
+                delete_file(&entry.datei, true)?;
+            }
+        }
+        if remaining.is_empty() {
+            fs::remove_file(&manifest).map_err(IsitError::io(&manifest))?;
+        } else {
+            let mut wtr = csv::WriterBuilder::new()
+                .delimiter(b';')
+                .from_path(&manifest)
+                .map_err(IsitError::csv(&manifest))?;
+            for entry in remaining {
+                wtr.serialize(entry).map_err(IsitError::csv(&manifest))?;
+            }
+            wtr.flush().map_err(IsitError::io(&manifest))?;
+        }
+    }
+    Ok(())
+}
+
+/// Entfernt eine Datei. Mit `secure` wird sie vorher mit Nullen überschrieben,
+/// damit keine Passwörter auf der Platte zurückbleiben.
+pub fn delete_file<P: AsRef<Path>>(path: P, secure: bool) -> Result<(), IsitError> {
+    let path = path.as_ref();
+    if secure {
+        let overwrite = || -> io::Result<()> {
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            let len = file.metadata()?.len() as usize;
+            file.write_all(&vec![0; len])?;
+            file.sync_all()
+        };
+        overwrite().map_err(IsitError::io(path))?;
+    }
+    fs::remove_file(path).map_err(IsitError::io(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::parse_csv_records;
+
+    /// Pfad im temporären Verzeichnis, eindeutig je Test und Prozess.
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("isit-test-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn semicolon_in_name_survives_write_and_read() {
+        let records = vec![RecordIserv::new(
+            "Nguyen;Tran".to_owned(),
+            "Anh".to_owned(),
+            "7b".to_owned(),
+            "4711".to_owned(),
+        )];
+        let path = temp_path("semicolon.csv");
+        let output = OutputSpec {
+            format: OutputFormat::Csv,
+            path: path.clone(),
+        };
+        write_iserv_records(&records, &[output], &OutputOptions::default()).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(text.contains("\"Nguyen;Tran\""));
+        let read = parse_csv_records(
+            &text,
+            Path::new(&path),
+            Some(RecordType::Iserv),
+            &ReadOptions::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        let read: Vec<RecordIserv> = read.into_iter().map(RecordIserv::from).collect();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].nachname, "Nguyen;Tran");
+        assert_eq!(read[0].vorname, "Anh");
+        assert_eq!(read[0].import_id, "4711");
+    }
+
+    fn tom_meier(klasse: &str, import_id: &str, password: &str) -> RecordIserv {
+        let mut record = RecordIserv::new(
+            "Meier".to_owned(),
+            "Tom".to_owned(),
+            klasse.to_owned(),
+            import_id.to_owned(),
+        );
+        record.password = password.to_owned();
+        record
+    }
+
+    /// Schreibt `records` im Format `format` und gibt die Zeilen der Datei zurück.
+    fn write_lines(records: &[RecordIserv], format: OutputFormat, name: &str) -> Vec<String> {
+        let path = temp_path(name);
+        let output = OutputSpec {
+            format,
+            path: path.clone(),
+        };
+        write_iserv_records(records, &[output], &OutputOptions::default()).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        text.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn username_column_numbers_duplicates() {
+        let records = [tom_meier("5a", "1", "eins"), tom_meier("5a", "2", "zwei")];
+        let path = temp_path("columns.csv");
+        let output = OutputSpec {
+            format: OutputFormat::Csv,
+            path: path.clone(),
+        };
+        let options = OutputOptions {
+            columns: vec![
+                parse_output_column("import_id").unwrap(),
+                parse_output_column("Login={benutzername}").unwrap(),
+                parse_output_column("Mail={benutzername}@schule.de").unwrap(),
+            ],
+            ..OutputOptions::default()
+        };
+        write_iserv_records(&records, &[output], &options).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                "import_id;Login;Mail",
+                "1;tom.meier;tom.meier@schule.de",
+                "2;tom.meier2;tom.meier2@schule.de",
+            ]
+        );
+    }
+
+    #[test]
+    fn email_and_id_templates_follow_the_username_rules() {
+        let record = |vorname: &str, nachname: &str, import_id| RecordIserv {
+            vorname: vorname.to_owned(),
+            nachname: nachname.to_owned(),
+            ..tom_meier("5a", import_id, "geheim")
+        };
+        let records = [
+            record("Sean", "O'Brien", "1"),
+            record("Maria José", "De la Cruz", "2"),
+        ];
+        let write = |username: UsernameOptions| {
+            let path = temp_path("vorlagen.csv");
+            let output = OutputSpec {
+                format: OutputFormat::Csv,
+                path: path.clone(),
+            };
+            let options = OutputOptions {
+                columns: vec![
+                    parse_output_column("Nachname").unwrap(),
+                    parse_output_column("E-Mail={vorname}.{nachname}@schule.de").unwrap(),
+                    parse_output_column("import_id=S-{nachname}").unwrap(),
+                ],
+                username,
+                ..OutputOptions::default()
+            };
+            write_iserv_records(&records, &[output], &options).unwrap();
+            let text = fs::read_to_string(&path).unwrap();
+            let _ = fs::remove_file(&path);
+            text.lines().skip(1).map(str::to_owned).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            write(UsernameOptions::default()),
+            [
+                "O'Brien;Sean.OBrien@schule.de;S-OBrien",
+                "De la Cruz;Maria-José.De-la-Cruz@schule.de;S-De-la-Cruz",
+            ]
+        );
+        let hyphen_and_remove = UsernameOptions {
+            apostrophe: NameSeparator::Hyphen,
+            space: NameSeparator::Remove,
+            ..UsernameOptions::default()
+        };
+        assert_eq!(
+            write(hyphen_and_remove),
+            [
+                "O'Brien;Sean.O-Brien@schule.de;S-O-Brien",
+                "De la Cruz;MariaJosé.DelaCruz@schule.de;S-DelaCruz",
+            ]
+        );
+    }
+
+    #[test]
+    fn classroom_list_numbers_duplicate_usernames_in_input_order() {
+        // Der zweite Tom Meier steht in der Eingabe später, aber in der Liste vorn.
+        let records = [tom_meier("9b", "1", "eins"), tom_meier("5a", "2", "zwei")];
+        let lines = write_lines(&records, OutputFormat::Classroom, "classroom.csv");
+        assert_eq!(lines[1], "5a;Meier, Tom;tom.meier2;zwei");
+        assert_eq!(lines[2], "9b;Meier, Tom;tom.meier;eins");
+        let moodle = write_lines(&records, OutputFormat::Moodle, "moodle.csv");
+        assert!(moodle[1].starts_with("tom.meier,"));
+        assert!(moodle[2].starts_with("tom.meier2,"));
+    }
+
+    #[test]
+    fn shortened_usernames_are_numbered_within_the_limit() {
+        let record = |vorname: &str, import_id| RecordIserv {
+            vorname: vorname.to_owned(),
+            nachname: "Wagenknecht-Niederhausen".to_owned(),
+            ..tom_meier("5a", import_id, "geheim")
+        };
+        let records = [record("Annalena", "1"), record("Annabell", "2")];
+        let path = temp_path("kurz.csv");
+        let output = OutputSpec {
+            format: OutputFormat::Moodle,
+            path: path.clone(),
+        };
+        let options = OutputOptions {
+            username: UsernameOptions {
+                max_length: Some(20),
+                ..UsernameOptions::default()
+            },
+            ..OutputOptions::default()
+        };
+        write_iserv_records(&records, &[output], &options).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines[1].starts_with("a.wagenknecht-nieder,"));
+        assert!(lines[2].starts_with("a.wagenknecht-niede2,"));
+    }
+
+    /// Schreibt `records` als Excel-Datei und liest alle Zellen mit calamine zurück.
+    fn write_and_read_xlsx(
+        records: &[RecordIserv],
+        options: &OutputOptions,
+        name: &str,
+    ) -> Vec<Vec<String>> {
+        use calamine::{open_workbook, Reader, Xlsx};
+        let path = temp_path(name);
+        let output = OutputSpec {
+            format: OutputFormat::Xlsx,
+            path: path.clone(),
+        };
+        write_iserv_records(records, &[output], options).unwrap();
+        let workbook: Result<Xlsx<_>, _> = open_workbook(&path);
+        let _ = fs::remove_file(&path);
+        let mut workbook = workbook.unwrap();
+        assert_eq!(workbook.sheet_names(), ["IServ"]);
+        workbook
+            .worksheet_range_at(0)
+            .unwrap()
+            .unwrap()
+            .rows()
+            .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn teachers_and_students_are_split_by_role() {
+        let teacher = RecordIserv::from(RecordLehrer {
+            nachname: "Meier".to_owned(),
+            vorname: "Eva".to_owned(),
+            kuerzel: "MEI".to_owned(),
+            email: String::new(),
+            extra: Default::default(),
+            id: RecordId::default(),
+        });
+        let student = RecordIserv::new(
+            "Schulz".to_owned(),
+            "Jan".to_owned(),
+            "5a".to_owned(),
+            "4711".to_owned(),
+        );
+        let roles = group_by_role(&[student.clone(), teacher, student]);
+        assert_eq!(roles[&Rolle::Schueler].len(), 2);
+        assert_eq!(roles[&Rolle::Lehrer][0].import_id, "MEI");
+        assert_eq!(
+            role_file_path("out/import.csv", Rolle::Lehrer),
+            Path::new("out").join("import_lehrer.csv").to_string_lossy()
+        );
+        assert_eq!(role_file_path("import", Rolle::Schueler), "import_schueler");
+    }
+
+    #[test]
+    fn xlsx_cells_read_back_unchanged() {
+        let mut record = RecordIserv::new(
+            "Müller & <Söhne>".to_owned(),
+            " \"Anna\" ".to_owned(),
+            "5-1".to_owned(),
+            "0047".to_owned(),
+        );
+        record.password = "a&b<c>".to_owned();
+        let rows = write_and_read_xlsx(&[record], &OutputOptions::default(), "cells.xlsx");
+        assert_eq!(
+            rows,
+            [
+                ["Nachname", "Vorname", "Klasse", "Import-ID", "Password"],
+                ["Müller & <Söhne>", " \"Anna\" ", "5-1", "0047", "a&b<c>"],
+            ]
+        );
+    }
+
+    #[test]
+    fn xlsx_cell_references_go_past_column_z() {
+        let columns: Vec<OutputColumn> = (0..28)
+            .map(|column| OutputColumn {
+                header: format!("S{}", column),
+                value: if column == 27 {
+                    "{import_id}"
+                } else {
+                    "{klasse}"
+                }
+                .to_owned(),
+            })
+            .collect();
+        let options = OutputOptions {
+            columns,
+            ..OutputOptions::default()
+        };
+        let records = [
+            RecordIserv::new(
+                "A".to_owned(),
+                "B".to_owned(),
+                "5a".to_owned(),
+                "1".to_owned(),
+            ),
+            RecordIserv::new(
+                "C".to_owned(),
+                "D".to_owned(),
+                "6b".to_owned(),
+                "2".to_owned(),
+            ),
+        ];
+        let rows = write_and_read_xlsx(&records, &options, "columns.xlsx");
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|row| row.len() == 28));
+        assert_eq!((rows[0][26].as_str(), rows[0][27].as_str()), ("S26", "S27"));
+        assert_eq!((rows[2][0].as_str(), rows[2][27].as_str()), ("6b", "2"));
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(column_name(702), "AAA");
+    }
+}